@@ -1,5 +1,3 @@
-use std::u8;
-
 use opcode_decoder;
 
 pub struct Disassembler {
@@ -22,7 +20,7 @@ impl Disassembler {
                     ops.push(op);
                     pointer += optype.len;
                 }
-                Err(err) => panic!(err),
+                Err(err) => panic!("{}", err),
             }
         }
 
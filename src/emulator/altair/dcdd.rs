@@ -0,0 +1,469 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use emulator::cpu::*;
+
+pub const BASE_PORT: usize = 0x08;
+pub const MAX_DRIVES: usize = 16;
+
+/// 8" Altair disk geometry, as in the SIMH .dsk images
+pub const TRACKS: usize = 77;
+pub const SECTORS_PER_TRACK: usize = 32;
+pub const SECTOR_SIZE: usize = 137;
+pub const DISK_SIZE: usize = TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE;
+
+const EMPTY_BYTE: u8 = 0xe5;
+
+// Status bits, the controller reports them inverted: 0 means true
+const STATUS_ENWD: u8 = 0x01;
+const STATUS_MOVE_HEAD: u8 = 0x02;
+const STATUS_HEAD: u8 = 0x04;
+const STATUS_INTE: u8 = 0x20;
+const STATUS_TRACK_0: u8 = 0x40;
+const STATUS_NRDA: u8 = 0x80;
+
+const SELECT_DISABLE: u8 = 0x80;
+const SELECT_DRIVE_MASK: u8 = 0x0f;
+
+const CONTROL_STEP_IN: u8 = 0x01;
+const CONTROL_STEP_OUT: u8 = 0x02;
+const CONTROL_HEAD_LOAD: u8 = 0x04;
+const CONTROL_HEAD_UNLOAD: u8 = 0x08;
+const CONTROL_INT_ENABLE: u8 = 0x10;
+const CONTROL_INT_DISABLE: u8 = 0x20;
+const CONTROL_WRITE_ENABLE: u8 = 0x80;
+
+/// An Altair disk image, saved back to its file sector by sector when written
+pub struct AltairDisk {
+    data: Vec<u8>,
+    path: Option<PathBuf>,
+}
+
+impl AltairDisk {
+    pub fn open(path: &str) -> Result<AltairDisk, String> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("Could not read disk image {}: {}", path, e))?;
+
+        let mut disk = AltairDisk::from_raw(data)?;
+        disk.path = Some(Path::new(path).to_path_buf());
+        Ok(disk)
+    }
+
+    pub fn from_raw(mut data: Vec<u8>) -> Result<AltairDisk, String> {
+        if data.len() > DISK_SIZE {
+            return Err(format!(
+                "Altair disk image is {} bytes, expected at most {}",
+                data.len(),
+                DISK_SIZE
+            ));
+        }
+
+        data.resize(DISK_SIZE, EMPTY_BYTE);
+
+        Ok(AltairDisk { data, path: None })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn read_sector(&self, track: usize, sector: usize) -> &[u8] {
+        let offset = sector_offset(track, sector);
+        &self.data[offset..offset + SECTOR_SIZE]
+    }
+
+    fn write_sector(&mut self, track: usize, sector: usize, data: &[u8]) -> Result<(), String> {
+        let offset = sector_offset(track, sector);
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(data);
+
+        if let Some(ref path) = self.path {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|mut f| {
+                    f.seek(SeekFrom::Start(offset as u64))?;
+                    f.write_all(data)
+                })
+                .map_err(|e| format!("Could not write to {}: {}", path.display(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn sector_offset(track: usize, sector: usize) -> usize {
+    (track * SECTORS_PER_TRACK + sector) * SECTOR_SIZE
+}
+
+struct Drive {
+    disk: Option<AltairDisk>,
+    track: usize,
+    head_loaded: bool,
+}
+
+/// The MITS 88-DCDD floppy controller.
+///
+/// Port 0x08 selects a drive and reads the status, 0x09 sends head control
+/// commands and reads the sector position, 0x0a transfers the data bytes.
+/// The disk spins one sector every time the CPU reads the sector position.
+pub struct Dcdd {
+    drives: Vec<Drive>,
+    selected: Option<usize>,
+
+    interrupts_enabled: bool,
+    sector: usize,
+    byte: usize,
+    buffer: [u8; SECTOR_SIZE],
+    writing: bool,
+    dirty: bool,
+
+    error: Option<String>,
+}
+
+impl Dcdd {
+    pub fn new(drive_count: usize) -> Dcdd {
+        let mut drives = Vec::new();
+        for _i in 0..drive_count.min(MAX_DRIVES) {
+            drives.push(Drive {
+                disk: None,
+                track: 0,
+                head_loaded: false,
+            });
+        }
+
+        Dcdd {
+            drives,
+            selected: None,
+            interrupts_enabled: false,
+            sector: SECTORS_PER_TRACK - 1,
+            byte: 0,
+            buffer: [0; SECTOR_SIZE],
+            writing: false,
+            dirty: false,
+            error: None,
+        }
+    }
+
+    pub fn drive_count(&self) -> usize {
+        self.drives.len()
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, disk: AltairDisk) {
+        self.drives[drive].disk = Some(disk);
+    }
+
+    pub fn eject_disk(&mut self, drive: usize) -> Option<AltairDisk> {
+        self.flush();
+        self.drives[drive].disk.take()
+    }
+
+    /// The last failure to save a written sector, if any
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    pub fn update(&mut self, cpu: &mut CPU) {
+        if let (v, true) = cpu.get_out_port(BASE_PORT) {
+            self.select(v);
+        }
+
+        if let (v, true) = cpu.get_out_port(BASE_PORT + 1) {
+            self.control(v);
+        }
+
+        if cpu.was_in_port_read(BASE_PORT + 1) {
+            self.next_sector();
+        }
+
+        if let (v, true) = cpu.get_out_port(BASE_PORT + 2) {
+            self.write_byte(v);
+        }
+
+        if cpu.was_in_port_read(BASE_PORT + 2) && !self.writing {
+            self.byte = (self.byte + 1) % SECTOR_SIZE;
+        }
+
+        cpu.set_in_port(BASE_PORT, !self.status());
+        cpu.set_in_port(BASE_PORT + 1, self.sector_position());
+        cpu.set_in_port(BASE_PORT + 2, self.buffer[self.byte]);
+    }
+
+    fn select(&mut self, val: u8) {
+        self.flush();
+        self.writing = false;
+
+        let drive = (val & SELECT_DRIVE_MASK) as usize;
+        let available = drive < self.drives.len() && self.drives[drive].disk.is_some();
+
+        self.selected = if val & SELECT_DISABLE == 0 && available {
+            Some(drive)
+        } else {
+            None
+        };
+    }
+
+    fn control(&mut self, val: u8) {
+        let drive = match self.selected {
+            Some(d) => d,
+            None => return,
+        };
+
+        if val & (CONTROL_STEP_IN | CONTROL_STEP_OUT) > 0 {
+            self.flush();
+
+            let track = &mut self.drives[drive].track;
+            if val & CONTROL_STEP_IN > 0 && *track < TRACKS - 1 {
+                *track += 1;
+            }
+            if val & CONTROL_STEP_OUT > 0 && *track > 0 {
+                *track -= 1;
+            }
+
+            self.load_sector();
+        }
+
+        if val & CONTROL_HEAD_LOAD > 0 {
+            self.drives[drive].head_loaded = true;
+            self.load_sector();
+        }
+
+        if val & CONTROL_HEAD_UNLOAD > 0 {
+            self.flush();
+            self.drives[drive].head_loaded = false;
+        }
+
+        if val & CONTROL_INT_ENABLE > 0 {
+            self.interrupts_enabled = true;
+        }
+
+        if val & CONTROL_INT_DISABLE > 0 {
+            self.interrupts_enabled = false;
+        }
+
+        if val & CONTROL_WRITE_ENABLE > 0 {
+            self.writing = true;
+            self.byte = 0;
+        }
+    }
+
+    fn next_sector(&mut self) {
+        if !self.head_loaded() {
+            return;
+        }
+
+        self.flush();
+        self.writing = false;
+        self.sector = (self.sector + 1) % SECTORS_PER_TRACK;
+        self.load_sector();
+    }
+
+    fn write_byte(&mut self, val: u8) {
+        if !self.writing {
+            return;
+        }
+
+        self.buffer[self.byte] = val;
+        self.byte += 1;
+        self.dirty = true;
+
+        if self.byte == SECTOR_SIZE {
+            self.flush();
+            self.writing = false;
+            self.byte = 0;
+        }
+    }
+
+    fn load_sector(&mut self) {
+        self.byte = 0;
+
+        if let Some(drive) = self.selected {
+            let track = self.drives[drive].track;
+            if let Some(ref disk) = self.drives[drive].disk {
+                self.buffer
+                    .copy_from_slice(disk.read_sector(track, self.sector));
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        self.dirty = false;
+
+        if let Some(drive) = self.selected {
+            let track = self.drives[drive].track;
+            let sector = self.sector;
+            let buffer = self.buffer;
+
+            if let Some(ref mut disk) = self.drives[drive].disk {
+                if let Err(e) = disk.write_sector(track, sector, &buffer) {
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+
+    fn head_loaded(&self) -> bool {
+        match self.selected {
+            Some(d) => self.drives[d].head_loaded,
+            None => false,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let drive = match self.selected {
+            Some(d) => &self.drives[d],
+            None => return 0,
+        };
+
+        let mut status = STATUS_MOVE_HEAD;
+
+        if drive.track == 0 {
+            status |= STATUS_TRACK_0;
+        }
+
+        if drive.head_loaded {
+            status |= STATUS_HEAD | STATUS_NRDA;
+        }
+
+        if self.writing {
+            status |= STATUS_ENWD;
+        }
+
+        if self.interrupts_enabled {
+            status |= STATUS_INTE;
+        }
+
+        status
+    }
+
+    /// The sector the next read reports, its number in bits 1-5.
+    /// Bit 0 (sector true) stays low as the head is always at a sector start.
+    fn sector_position(&self) -> u8 {
+        if !self.head_loaded() {
+            return 0xff;
+        }
+
+        let next = (self.sector + 1) % SECTORS_PER_TRACK;
+        0xc0 | ((next as u8) << 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn init_cpu() -> CPU {
+        let mut opcode_data = String::new();
+        {
+            let mut opcode_file = File::open("./data/opcodes.txt").unwrap();
+            opcode_file.read_to_string(&mut opcode_data).unwrap();
+        }
+        CPU::new(OpcodeDecoder::new(&opcode_data))
+    }
+
+    fn test_disk() -> AltairDisk {
+        let mut data = vec![0; DISK_SIZE];
+        for track in 0..TRACKS {
+            for sector in 0..SECTORS_PER_TRACK {
+                let offset = sector_offset(track, sector);
+                data[offset] = track as u8;
+                data[offset + 1] = sector as u8;
+            }
+        }
+        AltairDisk::from_raw(data).unwrap()
+    }
+
+    fn out(dcdd: &mut Dcdd, cpu: &mut CPU, port: usize, val: u8) {
+        cpu.set_out_port(port, val);
+        dcdd.update(cpu);
+    }
+
+    fn read(dcdd: &mut Dcdd, cpu: &mut CPU, port: usize) -> u8 {
+        // Mimics IN by executing it, so the read gets noticed
+        cpu.set_memory(0, &[0xdb, port as u8]);
+        cpu.set_pc(0);
+        cpu.tick();
+        dcdd.update(cpu);
+        cpu.a()
+    }
+
+    #[test]
+    fn test_select() {
+        let mut cpu = init_cpu();
+        let mut dcdd = Dcdd::new(2);
+        dcdd.insert_disk(0, test_disk());
+        dcdd.update(&mut cpu);
+
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT), 0xff);
+
+        out(&mut dcdd, &mut cpu, BASE_PORT, 0x00);
+        let status = read(&mut dcdd, &mut cpu, BASE_PORT);
+        assert_eq!(status & STATUS_MOVE_HEAD, 0);
+        assert_eq!(status & STATUS_TRACK_0, 0);
+        assert_eq!(status & STATUS_HEAD, STATUS_HEAD);
+
+        out(&mut dcdd, &mut cpu, BASE_PORT, 0x01);
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT), 0xff);
+    }
+
+    #[test]
+    fn test_read_sector() {
+        let mut cpu = init_cpu();
+        let mut dcdd = Dcdd::new(1);
+        dcdd.insert_disk(0, test_disk());
+
+        out(&mut dcdd, &mut cpu, BASE_PORT, 0x00);
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_HEAD_LOAD);
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_STEP_IN);
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_STEP_IN);
+
+        let status = read(&mut dcdd, &mut cpu, BASE_PORT);
+        assert_eq!(status & STATUS_HEAD, 0);
+        assert_eq!(status & STATUS_TRACK_0, STATUS_TRACK_0);
+
+        read(&mut dcdd, &mut cpu, BASE_PORT + 1);
+        read(&mut dcdd, &mut cpu, BASE_PORT + 1);
+        let position = read(&mut dcdd, &mut cpu, BASE_PORT + 1);
+        assert_eq!(position & 0x01, 0);
+        assert_eq!((position >> 1) & 0x1f, 2);
+
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT + 2), 2);
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT + 2), 2);
+    }
+
+    #[test]
+    fn test_write_sector() {
+        let mut cpu = init_cpu();
+        let mut dcdd = Dcdd::new(1);
+        dcdd.insert_disk(0, test_disk());
+
+        out(&mut dcdd, &mut cpu, BASE_PORT, 0x00);
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_HEAD_LOAD);
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_STEP_IN);
+        read(&mut dcdd, &mut cpu, BASE_PORT + 1);
+
+        out(&mut dcdd, &mut cpu, BASE_PORT + 1, CONTROL_WRITE_ENABLE);
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT) & STATUS_ENWD, 0);
+
+        for i in 0..SECTOR_SIZE {
+            out(&mut dcdd, &mut cpu, BASE_PORT + 2, i as u8);
+        }
+
+        assert_eq!(read(&mut dcdd, &mut cpu, BASE_PORT) & STATUS_ENWD, STATUS_ENWD);
+
+        let disk = dcdd.eject_disk(0).unwrap();
+        let offset = sector_offset(1, 0);
+        assert_eq!(disk.data()[offset], 0);
+        assert_eq!(disk.data()[offset + 136], 136);
+        assert_eq!(disk.data()[sector_offset(1, 1)], 1);
+        assert!(dcdd.take_error().is_none());
+    }
+}
@@ -0,0 +1,452 @@
+pub mod dcdd;
+mod panel;
+mod sio;
+
+pub use self::dcdd::{AltairDisk, Dcdd};
+pub use self::panel::run_script;
+pub use self::sio::Sio2;
+
+use emulator::cpu::*;
+use emulator::devices::Cassette;
+use opcode_decoder::*;
+
+pub const CPU_HZ: u64 = 2_000_000;
+pub const SIO_PORT: usize = 0x10;
+pub const SENSE_SWITCHES_PORT: usize = 0xff;
+
+const IN_OPCODE: u8 = 0xdb;
+
+// Where the 88-ACR cassette interface usually sits
+pub const ACR_STATUS_PORT: usize = 0x06;
+pub const ACR_DATA_PORT: usize = 0x07;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PanelLeds {
+    pub address: u16,
+    pub data: u8,
+    pub running: bool,
+    pub halted: bool,
+}
+
+/// A MITS Altair 8800 with RAM from address 0, an 88-2SIO console and the front panel.
+///
+/// Front panel switches behave like the real ones: examine and deposit only
+/// work while the machine is stopped.
+pub struct Altair8800 {
+    cpu: CPU,
+    sio: Sio2,
+    dcdd: Option<Dcdd>,
+    cassette: Option<Cassette>,
+    devices: Vec<Box<dyn PortDevice>>,
+
+    running: bool,
+    cycles: u64,
+    next_event: u64,
+    sense_switches: u8,
+}
+
+impl Altair8800 {
+    pub fn new(decoder: OpcodeDecoder, ram_size: u16) -> Result<Altair8800, String> {
+        if ram_size == 0 || ram_size > 64 {
+            return Err(format!(
+                "Altair RAM size must be between 1 and 64K, got {}K",
+                ram_size
+            ));
+        }
+
+        let mut cpu = CPU::new(decoder);
+
+        let ram_end = ram_size as usize * 1024;
+        cpu.map_memory(ram_end as u16, 0x10000 - ram_end, MemoryKind::Empty);
+
+        let mut machine = Altair8800 {
+            cpu,
+            sio: Sio2::new(SIO_PORT),
+            dcdd: None,
+            cassette: None,
+            devices: Vec::new(),
+            running: false,
+            cycles: 0,
+            next_event: 0,
+            sense_switches: 0,
+        };
+
+        machine.set_sense_switches(0);
+        machine.sio.update(&mut machine.cpu);
+
+        Ok(machine)
+    }
+
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        self.cpu.set_memory(addr, data);
+    }
+
+    pub fn load_rom(&mut self, addr: u16, data: &[u8]) {
+        self.cpu.map_memory(addr, data.len(), MemoryKind::Rom);
+        self.cpu.set_memory(addr, data);
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    /// Plugs an 88-DCDD floppy controller into the bus
+    pub fn attach_dcdd(&mut self, mut dcdd: Dcdd) {
+        dcdd.update(&mut self.cpu);
+        self.dcdd = Some(dcdd);
+        self.next_event = self.cycles;
+    }
+
+    pub fn dcdd(&mut self) -> Option<&mut Dcdd> {
+        self.dcdd.as_mut()
+    }
+
+    /// Plugs a cassette interface into the bus
+    pub fn attach_cassette(&mut self, mut cassette: Cassette) {
+        cassette.update(&mut self.cpu, self.cycles);
+        self.cassette = Some(cassette);
+        self.next_event = self.cycles;
+    }
+
+    pub fn cassette(&mut self) -> Option<&mut Cassette> {
+        self.cassette.as_mut()
+    }
+
+    /// Plugs any other port device into the bus
+    pub fn attach_device(&mut self, mut device: Box<dyn PortDevice>) {
+        device.update(&mut self.cpu, self.cycles);
+        self.devices.push(device);
+        self.next_event = self.cycles;
+    }
+
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.sio.push_input(data);
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.sio.take_output()
+    }
+
+    /// The upper eight address switches, read by the CPU on port 0xff
+    pub fn set_sense_switches(&mut self, val: u8) {
+        self.sense_switches = val;
+        self.cpu.set_in_port(SENSE_SWITCHES_PORT, val);
+    }
+
+    pub fn sense_switches(&self) -> u8 {
+        self.sense_switches
+    }
+
+    pub fn leds(&self) -> PanelLeds {
+        let address = self.cpu.pc();
+
+        PanelLeds {
+            address,
+            data: self.cpu.get_memory(address),
+            running: self.running,
+            halted: self.cpu.is_halted(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn start(&mut self) {
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Works like the panel switch, also while running and out of a HLT
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn examine(&mut self, addr: u16) {
+        if !self.running {
+            self.cpu.set_pc(addr);
+        }
+    }
+
+    pub fn examine_next(&mut self) {
+        let next = self.cpu.pc().wrapping_add(1);
+        self.examine(next);
+    }
+
+    pub fn deposit(&mut self, val: u8) {
+        if !self.running {
+            let addr = self.cpu.pc();
+            self.cpu.write_memory(addr, val);
+        }
+    }
+
+    pub fn deposit_next(&mut self, val: u8) {
+        if !self.running {
+            self.examine_next();
+            self.deposit(val);
+        }
+    }
+
+    pub fn single_step(&mut self) {
+        if !self.running {
+            self.step();
+        }
+    }
+
+    /// Runs for about `cycles` cycles if the machine is running, returning the cycles spent
+    pub fn run(&mut self, cycles: u64) -> u64 {
+        let mut spent = 0;
+
+        while self.running && spent < cycles {
+            spent += self.step();
+        }
+
+        spent
+    }
+
+    fn step(&mut self) -> u64 {
+        // Whatever an IN reads has to be up to date
+        if !self.cpu.is_halted() && self.cpu.get_memory(self.cpu.pc()) == IN_OPCODE {
+            self.update_devices();
+        }
+
+        let cycles = self.cpu.tick();
+        self.cycles += cycles as u64;
+
+        if self.cpu.take_port_access().is_some() || self.cycles >= self.next_event {
+            self.update_devices();
+        }
+
+        cycles as u64
+    }
+
+    fn update_devices(&mut self) {
+        self.sio.update(&mut self.cpu);
+
+        if let Some(ref mut dcdd) = self.dcdd {
+            dcdd.update(&mut self.cpu);
+        }
+
+        self.next_event = u64::MAX;
+
+        if let Some(ref mut cassette) = self.cassette {
+            cassette.update(&mut self.cpu, self.cycles);
+            self.next_event = self.next_event.min(cassette.next_event().unwrap_or(u64::MAX));
+        }
+
+        for device in &mut self.devices {
+            device.update(&mut self.cpu, self.cycles);
+            self.next_event = self.next_event.min(device.next_event().unwrap_or(u64::MAX));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::devices::KANSAS_CITY;
+
+    #[rustfmt::skip]
+    const ECHO: [u8; 13] = [
+        0xdb, 0x10,         // IN 0x10
+        0x0f,               // RRC
+        0xd2, 0x00, 0x00,   // JNC 0
+        0xdb, 0x11,         // IN 0x11
+        0xd3, 0x11,         // OUT 0x11
+        0xc3, 0x00, 0x00,   // JMP 0
+    ];
+
+    #[test]
+    fn test_sio_echo() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        machine.load(0, &ECHO);
+        machine.push_input(b"hi");
+
+        machine.start();
+        machine.run(1000);
+
+        assert_eq!(machine.take_output(), b"hi");
+    }
+
+    #[test]
+    fn test_examine_deposit() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+
+        machine.examine(0x0100);
+        machine.deposit(0x3e);
+        machine.deposit_next(0x42);
+
+        assert_eq!(machine.leds().address, 0x0101);
+        assert_eq!(machine.leds().data, 0x42);
+
+        machine.examine(0x0100);
+        machine.single_step();
+
+        assert_eq!(machine.cpu().a(), 0x42);
+        assert_eq!(machine.leds().address, 0x0102);
+        assert!(!machine.leds().running);
+    }
+
+    #[test]
+    fn test_switches_ignored_while_running() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+
+        machine.start();
+        machine.examine(0x0100);
+        machine.deposit(0x12);
+
+        assert_eq!(machine.leds().address, 0);
+        assert_eq!(machine.cpu().get_memory(0), 0);
+    }
+
+    #[test]
+    fn test_reset_after_halt() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        machine.cpu().set_memory(0, &[0xfb, 0x3c, 0x76]); // EI, INR A, HLT
+
+        machine.start();
+        machine.run(100);
+        assert!(machine.leds().halted);
+        assert_eq!(machine.cpu().a(), 1);
+
+        machine.reset();
+        assert!(!machine.leds().halted);
+        assert_eq!(machine.leds().address, 0);
+
+        machine.run(100);
+        assert_eq!(machine.cpu().a(), 2);
+        assert!(machine.leds().halted);
+    }
+
+    #[test]
+    fn test_ram_size() {
+        let mut machine = Altair8800::new(init_decoder(), 4).unwrap();
+
+        machine.examine(0x1000);
+        machine.deposit(0x12);
+        machine.examine(0x0fff);
+        machine.deposit(0x12);
+
+        assert_eq!(machine.cpu().get_memory(0x1000), 0xff);
+        assert_eq!(machine.cpu().get_memory(0x0fff), 0x12);
+    }
+
+    #[test]
+    fn test_dcdd() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        let mut dcdd = Dcdd::new(1);
+        dcdd.insert_disk(0, AltairDisk::from_raw(vec![0x42]).unwrap());
+        machine.attach_dcdd(dcdd);
+
+        #[rustfmt::skip]
+        machine.load(0, &[
+            0xaf,               // XRA A
+            0xd3, 0x08,         // OUT 0x08 - select drive 0
+            0x3e, 0x04,         // MVI A, 0x04
+            0xd3, 0x09,         // OUT 0x09 - load head
+            0xdb, 0x09,         // IN 0x09 - sector 0 comes around
+            0xdb, 0x0a,         // IN 0x0a
+            0x76,               // HLT
+        ]);
+
+        machine.start();
+        machine.run(1000);
+
+        assert_eq!(machine.cpu().a(), 0x42);
+    }
+
+    #[test]
+    fn test_cassette() {
+        let mut recorder = ::emulator::devices::cassette::Encoder::new(KANSAS_CITY);
+        recorder.write_byte(0x42);
+        let wav = recorder.wav().clone();
+
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        let mut cassette = Cassette::new(ACR_STATUS_PORT, ACR_DATA_PORT, KANSAS_CITY, CPU_HZ);
+        cassette.insert_tape(&wav);
+        machine.attach_cassette(cassette);
+
+        #[rustfmt::skip]
+        machine.load(0, &[
+            0xdb, 0x06,         // IN 6
+            0x0f,               // RRC
+            0xda, 0x00, 0x00,   // JC 0
+            0xdb, 0x07,         // IN 7
+            0x76,               // HLT
+        ]);
+
+        machine.start();
+        machine.run(2 * CPU_HZ);
+
+        assert!(machine.leds().halted);
+        assert_eq!(machine.cpu().a(), 0x42);
+    }
+
+    #[test]
+    fn test_attached_device() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        machine.attach_device(Box::new(::emulator::devices::Usart8251::new(0x12, 0x13)));
+
+        #[rustfmt::skip]
+        machine.load(0, &[
+            0x3e, 0x4e,         // MVI A, 0x4e
+            0xd3, 0x13,         // OUT 0x13
+            0xdb, 0x13,         // IN 0x13
+            0x76,               // HLT
+        ]);
+
+        machine.start();
+        machine.run(100);
+
+        assert_eq!(machine.cpu().a() & 0x01, 0x01);
+    }
+
+    struct Counter(::std::rc::Rc<::std::cell::Cell<u32>>);
+
+    impl PortDevice for Counter {
+        fn update(&mut self, _cpu: &mut CPU, _cycles: u64) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn next_event(&self) -> Option<u64> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_device_updated_on_port_access() {
+        let updates = ::std::rc::Rc::new(::std::cell::Cell::new(0));
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+        machine.attach_device(Box::new(Counter(updates.clone())));
+
+        #[rustfmt::skip]
+        machine.load(0, &[
+            0x00, 0x00, 0x00,   // NOP x3
+            0xd3, 0x20,         // OUT 0x20
+            0x00, 0x00,         // NOP x2
+            0x76,               // HLT
+        ]);
+
+        machine.start();
+        machine.run(100);
+
+        // Once on attach, once on the first step to pick up the deadline and once for the OUT
+        assert_eq!(updates.get(), 3);
+    }
+
+    #[test]
+    fn test_sense_switches() {
+        let mut machine = Altair8800::new(init_decoder(), 4).unwrap();
+        machine.set_sense_switches(0xa5);
+        machine.load(0, &[0xdb, 0xff, 0x76]); // IN 0xff, HLT
+
+        machine.start();
+        machine.run(100);
+
+        assert_eq!(machine.cpu().a(), 0xa5);
+        assert!(machine.leds().halted);
+    }
+}
@@ -0,0 +1,111 @@
+use super::Altair8800;
+
+/// Operates the front panel from a script, one command per line.
+///
+/// Values are hexadecimal and `#` starts a comment:
+///
+/// ```text
+/// switches fd         # set the sense switches
+/// examine ff00
+/// deposit c3          # deposit at the examined address
+/// deposit-next 00     # advance, then deposit
+/// step 3              # single step while stopped
+/// reset
+/// run                 # raise the RUN switch
+/// wait 100000         # let a running machine execute that many cycles
+/// type PRINT 2+2      # send a line to the console, ending with a carriage return
+/// stop
+/// ```
+pub fn run_script(machine: &mut Altair8800, script: &str) -> Result<(), String> {
+    for (num, line) in script.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        run_command(machine, line).map_err(|e| format!("Line {}: {}", num + 1, e))?;
+    }
+
+    Ok(())
+}
+
+fn run_command(machine: &mut Altair8800, line: &str) -> Result<(), String> {
+    let (command, arg) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i + 1..].trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        "switches" => machine.set_sense_switches(parse_hex(arg)? as u8),
+        "examine" => machine.examine(parse_hex(arg)?),
+        "examine-next" => machine.examine_next(),
+        "deposit" => machine.deposit(parse_hex(arg)? as u8),
+        "deposit-next" => machine.deposit_next(parse_hex(arg)? as u8),
+        "step" => {
+            let count = if arg.is_empty() { 1 } else { parse_hex(arg)? };
+
+            for _i in 0..count {
+                machine.single_step();
+            }
+        }
+        "reset" => machine.reset(),
+        "run" => machine.start(),
+        "stop" => machine.stop(),
+        "wait" => {
+            machine.run(parse_hex(arg)? as u64);
+        }
+        "type" => {
+            machine.push_input(arg.as_bytes());
+            machine.push_input(b"\r");
+        }
+        _ => return Err(format!("Unknown front panel command: {}", command)),
+    }
+
+    Ok(())
+}
+
+fn parse_hex(arg: &str) -> Result<u16, String> {
+    u16::from_str_radix(arg, 16).map_err(|_| format!("Expected a hex value, got '{}'", arg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    #[test]
+    fn test_script() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+
+        let script = "
+            # MVI A, 0x55; OUT 0x11; HLT
+            examine 0
+            deposit 3e
+            deposit-next 55
+            deposit-next d3
+            deposit-next 11
+            deposit-next 76
+            reset
+            run
+            wait 100
+        ";
+
+        run_script(&mut machine, script).unwrap();
+
+        assert_eq!(machine.take_output(), &[0x55]);
+        assert!(machine.leds().halted);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let mut machine = Altair8800::new(init_decoder(), 16).unwrap();
+
+        assert!(run_script(&mut machine, "examine zz").is_err());
+        assert!(run_script(&mut machine, "\nlevitate").is_err());
+    }
+}
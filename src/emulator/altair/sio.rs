@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+
+use emulator::cpu::*;
+
+const STATUS_RDRF: u8 = 0x01;
+const STATUS_TDRE: u8 = 0x02;
+const CONTROL_MASTER_RESET: u8 = 0x03;
+
+/// Channel A of the MITS 88-2SIO, a Motorola 6850 ACIA.
+///
+/// The status/control register sits on the base port and the data register
+/// on the one after it. Received bytes come from the host through `push_input`
+/// and transmitted ones are collected for `take_output`.
+pub struct Sio2 {
+    status_port: usize,
+    data_port: usize,
+
+    control: u8,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Sio2 {
+    pub fn new(base_port: usize) -> Sio2 {
+        Sio2 {
+            status_port: base_port,
+            data_port: base_port + 1,
+            control: CONTROL_MASTER_RESET,
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.input.extend(data);
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.split_off(0)
+    }
+
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    /// Picks up what the CPU did to the ports since the last call and refreshes what it reads next
+    pub fn update(&mut self, cpu: &mut CPU) {
+        if let (v, true) = cpu.get_out_port(self.status_port) {
+            self.control = v;
+        }
+
+        if let (v, true) = cpu.get_out_port(self.data_port) {
+            self.output.push(v);
+        }
+
+        if cpu.was_in_port_read(self.data_port) {
+            self.input.pop_front();
+        }
+
+        let mut status = STATUS_TDRE;
+        if !self.input.is_empty() {
+            status |= STATUS_RDRF;
+        }
+
+        cpu.set_in_port(self.status_port, status);
+        cpu.set_in_port(self.data_port, *self.input.front().unwrap_or(&0));
+    }
+}
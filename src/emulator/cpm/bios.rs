@@ -0,0 +1,506 @@
+use std::collections::VecDeque;
+
+use super::disk::*;
+use emulator::cpu::*;
+use opcode_decoder::*;
+
+pub const DRIVE_COUNT: usize = 4;
+
+const CCP_SIZE: u16 = 0x0800;
+const BDOS_SIZE: u16 = 0x0e00;
+const SYSTEM_SIZE: usize = (CCP_SIZE + BDOS_SIZE) as usize;
+const SYSTEM_OFFSET: usize = SECTOR_SIZE; // track 0 sector 2, right after the cold start loader
+
+const IOBYTE_ADDR: u16 = 0x0003;
+const CURRENT_DISK_ADDR: u16 = 0x0004;
+const BDOS_JMP_ADDR: u16 = 0x0005;
+const DEFAULT_DMA: u16 = 0x0080;
+
+const BIOS_FUNCTION_COUNT: u16 = 17;
+const BIOS_STUBS_OFFSET: u16 = BIOS_FUNCTION_COUNT * 3;
+const DPH_OFFSET: u16 = 0x50;
+const DPH_SIZE: u16 = 16;
+const DPB_OFFSET: u16 = 0x90;
+const XLT_OFFSET: u16 = 0xa0;
+const DIRBUF_OFFSET: u16 = 0xc0;
+const CSV_OFFSET: u16 = 0x140;
+const CSV_SIZE: u16 = 16;
+const ALV_OFFSET: u16 = 0x180;
+const ALV_SIZE: u16 = 32;
+
+#[rustfmt::skip]
+const DPB_3740: [u8; 15] = [
+    26, 0,      // SPT - sectors per track
+    3,          // BSH - 1K blocks
+    7,          // BLM
+    0,          // EXM
+    242, 0,     // DSM - last block number
+    63, 0,      // DRM - last directory entry
+    0xc0, 0x00, // AL0, AL1 - two directory blocks
+    16, 0,      // CKS - directory check vector size
+    2, 0,       // OFF - system tracks
+];
+
+const XLT_3740: [u8; 26] = [
+    1, 7, 13, 19, 25, 5, 11, 17, 23, 3, 9, 15, 21, 2, 8, 14, 20, 26, 6, 12, 18, 24, 4, 10, 16, 22,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum BiosFunction {
+    Boot,
+    WBoot,
+    Const,
+    Conin,
+    Conout,
+    List,
+    Punch,
+    Reader,
+    Home,
+    Seldsk,
+    Settrk,
+    Setsec,
+    Setdma,
+    Read,
+    Write,
+    Listst,
+    Sectran,
+}
+
+impl BiosFunction {
+    fn by_index(index: u16) -> BiosFunction {
+        match index {
+            0 => BiosFunction::Boot,
+            1 => BiosFunction::WBoot,
+            2 => BiosFunction::Const,
+            3 => BiosFunction::Conin,
+            4 => BiosFunction::Conout,
+            5 => BiosFunction::List,
+            6 => BiosFunction::Punch,
+            7 => BiosFunction::Reader,
+            8 => BiosFunction::Home,
+            9 => BiosFunction::Seldsk,
+            10 => BiosFunction::Settrk,
+            11 => BiosFunction::Setsec,
+            12 => BiosFunction::Setdma,
+            13 => BiosFunction::Read,
+            14 => BiosFunction::Write,
+            15 => BiosFunction::Listst,
+            16 => BiosFunction::Sectran,
+            _ => panic!("Invalid BIOS function"),
+        }
+    }
+}
+
+/// A CP/M 2.2 system booted from IBM 3740 disk images.
+///
+/// CCP and BDOS are loaded from the system tracks of drive A, the BIOS is
+/// emulated. Its jump table points at one `RET` per function and the machine
+/// services the call in Rust when the CPU is about to execute it.
+pub struct CpmMachine {
+    cpu: CPU,
+    drives: Vec<Option<DiskImage>>,
+
+    ccp_base: u16,
+    bios_base: u16,
+
+    disk: usize,
+    track: u16,
+    sector: u16,
+    dma: u16,
+
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    waiting_for_input: bool,
+}
+
+impl CpmMachine {
+    /// `memory_size` in kilobytes is the size the CCP and BDOS on the system disk were built for
+    pub fn new(decoder: OpcodeDecoder, memory_size: u16) -> Result<CpmMachine, String> {
+        if !(20..=64).contains(&memory_size) {
+            return Err(format!(
+                "CP/M memory size must be between 20 and 64K, got {}K",
+                memory_size
+            ));
+        }
+
+        let top = memory_size as u32 * 1024;
+        let ccp_base = (top - 0x1c00) as u16;
+        let bios_base = ccp_base + CCP_SIZE + BDOS_SIZE;
+
+        let mut drives = Vec::new();
+        for _i in 0..DRIVE_COUNT {
+            drives.push(None);
+        }
+
+        let mut machine = CpmMachine {
+            cpu: CPU::new(decoder),
+            drives,
+            ccp_base,
+            bios_base,
+            disk: 0,
+            track: 0,
+            sector: 1,
+            dma: DEFAULT_DMA,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            waiting_for_input: false,
+        };
+
+        machine.install_bios();
+
+        Ok(machine)
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, disk: DiskImage) {
+        self.drives[drive] = Some(disk);
+    }
+
+    pub fn eject_disk(&mut self, drive: usize) -> Option<DiskImage> {
+        self.drives[drive].take()
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn bios_base(&self) -> u16 {
+        self.bios_base
+    }
+
+    /// Loads the system from drive A and starts the CCP
+    pub fn boot(&mut self) -> Result<(), String> {
+        self.cpu.set_memory(IOBYTE_ADDR, &[0]);
+        self.cpu.set_memory(CURRENT_DISK_ADDR, &[0]);
+        self.warm_boot()
+    }
+
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.input.extend(data);
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.split_off(0)
+    }
+
+    /// True when the last `run` stopped because CONIN had nothing to read
+    pub fn is_waiting_for_input(&self) -> bool {
+        self.waiting_for_input
+    }
+
+    /// Runs for about `cycles` cycles, returning early when waiting for console input
+    pub fn run(&mut self, cycles: u64) -> Result<u64, String> {
+        let mut spent = 0;
+        self.waiting_for_input = false;
+
+        while spent < cycles {
+            if let Some(function) = self.bios_call() {
+                if !self.call_bios(function)? {
+                    self.waiting_for_input = true;
+                    break;
+                }
+            }
+
+            spent += self.cpu.tick() as u64;
+        }
+
+        Ok(spent)
+    }
+
+    fn install_bios(&mut self) {
+        let base = self.bios_base;
+
+        for i in 0..BIOS_FUNCTION_COUNT {
+            let stub = base + BIOS_STUBS_OFFSET + i;
+            self.cpu
+                .set_memory(base + i * 3, &[0xc3, stub as u8, (stub >> 8) as u8]); // JMP stub
+            self.cpu.set_memory(stub, &[0xc9]); // RET
+        }
+
+        self.cpu.set_memory(base + DPB_OFFSET, &DPB_3740);
+        self.cpu.set_memory(base + XLT_OFFSET, &XLT_3740);
+
+        for drive in 0..DRIVE_COUNT as u16 {
+            let mut dph = Vec::new();
+
+            for word in &[
+                base + XLT_OFFSET,
+                0,
+                0,
+                0,
+                base + DIRBUF_OFFSET,
+                base + DPB_OFFSET,
+                base + CSV_OFFSET + drive * CSV_SIZE,
+                base + ALV_OFFSET + drive * ALV_SIZE,
+            ] {
+                dph.push(*word as u8);
+                dph.push((*word >> 8) as u8);
+            }
+
+            self.cpu.set_memory(self.dph_addr(drive as usize), &dph);
+        }
+    }
+
+    fn dph_addr(&self, drive: usize) -> u16 {
+        self.bios_base + DPH_OFFSET + drive as u16 * DPH_SIZE
+    }
+
+    fn bios_call(&self) -> Option<BiosFunction> {
+        let stubs = self.bios_base + BIOS_STUBS_OFFSET;
+        let pc = self.cpu.pc();
+
+        if pc >= stubs && pc < stubs + BIOS_FUNCTION_COUNT {
+            Some(BiosFunction::by_index(pc - stubs))
+        } else {
+            None
+        }
+    }
+
+    /// Returns false when the call has to wait for console input
+    fn call_bios(&mut self, function: BiosFunction) -> Result<bool, String> {
+        match function {
+            BiosFunction::Boot => self.boot()?,
+            BiosFunction::WBoot => self.warm_boot()?,
+            BiosFunction::Const => {
+                let status = if self.input.is_empty() { 0x00 } else { 0xff };
+                self.cpu.set_a(status);
+            }
+            BiosFunction::Conin => match self.input.pop_front() {
+                Some(c) => self.cpu.set_a(c & 0x7f),
+                None => return Ok(false),
+            },
+            BiosFunction::Conout => {
+                let c = self.cpu.bc() as u8;
+                self.output.push(c & 0x7f);
+            }
+            BiosFunction::List | BiosFunction::Punch => (),
+            BiosFunction::Reader => self.cpu.set_a(0x1a),
+            BiosFunction::Listst => self.cpu.set_a(0xff),
+            BiosFunction::Home => self.track = 0,
+            BiosFunction::Seldsk => {
+                let drive = (self.cpu.bc() & 0xff) as usize;
+
+                if drive < DRIVE_COUNT && self.drives[drive].is_some() {
+                    self.disk = drive;
+                    let dph = self.dph_addr(drive);
+                    self.cpu.set_hl(dph);
+                } else {
+                    self.cpu.set_hl(0);
+                }
+            }
+            BiosFunction::Settrk => self.track = self.cpu.bc(),
+            BiosFunction::Setsec => self.sector = self.cpu.bc(),
+            BiosFunction::Setdma => self.dma = self.cpu.bc(),
+            BiosFunction::Read => {
+                let status = self.read_sector();
+                self.cpu.set_a(status);
+            }
+            BiosFunction::Write => {
+                let status = self.write_sector();
+                self.cpu.set_a(status);
+            }
+            BiosFunction::Sectran => {
+                let sector = self.cpu.bc();
+                let table = self.cpu.de();
+
+                let translated = if table == 0 {
+                    sector + 1
+                } else {
+                    self.cpu.get_memory(table.wrapping_add(sector)) as u16
+                };
+
+                self.cpu.set_hl(translated);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn warm_boot(&mut self) -> Result<(), String> {
+        let system = match self.drives[0] {
+            Some(ref disk) => disk.data()[SYSTEM_OFFSET..SYSTEM_OFFSET + SYSTEM_SIZE].to_vec(),
+            None => return Err("No system disk in drive A".to_string()),
+        };
+
+        self.cpu.set_memory(self.ccp_base, &system);
+
+        let wboot = self.bios_base + 3;
+        let bdos = self.ccp_base + CCP_SIZE + 6;
+        self.cpu
+            .set_memory(0x0000, &[0xc3, wboot as u8, (wboot >> 8) as u8]);
+        self.cpu
+            .set_memory(BDOS_JMP_ADDR, &[0xc3, bdos as u8, (bdos >> 8) as u8]);
+
+        self.dma = DEFAULT_DMA;
+
+        let current_disk = self.cpu.get_memory(CURRENT_DISK_ADDR);
+        self.cpu.set_bc(current_disk as u16);
+        self.cpu.set_pc(self.ccp_base);
+
+        Ok(())
+    }
+
+    fn read_sector(&mut self) -> u8 {
+        let data = match self.drives[self.disk] {
+            Some(ref disk) => match disk.read_sector(self.track as usize, self.sector as usize) {
+                Some(data) => data.to_vec(),
+                None => return 1,
+            },
+            None => return 1,
+        };
+
+        self.cpu.set_memory(self.dma, &data);
+        0
+    }
+
+    fn write_sector(&mut self) -> u8 {
+        let mut data = Vec::new();
+        for i in 0..SECTOR_SIZE as u16 {
+            data.push(self.cpu.get_memory(self.dma.wrapping_add(i)));
+        }
+
+        let (track, sector) = (self.track as usize, self.sector as usize);
+
+        match self.drives[self.disk] {
+            Some(ref mut disk) => match disk.write_sector(track, sector, &data) {
+                Ok(()) => 0,
+                Err(_) => 1,
+            },
+            None => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(machine: &mut CpmMachine, function: u16) {
+        let addr = machine.bios_base() + function * 3;
+        machine.cpu().set_memory(0x0200, &[0xcd, addr as u8, (addr >> 8) as u8]); // CALL addr
+        machine.cpu().set_pc(0x0200);
+
+        while machine.cpu().pc() != 0x0203 {
+            machine.run(1).unwrap();
+        }
+    }
+
+    fn system_disk(ccp: &[u8]) -> DiskImage {
+        let mut data = vec![0; SYSTEM_OFFSET];
+        data.extend_from_slice(ccp);
+        DiskImage::from_raw(data).unwrap()
+    }
+
+    #[test]
+    fn test_boot_and_conout() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+        let conout = machine.bios_base() + 4 * 3;
+
+        #[rustfmt::skip]
+        let ccp = [
+            0x0e, b'A',                                 // MVI C, 'A'
+            0xcd, conout as u8, (conout >> 8) as u8,    // CALL CONOUT
+            0xc3, 0x05, 0xe4,                           // JMP $
+        ];
+
+        machine.insert_disk(0, system_disk(&ccp));
+        machine.boot().unwrap();
+
+        assert_eq!(machine.cpu().pc(), 0xe400);
+        assert_eq!(machine.cpu().get_memory(0x0005), 0xc3);
+        assert_eq!(machine.cpu().get_memory(0x0006), 0x06);
+        assert_eq!(machine.cpu().get_memory(0x0007), 0xec);
+
+        machine.run(1000).unwrap();
+
+        assert_eq!(machine.take_output(), b"A");
+    }
+
+    #[test]
+    fn test_boot_without_disk() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+
+        assert!(machine.boot().is_err());
+    }
+
+    #[test]
+    fn test_seldsk() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+        machine.insert_disk(1, DiskImage::from_raw(Vec::new()).unwrap());
+
+        machine.cpu().set_bc(1);
+        call(&mut machine, 9);
+        let dph = machine.cpu().hl();
+        assert_eq!(dph, machine.dph_addr(1));
+
+        let dpb = machine.cpu().get_memory(dph + 10) as u16
+            | (machine.cpu().get_memory(dph + 11) as u16) << 8;
+        assert_eq!(dpb, machine.bios_base() + DPB_OFFSET);
+
+        machine.cpu().set_bc(2);
+        call(&mut machine, 9);
+        assert_eq!(machine.cpu().hl(), 0);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+        machine.insert_disk(0, DiskImage::from_raw(Vec::new()).unwrap());
+
+        machine.cpu().set_bc(0);
+        call(&mut machine, 9);
+        machine.cpu().set_bc(5);
+        call(&mut machine, 10);
+        machine.cpu().set_bc(26);
+        call(&mut machine, 11);
+        machine.cpu().set_bc(0x3000);
+        call(&mut machine, 12);
+
+        machine.cpu().set_memory(0x3000, &[0x77; SECTOR_SIZE]);
+        call(&mut machine, 14);
+        assert_eq!(machine.cpu().a(), 0);
+
+        machine.cpu().set_memory(0x3000, &[0x00; SECTOR_SIZE]);
+        call(&mut machine, 13);
+        assert_eq!(machine.cpu().a(), 0);
+
+        assert_eq!(machine.cpu().get_memory(0x3000), 0x77);
+        assert_eq!(machine.cpu().get_memory(0x307f), 0x77);
+
+        let disk = machine.eject_disk(0).unwrap();
+        assert_eq!(disk.read_sector(5, 26).unwrap()[0], 0x77);
+    }
+
+    #[test]
+    fn test_sectran() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+        let xlt = machine.bios_base() + XLT_OFFSET;
+
+        machine.cpu().set_bc(1);
+        machine.cpu().set_de(xlt);
+        call(&mut machine, 16);
+
+        assert_eq!(machine.cpu().hl(), 7);
+    }
+
+    #[test]
+    fn test_conin_waits() {
+        let mut machine = CpmMachine::new(init_decoder(), 64).unwrap();
+        let conin = machine.bios_base() + 3 * 3;
+
+        #[rustfmt::skip]
+        machine.cpu().set_memory(0x0200, &[
+            0xcd, conin as u8, (conin >> 8) as u8,  // CALL CONIN
+            0xc3, 0x03, 0x02,                       // JMP $
+        ]);
+        machine.cpu().set_pc(0x0200);
+
+        machine.run(1000).unwrap();
+        assert!(machine.is_waiting_for_input());
+
+        machine.push_input(b"x");
+        machine.run(30).unwrap();
+        assert!(!machine.is_waiting_for_input());
+        assert_eq!(machine.cpu().pc(), 0x0203);
+        assert_eq!(machine.cpu().a(), b'x');
+    }
+}
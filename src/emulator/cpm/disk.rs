@@ -0,0 +1,215 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+/// 8" IBM 3740 single sided, single density geometry
+pub const TRACKS: usize = 77;
+pub const SECTORS_PER_TRACK: usize = 26;
+pub const SECTOR_SIZE: usize = 128;
+pub const DISK_SIZE: usize = TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE;
+
+const EMPTY_BYTE: u8 = 0xe5;
+const IMD_HEADER_END: u8 = 0x1a;
+
+/// A disk image kept in memory, with sectors stored in physical order.
+///
+/// Raw images opened from a file get every sector write saved back to it.
+/// IMD images are converted on load and their writes stay in memory.
+pub struct DiskImage {
+    data: Vec<u8>,
+    path: Option<PathBuf>,
+}
+
+impl DiskImage {
+    pub fn open(path: &str) -> Result<DiskImage, String> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("Could not read disk image {}: {}", path, e))?;
+
+        if data.starts_with(b"IMD ") {
+            return DiskImage::from_imd(&data);
+        }
+
+        let mut disk = DiskImage::from_raw(data)?;
+        disk.path = Some(Path::new(path).to_path_buf());
+        Ok(disk)
+    }
+
+    /// Short images are padded as if the remaining sectors were freshly formatted
+    pub fn from_raw(mut data: Vec<u8>) -> Result<DiskImage, String> {
+        if data.len() > DISK_SIZE {
+            return Err(format!(
+                "Disk image is {} bytes, expected at most {}",
+                data.len(),
+                DISK_SIZE
+            ));
+        }
+
+        data.resize(DISK_SIZE, EMPTY_BYTE);
+
+        Ok(DiskImage { data, path: None })
+    }
+
+    pub fn from_imd(imd: &[u8]) -> Result<DiskImage, String> {
+        let mut data = vec![EMPTY_BYTE; DISK_SIZE];
+
+        let mut pos = match imd.iter().position(|b| *b == IMD_HEADER_END) {
+            Some(p) => p + 1,
+            None => return Err("IMD image has no header terminator".to_string()),
+        };
+
+        while pos < imd.len() {
+            let header = take(imd, &mut pos, 5)?;
+            let (cylinder, head, sector_count, size_code) =
+                (header[1] as usize, header[2], header[3] as usize, header[4]);
+
+            if size_code != 0 || head & 0x0f != 0 || cylinder >= TRACKS {
+                return Err(format!(
+                    "IMD track {} head {} is not in IBM 3740 format",
+                    cylinder,
+                    head & 0x0f
+                ));
+            }
+
+            let sector_map = take(imd, &mut pos, sector_count)?.to_vec();
+
+            if head & 0x80 > 0 {
+                take(imd, &mut pos, sector_count)?;
+            }
+
+            if head & 0x40 > 0 {
+                take(imd, &mut pos, sector_count)?;
+            }
+
+            for sector in sector_map {
+                let record_type = take(imd, &mut pos, 1)?[0];
+
+                let sector_data = match record_type {
+                    0 => continue,
+                    1 | 3 | 5 | 7 => take(imd, &mut pos, SECTOR_SIZE)?.to_vec(),
+                    2 | 4 | 6 | 8 => vec![take(imd, &mut pos, 1)?[0]; SECTOR_SIZE],
+                    t => return Err(format!("Unknown IMD sector record type {}", t)),
+                };
+
+                let sector = sector as usize;
+                if sector == 0 || sector > SECTORS_PER_TRACK {
+                    return Err(format!("IMD sector number {} is out of range", sector));
+                }
+
+                let offset = sector_offset(cylinder, sector);
+                data[offset..offset + SECTOR_SIZE].copy_from_slice(&sector_data);
+            }
+        }
+
+        Ok(DiskImage { data, path: None })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Sectors are numbered from 1, as on the disk itself
+    pub fn read_sector(&self, track: usize, sector: usize) -> Option<&[u8]> {
+        if !is_valid_sector(track, sector) {
+            return None;
+        }
+
+        let offset = sector_offset(track, sector);
+        Some(&self.data[offset..offset + SECTOR_SIZE])
+    }
+
+    pub fn write_sector(&mut self, track: usize, sector: usize, data: &[u8]) -> Result<(), String> {
+        if !is_valid_sector(track, sector) {
+            return Err(format!("Invalid track {} sector {}", track, sector));
+        }
+
+        let offset = sector_offset(track, sector);
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&data[..SECTOR_SIZE]);
+
+        if let Some(ref path) = self.path {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|mut f| {
+                    f.seek(SeekFrom::Start(offset as u64))?;
+                    f.write_all(&data[..SECTOR_SIZE])
+                })
+                .map_err(|e| format!("Could not write to {}: {}", path.display(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn take<'a>(imd: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    if *pos + len > imd.len() {
+        return Err("IMD image is truncated".to_string());
+    }
+
+    let slice = &imd[*pos..*pos + len];
+    *pos += len;
+    Ok(slice)
+}
+
+fn is_valid_sector(track: usize, sector: usize) -> bool {
+    track < TRACKS && (1..=SECTORS_PER_TRACK).contains(&sector)
+}
+
+fn sector_offset(track: usize, sector: usize) -> usize {
+    (track * SECTORS_PER_TRACK + sector - 1) * SECTOR_SIZE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw_padding() {
+        let disk = DiskImage::from_raw(vec![1, 2, 3]).unwrap();
+
+        assert_eq!(disk.data().len(), DISK_SIZE);
+        assert_eq!(disk.read_sector(0, 1).unwrap()[2], 3);
+        assert_eq!(disk.read_sector(0, 1).unwrap()[3], EMPTY_BYTE);
+    }
+
+    #[test]
+    fn test_sector_offsets() {
+        let mut disk = DiskImage::from_raw(Vec::new()).unwrap();
+        disk.write_sector(2, 5, &[0x42; SECTOR_SIZE]).unwrap();
+
+        let offset = (2 * SECTORS_PER_TRACK + 4) * SECTOR_SIZE;
+        assert_eq!(disk.data()[offset], 0x42);
+        assert_eq!(disk.data()[offset - 1], EMPTY_BYTE);
+        assert!(disk.read_sector(0, 0).is_none());
+        assert!(disk.read_sector(TRACKS, 1).is_none());
+    }
+
+    #[test]
+    fn test_imd() {
+        let mut imd = b"IMD 1.18: test\r\n".to_vec();
+        imd.push(IMD_HEADER_END);
+        imd.extend_from_slice(&[0x00, 1, 0x00, 3, 0]); // mode, cylinder 1, head 0, 3 sectors of 128
+        imd.extend_from_slice(&[1, 3, 2]); // sector numbering map
+        imd.push(1);
+        imd.extend_from_slice(&[0x11; SECTOR_SIZE]);
+        imd.extend_from_slice(&[2, 0x33]);
+        imd.push(0);
+
+        let disk = DiskImage::from_imd(&imd).unwrap();
+
+        assert_eq!(disk.read_sector(1, 1).unwrap(), &[0x11; SECTOR_SIZE][..]);
+        assert_eq!(disk.read_sector(1, 3).unwrap(), &[0x33; SECTOR_SIZE][..]);
+        assert_eq!(disk.read_sector(1, 2).unwrap(), &[EMPTY_BYTE; SECTOR_SIZE][..]);
+    }
+
+    #[test]
+    fn test_imd_truncated() {
+        let mut imd = b"IMD".to_vec();
+        imd.push(IMD_HEADER_END);
+        imd.extend_from_slice(&[0x00, 0, 0x00, 2, 0, 1]);
+
+        assert!(DiskImage::from_imd(&imd).is_err());
+    }
+}
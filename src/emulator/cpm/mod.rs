@@ -0,0 +1,187 @@
+mod bios;
+mod disk;
+
+pub use self::bios::{CpmMachine, DRIVE_COUNT};
+pub use self::disk::DiskImage;
+
+use super::cpu::*;
+use opcode_decoder::*;
+
+const TPA_START: u16 = 0x0100;
+const BDOS_ENTRY: u16 = 0x0005;
+const BDOS_STUB: u16 = 0xfe00;
+
+const BDOS_CONSOLE_OUTPUT: u8 = 2;
+const BDOS_PRINT_STRING: u8 = 9;
+
+// A string without its '$' stops after wrapping around the whole memory once
+const MAX_STRING_LEN: usize = 0x10000;
+
+/// Runs a CP/M .COM program without an operating system behind it.
+///
+/// Only the BDOS console output functions (2 and 9) are emulated, which is
+/// enough for the classic CPU exercisers. Their output is collected into a
+/// string instead of being printed. A jump to 0x0000 (warm boot) ends the run.
+pub struct ComRunner {
+    cpu: CPU,
+    output: String,
+    cycles: u64,
+}
+
+impl ComRunner {
+    pub fn new(decoder: OpcodeDecoder, program: &[u8]) -> ComRunner {
+        let mut cpu = CPU::new(decoder);
+        cpu.set_memory(TPA_START, program);
+
+        cpu.set_memory(0x0000, &[0xc3, 0x00, 0x01]); // JMP 0x0100
+        cpu.set_memory(BDOS_ENTRY, &[0xc3, 0x00, 0xfe]); // JMP BDOS_STUB
+
+        // The host handles the call when the CPU gets to the stub, then it returns
+        cpu.set_memory(BDOS_STUB, &[0xc9]); // RET
+
+        ComRunner {
+            cpu,
+            output: String::new(),
+            cycles: 0,
+        }
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Runs until the program warm boots. Fails if it has not done so within `max_cycles`.
+    pub fn run(&mut self, max_cycles: u64) -> Result<(), String> {
+        loop {
+            if self.cpu.pc() == BDOS_STUB {
+                self.handle_bdos_call();
+            }
+
+            self.cycles += self.cpu.tick() as u64;
+
+            if self.cpu.pc() == 0 {
+                return Ok(());
+            }
+
+            if self.cycles >= max_cycles {
+                return Err(format!(
+                    "Program did not finish within {} cycles, pc: {:#06x?}",
+                    max_cycles,
+                    self.cpu.pc()
+                ));
+            }
+        }
+    }
+
+    fn handle_bdos_call(&mut self) {
+        let function = self.cpu.bc() as u8;
+        let de = self.cpu.de();
+
+        match function {
+            BDOS_CONSOLE_OUTPUT => self.output.push(de as u8 as char),
+            BDOS_PRINT_STRING => {
+                let mut addr = de;
+
+                for _ in 0..MAX_STRING_LEN {
+                    let c = self.cpu.get_memory(addr);
+                    if c == b'$' {
+                        break;
+                    }
+
+                    self.output.push(c as char);
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_print_string() {
+        #[rustfmt::skip]
+        let program = [
+            0x11, 0x0b, 0x01,   // LXI D, 0x010b
+            0x0e, 0x09,         // MVI C, 9
+            0xcd, 0x05, 0x00,   // CALL 5
+            0xc3, 0x00, 0x00,   // JMP 0
+            b'O', b'K', b'$',
+        ];
+
+        let mut runner = ComRunner::new(init_decoder(), &program);
+        runner.run(1000).unwrap();
+
+        assert_eq!(runner.output(), "OK");
+    }
+
+    #[test]
+    fn test_console_output() {
+        #[rustfmt::skip]
+        let program = [
+            0x1e, b'!',         // MVI E, '!'
+            0x0e, 0x02,         // MVI C, 2
+            0xcd, 0x05, 0x00,   // CALL 5
+            0xc3, 0x00, 0x00,   // JMP 0
+        ];
+
+        let mut runner = ComRunner::new(init_decoder(), &program);
+        runner.run(1000).unwrap();
+
+        assert_eq!(runner.output(), "!");
+    }
+
+    #[test]
+    fn test_guest_out_is_not_a_call() {
+        #[rustfmt::skip]
+        let program = [
+            0x3e, b'!',         // MVI A, '!'
+            0x0e, 0x02,         // MVI C, 2
+            0xd3, 0x00,         // OUT 0
+            0xd3, 0x01,         // OUT 1
+            0xd3, 0x02,         // OUT 2
+            0xc3, 0x00, 0x00,   // JMP 0
+        ];
+
+        let mut runner = ComRunner::new(init_decoder(), &program);
+        runner.run(1000).unwrap();
+
+        assert_eq!(runner.output(), "");
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        #[rustfmt::skip]
+        let program = [
+            0x11, 0x00, 0x00,   // LXI D, 0
+            0x0e, 0x09,         // MVI C, 9
+            0xcd, 0x05, 0x00,   // CALL 5
+            0xc3, 0x00, 0x00,   // JMP 0
+        ];
+
+        let mut runner = ComRunner::new(init_decoder(), &program);
+        runner.run(1000).unwrap();
+
+        assert_eq!(runner.output().chars().count(), MAX_STRING_LEN);
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        let program = [0xc3, 0x00, 0x01]; // JMP 0x0100
+
+        let mut runner = ComRunner::new(init_decoder(), &program);
+
+        assert!(runner.run(1000).is_err());
+    }
+}
@@ -50,9 +50,9 @@ impl FlagRegister {
 
     pub fn set(&mut self, flag: Flag, toggle: bool) {
         if toggle {
-            self.flags = self.flags | flag.bit();
+            self.flags |= flag.bit();
         } else {
-            self.flags = self.flags & !flag.bit();
+            self.flags &= !flag.bit();
         }
     }
 
@@ -3,14 +3,33 @@ mod ops;
 mod port;
 
 use std::boxed::Box;
+use std::fmt;
 
 use self::flags::{Flag, FlagRegister};
 use self::port::{InPort, OutPort};
+pub use self::port::{PortAccess, PortDevice};
 use super::math;
 use opcode_decoder::*;
 
 const MEMORY_SIZE: usize = 65536;
-const PORT_NUM: usize = 8;
+const PORT_NUM: usize = 256;
+const HALTED_CYCLES: u8 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MemoryKind {
+    Ram,
+    Rom,
+    Empty,
+}
+
+/// Where a peripheral's interrupt output is wired
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interrupt {
+    /// Straight to the CPU as an RST, for boards without an interrupt controller
+    Rst(u8),
+    /// To one of the eight request lines an interrupt controller watches
+    Line(u8),
+}
 
 #[derive(Debug, Copy, Clone)]
 enum Register {
@@ -57,7 +76,7 @@ impl Register {
             0x00 => (Register::B, Register::C),
             0x01 => (Register::D, Register::E),
             0x02 => (Register::H, Register::L),
-            0x03 => (Register::Flags, Register::A),
+            0x03 => (Register::A, Register::Flags),
             _ => panic!("Invalid register code"),
         }
     }
@@ -74,6 +93,9 @@ pub struct CPU {
     sp: u16,
     pc: u16,
     enable_interrupts: bool,
+    halted: bool,
+    irq_lines: u8,
+    last_port_access: Option<PortAccess>,
 
     flags: FlagRegister,
     memory: Memory,
@@ -88,6 +110,7 @@ pub struct CPU {
 
 struct Memory {
     data: Box<[u8; MEMORY_SIZE]>,
+    writable: Box<[bool; MEMORY_SIZE]>,
 }
 
 impl CPU {
@@ -112,6 +135,9 @@ impl CPU {
             pc: 0,
             flags: FlagRegister::new(),
             enable_interrupts: false,
+            halted: false,
+            irq_lines: 0,
+            last_port_access: None,
             memory: Memory::new(),
 
             in_ports,
@@ -127,6 +153,11 @@ impl CPU {
         self.memory.set_block(addr, data);
     }
 
+    /// Writes through the memory map like the CPU does, unlike `set_memory`
+    pub fn write_memory(&mut self, addr: u16, val: u8) {
+        self.memory.set(addr, val);
+    }
+
     pub fn get_memory(&self, addr: u16) -> u8 {
         self.memory.get(addr)
     }
@@ -135,11 +166,69 @@ impl CPU {
         self.memory.get_to_end(addr)
     }
 
+    /// ROM ignores writes from the CPU, empty space also reads as 0xff.
+    /// `set_memory` still writes anywhere, so ROM images are loaded after mapping.
+    pub fn map_memory(&mut self, addr: u16, len: usize, kind: MemoryKind) {
+        self.memory.map(addr, len, kind);
+    }
+
     pub fn pc(&self) -> u16 {
         self.pc
     }
 
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn set_a(&mut self, val: u8) {
+        self.a = val;
+    }
+
+    pub fn bc(&self) -> u16 {
+        self.get_reg_pair_value(Register::B, Register::C)
+    }
+
+    pub fn set_bc(&mut self, val: u16) {
+        self.set_reg_pair_value(Register::B, Register::C, val);
+    }
+
+    pub fn de(&self) -> u16 {
+        self.get_reg_pair_value(Register::D, Register::E)
+    }
+
+    pub fn set_de(&mut self, val: u16) {
+        self.set_reg_pair_value(Register::D, Register::E, val);
+    }
+
+    pub fn hl(&self) -> u16 {
+        self.get_reg_pair_value(Register::H, Register::L)
+    }
+
+    pub fn set_hl(&mut self, val: u16) {
+        self.set_reg_pair_value(Register::H, Register::L, val);
+    }
+
+    /// What the RESET pin does: PC goes to 0 and interrupts are disabled,
+    /// everything else is left as it was
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.enable_interrupts = false;
+        self.halted = false;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
     pub fn tick(&mut self) -> u8 {
+        if self.halted {
+            return HALTED_CYCLES;
+        }
+
         let op = self
             .decoder
             .get_next_op(self.memory.get_to_end(self.pc))
@@ -149,14 +238,7 @@ impl CPU {
     }
 
     pub fn print_state(&self) {
-        println!("{}", &self.to_string());
-    }
-
-    pub fn to_string(&self) -> String {
-        format!(
-            "{:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?}\n{:#010b} {:#06x?} {:#06x?}",
-            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.flags.get_all(), self.sp, self.pc
-        )
+        println!("{}", self);
     }
 
     fn update_flags(&mut self, result: u8, carry: Option<bool>, acarry: Option<bool>) {
@@ -182,7 +264,7 @@ impl CPU {
     }
 
     fn update_p(&mut self, result: u8) {
-        self.flags.set(Flag::P, result.count_ones() % 2 == 0);
+        self.flags.set(Flag::P, result.count_ones().is_multiple_of(2));
     }
 
     fn get_reg(&self, code: Register) -> &u8 {
@@ -245,13 +327,9 @@ impl CPU {
     fn reg_add(&mut self, code: Register, val: u8, set_carry: bool, with_carry: bool) {
         let old_val = self.get_reg_value(code);
 
-        let carry_val = if with_carry && self.flags.is_set(Flag::C) {
-            1
-        } else {
-            0
-        };
+        let carry_in = with_carry && self.flags.is_set(Flag::C);
 
-        let (result, carry, acarry) = math::add_8(old_val, val + carry_val);
+        let (result, carry, acarry) = math::add_8_with_carry(old_val, val, carry_in);
         self.set_reg_value(code, result);
         let carry = if set_carry { Some(carry) } else { None };
         self.update_flags(result, carry, Some(acarry));
@@ -260,13 +338,9 @@ impl CPU {
     fn reg_sub(&mut self, code: Register, val: u8, set_carry: bool, with_carry: bool) {
         let old_val = self.get_reg_value(code);
 
-        let carry_val = if with_carry && self.flags.is_set(Flag::C) {
-            1
-        } else {
-            0
-        };
+        let carry_in = with_carry && self.flags.is_set(Flag::C);
 
-        let (result, carry, acarry) = math::sub_8(old_val, val + carry_val);
+        let (result, carry, acarry) = math::sub_8_with_borrow(old_val, val, carry_in);
         self.set_reg_value(code, result);
         let carry = if set_carry { Some(carry) } else { None };
         self.update_flags(result, carry, Some(acarry));
@@ -314,7 +388,7 @@ impl CPU {
         };
         let (result, carry) = math::rot_left(val, lowest_bit_override);
         self.set_reg_value(Register::A, result);
-        self.update_flags(result, Some(carry), None);
+        self.flags.set(Flag::C, carry);
     }
 
     fn reg_rot_right(&mut self, with_carry: bool) {
@@ -326,7 +400,7 @@ impl CPU {
         };
         let (result, carry) = math::rot_right(val, highest_bit_override);
         self.set_reg_value(Register::A, result);
-        self.update_flags(result, Some(carry), None);
+        self.flags.set(Flag::C, carry);
     }
 
     fn reg_pair_add(&mut self, code1: Register, code2: Register, val: u16, set_carry: bool) {
@@ -342,23 +416,30 @@ impl CPU {
     }
 
     fn push(&mut self, val1: u8, val2: u8) {
-        self.memory.set(self.sp - 1, val1);
-        self.memory.set(self.sp - 2, val2);
-        self.sp -= 2;
+        self.memory.set(self.sp.wrapping_sub(1), val1);
+        self.memory.set(self.sp.wrapping_sub(2), val2);
+        self.sp = self.sp.wrapping_sub(2);
     }
 
     fn pop(&mut self) -> (u8, u8) {
-        let val1 = self.memory.get(self.sp + 1);
+        let val1 = self.memory.get(self.sp.wrapping_add(1));
         let val2 = self.memory.get(self.sp);
-        self.sp += 2;
+        self.sp = self.sp.wrapping_add(2);
 
         (val1, val2)
     }
 
     fn read_in_port(&mut self, port_num: u8) {
-        let port = &self.in_ports[port_num as usize];
-        let val = port.read();
-        self.a = val;
+        self.last_port_access = Some(PortAccess::In(port_num));
+
+        let port = &mut self.in_ports[port_num as usize];
+        port.mark_read();
+        self.a = port.read();
+    }
+
+    /// True if the CPU has executed IN on the port since the last call
+    pub fn was_in_port_read(&mut self, port: usize) -> bool {
+        self.in_ports[port].take_read()
     }
 
     pub fn get_in_port(&mut self, port: usize) -> u8 {
@@ -383,11 +464,17 @@ impl CPU {
     }
 
     fn write_out_port(&mut self, port: u8) {
+        self.last_port_access = Some(PortAccess::Out(port, self.a));
         let port = port as usize;
 
         self.out_ports[port].write(self.a);
     }
 
+    /// The last IN or OUT executed since the previous call
+    pub fn take_port_access(&mut self) -> Option<PortAccess> {
+        self.last_port_access.take()
+    }
+
     pub fn get_out_port(&mut self, port: usize) -> (u8, bool) {
         let port = &mut self.out_ports[port];
         let is_dirty = port.is_dirty();
@@ -399,30 +486,90 @@ impl CPU {
         self.out_ports[port].write(val);
     }
 
+    pub fn interrupts_enabled(&self) -> bool {
+        self.enable_interrupts
+    }
+
     pub fn interrupt(&mut self, handler_num: u8) {
+        self.interrupt_call(((handler_num & 0x07) << 3) as u16);
+    }
+
+    /// Takes an interrupt answered with a CALL, as an 8259 does
+    pub fn interrupt_call(&mut self, addr: u16) {
         self.enable_interrupts = false;
+        self.halted = false;
 
         let addr_high = math::higher_8(self.pc);
         let addr_low = math::lower_8(self.pc);
         self.push(addr_high, addr_low);
-        self.pc = ((handler_num & 0x07) << 3) as u16;
+        self.pc = addr;
+    }
+
+    /// Drives an interrupt request line, or fires the RST right away if interrupts are enabled
+    pub fn signal(&mut self, interrupt: Interrupt, level: bool) {
+        match interrupt {
+            Interrupt::Rst(n) => {
+                if level && self.enable_interrupts {
+                    self.interrupt(n);
+                }
+            }
+            Interrupt::Line(n) => self.set_irq_line(n, level),
+        }
+    }
+
+    pub fn set_irq_line(&mut self, line: u8, level: bool) {
+        let bit = 1 << (line & 0x07);
+
+        if level {
+            self.irq_lines |= bit;
+        } else {
+            self.irq_lines &= !bit;
+        }
+    }
+
+    /// One bit per request line
+    pub fn irq_lines(&self) -> u8 {
+        self.irq_lines
+    }
+}
+
+impl fmt::Display for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?} {:#04x?}\n{:#010b} {:#06x?} {:#06x?}",
+            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.flags.get_all(), self.sp, self.pc
+        )
     }
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        let mut data = Box::new([0; MEMORY_SIZE]);
+        let data = Box::new([0; MEMORY_SIZE]);
+        let writable = Box::new([true; MEMORY_SIZE]);
 
-        for i in 0..MEMORY_SIZE {
-            data[i] = 0;
-        }
+        Memory { data, writable }
+    }
+
+    pub fn map(&mut self, addr: u16, len: usize, kind: MemoryKind) {
+        let start = addr as usize;
+        let end = MEMORY_SIZE.min(start + len);
 
-        Memory { data }
+        for i in start..end {
+            self.writable[i] = kind == MemoryKind::Ram;
+
+            if kind == MemoryKind::Empty {
+                self.data[i] = 0xff;
+            }
+        }
     }
 
     pub fn set(&mut self, addr: u16, data: u8) {
         let addr = addr as usize;
-        self.data[addr] = data;
+
+        if self.writable[addr] {
+            self.data[addr] = data;
+        }
     }
 
     pub fn get(&self, addr: u16) -> u8 {
@@ -434,8 +581,8 @@ impl Memory {
         let mut addr = addr;
 
         for d in data {
-            self.set(addr, *d);
-            addr += 1;
+            self.data[addr as usize] = *d;
+            addr = addr.wrapping_add(1);
         }
     }
 
@@ -20,10 +20,10 @@ impl CPU {
         let opcode = optype.opcode;
 
         if self.debug {
-            println!("{}", op.to_string());
+            println!("{}", op);
         }
 
-        let mut pc_after = self.pc + optype.len as u16;
+        let mut pc_after = self.pc.wrapping_add(optype.len as u16);
         let mut cycles = optype.cycles.0;
 
         match opcode {
@@ -78,25 +78,31 @@ impl CPU {
             0x22 => {
                 let addr = math::combine_8_to_16(op.arg1(), op.arg2());
                 self.memory.set(addr, self.l);
-                self.memory.set(addr + 1, self.h);
+                self.memory.set(addr.wrapping_add(1), self.h);
             }
             0x27 => {
-                if (self.a & 0x0F) > 9 || self.flags.is_set(Flag::AC) {
-                    let (result, _, acarry) = math::add_8(self.a, 6);
-                    self.a = result;
-                    self.flags.set(Flag::AC, acarry);
+                let low = self.a & 0x0F;
+                let high = self.a >> 4;
+                let mut correction = 0;
+                let mut carry = self.flags.is_set(Flag::C);
+
+                if low > 9 || self.flags.is_set(Flag::AC) {
+                    correction |= 0x06;
                 }
 
-                if (self.a >> 4) > 9 || self.flags.is_set(Flag::C) {
-                    let (result, carry, _) = math::add_8(self.a, 6 << 4);
-                    self.a = result;
-                    self.flags.set(Flag::C, carry);
+                if high > 9 || carry || (high >= 9 && low > 9) {
+                    correction |= 0x60;
+                    carry = true;
                 }
+
+                let (result, _, acarry) = math::add_8(self.a, correction);
+                self.a = result;
+                self.update_flags(result, Some(carry), Some(acarry));
             }
             0x2a => {
                 let addr = math::combine_8_to_16(op.arg1(), op.arg2());
                 self.l = self.memory.get(addr);
-                self.h = self.memory.get(addr + 1);
+                self.h = self.memory.get(addr.wrapping_add(1));
             }
             0x2f => self.a = !self.a,
             0x30 => not_implemented(),
@@ -106,42 +112,42 @@ impl CPU {
             0x37 => self.flags.set(Flag::C, true),
             0x3a => self.a = self.memory.get(math::combine_8_to_16(op.arg1(), op.arg2())),
             0x3f => self.flags.flip(Flag::C),
-            0x76 => not_implemented(),
-            0x40...0x7f => {
+            0x76 => self.halted = true,
+            0x40..=0x7f => {
                 let src = Register::by_code(opcode);
                 let dst = Register::by_code(opcode >> 3);
                 self.reg_mov(dst, src);
             }
-            0x80...0x87 => {
+            0x80..=0x87 => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_add(Register::A, val, true, false);
             }
-            0x88...0x8f => {
+            0x88..=0x8f => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_add(Register::A, val, true, true);
             }
-            0x90...0x97 => {
+            0x90..=0x97 => {
                 let reg = Register::by_code(opcode);
                 let val = self.get_reg_value(reg);
                 self.reg_sub(Register::A, val, true, false);
             }
-            0x98...0x9f => {
+            0x98..=0x9f => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_sub(Register::A, val, true, true);
             }
-            0xa0...0xa7 => {
+            0xa0..=0xa7 => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_and(Register::A, val);
             }
-            0xa8...0xaf => {
+            0xa8..=0xaf => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_xor(Register::A, val);
             }
-            0xb0...0xb7 => {
+            0xb0..=0xb7 => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_or(Register::A, val);
             }
-            0xb8...0xbf => {
+            0xb8..=0xbf => {
                 let val = self.get_reg_value(Register::by_code(opcode));
                 self.reg_cmp(Register::A, val);
             }
@@ -176,7 +182,7 @@ impl CPU {
             0xc6 => self.reg_add(Register::A, op.arg1(), true, false),
             0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
                 self.push(math::higher_8(pc_after), math::lower_8(pc_after));
-                let exp = op.arg1() & 0x38;
+                let exp = opcode & 0x38;
                 pc_after = exp as u16;
             }
             0xc9 => {
@@ -197,13 +203,13 @@ impl CPU {
                 let reg_h = self.get_reg_value(Register::H);
                 let reg_l = self.get_reg_value(Register::L);
 
-                let memory_higher = self.memory.get(self.sp + 1);
+                let memory_higher = self.memory.get(self.sp.wrapping_add(1));
                 let memory_lower = self.memory.get(self.sp);
 
                 self.set_reg_value(Register::H, memory_higher);
                 self.set_reg_value(Register::L, memory_lower);
 
-                self.memory.set(self.sp + 1, reg_h);
+                self.memory.set(self.sp.wrapping_add(1), reg_h);
                 self.memory.set(self.sp, reg_l);
             }
             0xe6 => self.reg_and(Register::A, op.arg1()),
@@ -220,7 +226,6 @@ impl CPU {
             0xf9 => self.sp = self.get_reg_pair_value(Register::H, Register::L),
             0xfb => self.enable_interrupts = true,
             0xfe => self.reg_cmp(Register::A, op.arg1()),
-            _ => panic!("Encountered opcode outside of u16 scope"),
         };
 
         self.pc = pc_after;
@@ -1,16 +1,56 @@
+use super::CPU;
+
+/// A peripheral wired to some of the CPU's ports.
+///
+/// Machines call `update` right before an IN, after every IN or OUT and once
+/// the cycle from `next_event` is reached. The device picks up OUTs and IN
+/// reads from the ports it owns and sets what the CPU reads next.
+pub trait PortDevice {
+    /// `cycles` is the total number of CPU cycles run so far
+    fn update(&mut self, cpu: &mut CPU, cycles: u64);
+
+    /// The cycle the device next does something on its own, like raise an
+    /// interrupt, None if only port accesses move it. The default is due
+    /// straight away, so the device is updated after every instruction.
+    fn next_event(&self) -> Option<u64> {
+        Some(0)
+    }
+}
+
+/// An IN or OUT the CPU executed, with the value written for OUT
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PortAccess {
+    In(u8),
+    Out(u8, u8),
+}
+
 pub struct InPort {
     data: u8,
+    was_read: bool,
 }
 
 impl InPort {
     pub fn new(val: u8) -> InPort {
-        InPort { data: val }
+        InPort {
+            data: val,
+            was_read: false,
+        }
     }
 
     pub fn read(&self) -> u8 {
         self.data
     }
 
+    pub fn mark_read(&mut self) {
+        self.was_read = true;
+    }
+
+    pub fn take_read(&mut self) -> bool {
+        let was_read = self.was_read;
+        self.was_read = false;
+        was_read
+    }
+
     pub fn write(&mut self, val: u8) {
         self.data = val;
     }
@@ -1,20 +1,9 @@
 extern crate rand;
 
 use super::test::rand::prelude::*;
-use std::fs::File;
-use std::io::prelude::*;
 
 use super::*;
 
-fn init_decoder() -> OpcodeDecoder {
-    let mut opcode_data = String::new();
-    {
-        let mut opcode_file = File::open("./data/opcodes.txt").unwrap();
-        opcode_file.read_to_string(&mut opcode_data).unwrap();
-    }
-    OpcodeDecoder::new(&opcode_data)
-}
-
 fn set_op_at_rnd_addr(cpu: &mut CPU, op: u8) -> u16 {
     let addr: u16 = random();
     let addr = addr & 0x1fff;
@@ -265,7 +254,7 @@ fn test_rlc() {
     cpu.flags.set(Flag::C, true);
     cpu.tick();
 
-    assert_eq!(cpu.a, 0b01100100);
+    assert_eq!(cpu.a, 0b01100101);
     assert!(cpu.flags.is_set(Flag::C));
 }
 
@@ -303,7 +292,7 @@ fn test_rrc() {
     cpu.flags.set(Flag::C, true);
     cpu.tick();
 
-    assert_eq!(cpu.a, 0b00011001);
+    assert_eq!(cpu.a, 0b10011001);
     assert!(cpu.flags.is_set(Flag::C));
 }
 
@@ -438,3 +427,127 @@ fn ldax() {
 
     assert_eq!(cpu.a, 54);
 }
+
+#[test]
+fn test_rlc_keeps_zero_flag() {
+    let mut cpu = CPU::new(init_decoder());
+
+    set_op_at_rnd_addr(&mut cpu, 0x07);
+    cpu.a = 0b10000000;
+    cpu.flags.set(Flag::Z, true);
+    cpu.tick();
+
+    assert_eq!(cpu.a, 0b00000001);
+    assert!(cpu.flags.is_set(Flag::Z));
+    assert!(cpu.flags.is_set(Flag::C));
+}
+
+#[test]
+fn test_adc_overflow() {
+    let mut cpu = CPU::new(init_decoder());
+
+    set_op_at_rnd_addr(&mut cpu, 0x88);
+    cpu.a = 1;
+    cpu.b = 0xff;
+    cpu.flags.set(Flag::C, true);
+    cpu.tick();
+
+    assert_eq!(cpu.a, 1);
+    assert!(cpu.flags.is_set(Flag::C));
+    assert!(cpu.flags.is_set(Flag::AC));
+}
+
+#[test]
+fn test_daa_keeps_carry() {
+    let mut cpu = CPU::new(init_decoder());
+
+    set_op_at_rnd_addr(&mut cpu, 0x27);
+    cpu.a = 0x00;
+    cpu.flags.set(Flag::C, true);
+    cpu.tick();
+
+    assert_eq!(cpu.a, 0x60);
+    assert!(cpu.flags.is_set(Flag::C));
+    assert!(!cpu.flags.is_set(Flag::Z));
+}
+
+#[test]
+fn test_push_psw() {
+    let mut cpu = CPU::new(init_decoder());
+
+    set_op_at_rnd_addr(&mut cpu, 0xf5);
+    cpu.a = 0x12;
+    cpu.flags.set_all(0xff);
+    cpu.sp = 0xf000;
+    cpu.tick();
+
+    assert_eq!(cpu.sp, 0xeffe);
+    assert_eq!(cpu.memory.get(0xefff), 0x12);
+    assert_eq!(cpu.memory.get(0xeffe), 0xd7);
+}
+
+#[test]
+fn test_rst() {
+    let mut cpu = CPU::new(init_decoder());
+
+    let addr = set_op_at_rnd_addr(&mut cpu, 0xef);
+    cpu.sp = 0xf000;
+    cpu.tick();
+
+    let ret_addr = addr + 1;
+    assert_eq!(cpu.pc, 0x28);
+    assert_eq!(cpu.memory.get(0xefff), math::higher_8(ret_addr));
+    assert_eq!(cpu.memory.get(0xeffe), math::lower_8(ret_addr));
+}
+
+#[test]
+fn test_hlt() {
+    let mut cpu = CPU::new(init_decoder());
+
+    let addr = set_op_at_rnd_addr(&mut cpu, 0x76);
+    cpu.sp = 0xf000;
+    cpu.tick();
+    cpu.tick();
+
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.pc, addr + 1);
+
+    cpu.interrupt(1);
+
+    assert!(!cpu.is_halted());
+    assert_eq!(cpu.pc, 0x08);
+}
+
+#[test]
+fn test_memory_map() {
+    let mut cpu = CPU::new(init_decoder());
+
+    cpu.map_memory(0x4000, 0x100, MemoryKind::Rom);
+    cpu.map_memory(0x8000, 0x8000, MemoryKind::Empty);
+    cpu.set_memory(0x4000, &[0x12]);
+
+    cpu.memory.set(0x4000, 0x34);
+    cpu.memory.set(0x8000, 0x34);
+    cpu.memory.set(0x7fff, 0x34);
+
+    assert_eq!(cpu.get_memory(0x4000), 0x12);
+    assert_eq!(cpu.get_memory(0x8000), 0xff);
+    assert_eq!(cpu.get_memory(0xffff), 0xff);
+    assert_eq!(cpu.get_memory(0x7fff), 0x34);
+}
+
+#[test]
+fn test_in_port_read() {
+    let mut cpu = CPU::new(init_decoder());
+
+    let addr = set_op_at_rnd_addr(&mut cpu, 0xdb);
+    cpu.set_memory(addr + 1, &[0x11]);
+    cpu.set_in_port(0x11, 0x42);
+
+    assert!(!cpu.was_in_port_read(0x11));
+    cpu.tick();
+
+    assert_eq!(cpu.a, 0x42);
+    assert!(cpu.was_in_port_read(0x11));
+    assert!(!cpu.was_in_port_read(0x11));
+}
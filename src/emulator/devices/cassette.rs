@@ -0,0 +1,483 @@
+use std::f64::consts::PI;
+
+use emulator::cpu::*;
+use emulator::wav::Wav;
+
+// Status bits, active low like the 88-SIO the MITS 88-ACR is built on
+pub const STATUS_RX_EMPTY: u8 = 0x01;
+pub const STATUS_TX_BUSY: u8 = 0x80;
+
+const RECORD_SAMPLE_RATE: u32 = 44100;
+const RECORD_AMPLITUDE: f64 = 0.8;
+const LEADER_SECONDS: f64 = 1.0;
+
+// Start bit, eight data bits, two stop bits
+const FRAME_BITS: u32 = 11;
+
+/// Tones used for a 0 (space) and a 1 (mark) bit
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CassetteFormat {
+    pub baud: u32,
+    pub space_freq: f64,
+    pub mark_freq: f64,
+}
+
+/// Kansas City Standard, 300 baud
+pub const KANSAS_CITY: CassetteFormat = CassetteFormat {
+    baud: 300,
+    space_freq: 1200.0,
+    mark_freq: 2400.0,
+};
+
+/// Processor Technology CUTS, 1200 baud
+pub const CUTS: CassetteFormat = CassetteFormat {
+    baud: 1200,
+    space_freq: 600.0,
+    mark_freq: 1200.0,
+};
+
+impl CassetteFormat {
+    pub fn from_name(name: &str) -> Result<CassetteFormat, String> {
+        match name {
+            "kcs" => Ok(KANSAS_CITY),
+            "cuts" => Ok(CUTS),
+            _ => Err(format!("Unknown cassette format {}", name)),
+        }
+    }
+
+    fn bit_time(&self) -> f64 {
+        1.0 / self.baud as f64
+    }
+}
+
+/// Decodes the bytes on a tape, each with the time in seconds its last stop bit ends
+pub fn decode(wav: &Wav, format: CassetteFormat) -> Vec<(f64, u8)> {
+    let levels = tone_levels(wav, format);
+    let bit_samples = wav.sample_rate as f64 / format.baud as f64;
+    let level_at = |pos: f64| levels.get(pos as usize).cloned().unwrap_or(true);
+
+    let mut bytes = Vec::new();
+    let mut pos = 1;
+
+    while pos < levels.len() {
+        // Wait for the mark to space edge of a start bit
+        if levels[pos] || !levels[pos - 1] {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos as f64;
+
+        if level_at(start + bit_samples * 0.5) {
+            pos += 1;
+            continue;
+        }
+
+        let mut val = 0;
+        for bit in 0..8 {
+            if level_at(start + bit_samples * (1.5 + bit as f64)) {
+                val |= 1 << bit;
+            }
+        }
+
+        if !level_at(start + bit_samples * 9.5) {
+            // Framing error, look for the next start bit
+            pos += 1;
+            continue;
+        }
+
+        let end = start + bit_samples * 10.0;
+        bytes.push((end / wav.sample_rate as f64, val));
+        pos = (start + bit_samples * 9.5) as usize;
+    }
+
+    bytes
+}
+
+/// Marks every sample as mark (true) or space (false) by the length of its half wave
+fn tone_levels(wav: &Wav, format: CassetteFormat) -> Vec<bool> {
+    let rate = wav.sample_rate as f64;
+    let threshold = (rate / (2.0 * format.mark_freq) + rate / (2.0 * format.space_freq)) / 2.0;
+
+    let mean = if wav.samples.is_empty() {
+        0.0
+    } else {
+        wav.samples.iter().sum::<f32>() / wav.samples.len() as f32
+    };
+
+    let mut levels = vec![true; wav.samples.len()];
+    let mut positive = false;
+    let mut last_crossing = 0;
+
+    for (i, s) in wav.samples.iter().enumerate() {
+        let now_positive = *s - mean > 0.0;
+
+        if now_positive != positive {
+            let mark = ((i - last_crossing) as f64) < threshold;
+
+            for level in &mut levels[last_crossing..i] {
+                *level = mark;
+            }
+
+            positive = now_positive;
+            last_crossing = i;
+        }
+    }
+
+    levels
+}
+
+/// Writes audio for a tape, starting with a mark tone leader
+pub struct Encoder {
+    format: CassetteFormat,
+    wav: Wav,
+    // Samples already handed out by `take_samples`
+    taken: usize,
+    phase: f64,
+}
+
+impl Encoder {
+    pub fn new(format: CassetteFormat) -> Encoder {
+        let mut encoder = Encoder {
+            format,
+            wav: Wav::new(RECORD_SAMPLE_RATE),
+            taken: 0,
+            phase: 0.0,
+        };
+
+        encoder.mark_until(LEADER_SECONDS);
+        encoder
+    }
+
+    /// Length of the recording in seconds
+    pub fn time(&self) -> f64 {
+        self.len() as f64 / self.wav.sample_rate as f64
+    }
+
+    fn len(&self) -> usize {
+        self.taken + self.wav.samples.len()
+    }
+
+    /// Fills the gap up to `time` with mark tone
+    pub fn mark_until(&mut self, time: f64) {
+        if time > self.time() {
+            let duration = time - self.time();
+            self.tone(true, duration);
+        }
+    }
+
+    pub fn write_byte(&mut self, val: u8) {
+        let bit_time = self.format.bit_time();
+
+        self.tone(false, bit_time);
+        for bit in 0..8 {
+            self.tone(val & (1 << bit) != 0, bit_time);
+        }
+        self.tone(true, bit_time);
+        self.tone(true, bit_time);
+    }
+
+    /// The audio not taken yet
+    pub fn wav(&self) -> &Wav {
+        &self.wav
+    }
+
+    /// Hands out the audio so far, the encoder keeps going from where it was
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.taken += self.wav.samples.len();
+        self.wav.samples.split_off(0)
+    }
+
+    fn tone(&mut self, mark: bool, duration: f64) {
+        let freq = if mark {
+            self.format.mark_freq
+        } else {
+            self.format.space_freq
+        };
+
+        let rate = self.wav.sample_rate as f64;
+        let end = ((self.time() + duration) * rate).round() as usize;
+        let step = 2.0 * PI * freq / rate;
+
+        while self.len() < end {
+            self.wav
+                .samples
+                .push((self.phase.sin() * RECORD_AMPLITUDE) as f32);
+            self.phase = (self.phase + step) % (2.0 * PI);
+        }
+    }
+}
+
+/// A cassette interface on a status and a data port.
+///
+/// The tape moves with the CPU cycle count, so bytes arrive at the speed the
+/// format allows and ones the program doesn't read in time get overwritten.
+/// In turbo mode the next byte is ready as soon as the last one is read and
+/// output is recorded back to back.
+pub struct Cassette {
+    status_port: usize,
+    data_port: usize,
+    format: CassetteFormat,
+    cpu_hz: u64,
+    turbo: bool,
+
+    tape: Vec<(f64, u8)>,
+    tape_pos: usize,
+    rx: Option<u8>,
+
+    encoder: Encoder,
+    tx_busy_until: f64,
+    recorded: bool,
+    // Idle time left out of the recording, so gaps get no more than a leader
+    skipped: f64,
+
+    start_cycle: Option<u64>,
+}
+
+impl Cassette {
+    pub fn new(
+        status_port: usize,
+        data_port: usize,
+        format: CassetteFormat,
+        cpu_hz: u64,
+    ) -> Cassette {
+        Cassette {
+            status_port,
+            data_port,
+            format,
+            cpu_hz,
+            turbo: false,
+            tape: Vec::new(),
+            tape_pos: 0,
+            rx: None,
+            encoder: Encoder::new(format),
+            tx_busy_until: 0.0,
+            recorded: false,
+            skipped: 0.0,
+            start_cycle: None,
+        }
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    /// Loads a tape for playback, rewound to the start
+    pub fn insert_tape(&mut self, wav: &Wav) {
+        self.tape = decode(wav, self.format);
+        self.tape_pos = 0;
+        self.rx = None;
+        self.start_cycle = None;
+    }
+
+    /// Bytes left to play
+    pub fn remaining(&self) -> usize {
+        self.tape.len() - self.tape_pos
+    }
+
+    /// Audio of what was written to the data port since the last call
+    pub fn take_recording(&mut self) -> Wav {
+        Wav {
+            sample_rate: RECORD_SAMPLE_RATE,
+            samples: self.encoder.take_samples(),
+        }
+    }
+
+    /// Whether anything was recorded since the last call
+    pub fn take_recorded(&mut self) -> bool {
+        let recorded = self.recorded;
+        self.recorded = false;
+        recorded
+    }
+
+    fn record(&mut self, val: u8, time: f64) {
+        if !self.turbo {
+            let end = time + LEADER_SECONDS - self.skipped;
+            let max_end = self.encoder.time() + LEADER_SECONDS;
+
+            if end > max_end {
+                self.skipped += end - max_end;
+            }
+            self.encoder.mark_until(end.min(max_end));
+        }
+
+        self.encoder.write_byte(val);
+        self.tx_busy_until = time + self.format.bit_time() * FRAME_BITS as f64;
+        self.recorded = true;
+    }
+}
+
+impl PortDevice for Cassette {
+    // The tape only shows through IN, and machines update before those
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+
+    fn update(&mut self, cpu: &mut CPU, cycles: u64) {
+        let start = *self.start_cycle.get_or_insert(cycles);
+        let time = (cycles - start) as f64 / self.cpu_hz as f64;
+
+        if cpu.was_in_port_read(self.data_port) {
+            self.rx = None;
+        }
+
+        let (val, dirty) = cpu.get_out_port(self.data_port);
+        if dirty {
+            self.record(val, time);
+        }
+
+        if self.turbo {
+            if self.rx.is_none() && self.tape_pos < self.tape.len() {
+                self.rx = Some(self.tape[self.tape_pos].1);
+                self.tape_pos += 1;
+            }
+        } else {
+            while self.tape_pos < self.tape.len() && self.tape[self.tape_pos].0 <= time {
+                self.rx = Some(self.tape[self.tape_pos].1);
+                self.tape_pos += 1;
+            }
+        }
+
+        let mut status = 0;
+        if self.rx.is_none() {
+            status |= STATUS_RX_EMPTY;
+        }
+        if !self.turbo && time < self.tx_busy_until {
+            status |= STATUS_TX_BUSY;
+        }
+
+        cpu.set_in_port(self.status_port, status);
+        cpu.set_in_port(self.data_port, self.rx.unwrap_or(0));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn encode(format: CassetteFormat, data: &[u8]) -> Wav {
+        let mut encoder = Encoder::new(format);
+        for val in data {
+            encoder.write_byte(*val);
+        }
+        let end = encoder.time() + 0.1;
+        encoder.mark_until(end);
+
+        encoder.wav().clone()
+    }
+
+    fn decoded(wav: &Wav, format: CassetteFormat) -> Vec<u8> {
+        decode(wav, format).iter().map(|b| b.1).collect()
+    }
+
+    #[test]
+    fn test_kansas_city_roundtrip() {
+        let data = [0x00, 0xff, 0x55, 0xaa, b'B', b'A', b'S', b'I', b'C'];
+        let wav = encode(KANSAS_CITY, &data);
+
+        assert_eq!(decoded(&wav, KANSAS_CITY), data);
+    }
+
+    #[test]
+    fn test_cuts_roundtrip() {
+        let data = [0x01, 0x80, 0x7e, 0xd3];
+        let wav = encode(CUTS, &data);
+
+        assert_eq!(decoded(&wav, CUTS), data);
+    }
+
+    #[test]
+    fn test_decode_through_wav_file() {
+        let data = b"LOAD";
+        let wav = encode(KANSAS_CITY, data);
+        let wav = Wav::from_bytes(&wav.to_bytes()).unwrap();
+
+        assert_eq!(decoded(&wav, KANSAS_CITY), data);
+    }
+
+    #[test]
+    fn test_authentic_timing() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut cassette = Cassette::new(6, 7, KANSAS_CITY, 2_000_000);
+        cassette.insert_tape(&encode(KANSAS_CITY, &[0x42]));
+
+        cassette.update(&mut cpu, 0);
+        assert_eq!(cpu.get_in_port(6) & STATUS_RX_EMPTY, STATUS_RX_EMPTY);
+
+        // One second of leader, then eleven bits at 300 baud
+        cassette.update(&mut cpu, 2_000_000);
+        assert_eq!(cpu.get_in_port(6) & STATUS_RX_EMPTY, STATUS_RX_EMPTY);
+
+        cassette.update(&mut cpu, 2_080_000);
+        assert_eq!(cpu.get_in_port(6) & STATUS_RX_EMPTY, 0);
+        assert_eq!(cpu.get_in_port(7), 0x42);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut cassette = Cassette::new(6, 7, KANSAS_CITY, 2_000_000);
+        cassette.set_turbo(true);
+        cassette.insert_tape(&encode(KANSAS_CITY, &[1, 2]));
+
+        cassette.update(&mut cpu, 0);
+        assert_eq!(cpu.get_in_port(7), 1);
+
+        // Nothing moves until the byte is read
+        cassette.update(&mut cpu, 10);
+        assert_eq!(cpu.get_in_port(7), 1);
+        assert_eq!(cassette.remaining(), 1);
+    }
+
+    #[test]
+    fn test_load_and_record() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut cassette = Cassette::new(6, 7, KANSAS_CITY, 2_000_000);
+        cassette.set_turbo(true);
+        cassette.insert_tape(&encode(KANSAS_CITY, b"ok"));
+
+        #[rustfmt::skip]
+        cpu.set_memory(0, &[
+            0xdb, 0x06,         // IN 6
+            0x0f,               // RRC
+            0xda, 0x00, 0x00,   // JC 0
+            0xdb, 0x07,         // IN 7
+            0xd3, 0x07,         // OUT 7
+            0xc3, 0x00, 0x00,   // JMP 0
+        ]);
+
+        let mut cycles = 0;
+        while cycles < 2000 {
+            cycles += cpu.tick() as u64;
+            cassette.update(&mut cpu, cycles);
+        }
+
+        assert!(cassette.take_recorded());
+        assert_eq!(decoded(&cassette.take_recording(), KANSAS_CITY), b"ok");
+        assert!(cassette.take_recording().samples.is_empty());
+    }
+
+    #[test]
+    fn test_record_after_idle() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut cassette = Cassette::new(6, 7, KANSAS_CITY, 2_000_000);
+        let hour = 3600 * 2_000_000;
+        let frame = 2_000_000 * FRAME_BITS as u64 / 300;
+
+        cassette.update(&mut cpu, 0);
+        cpu.set_out_port(7, b'A');
+        cassette.update(&mut cpu, hour);
+        cpu.set_out_port(7, b'B');
+        cassette.update(&mut cpu, hour + frame);
+        cpu.set_out_port(7, b'C');
+        cassette.update(&mut cpu, 2 * hour);
+
+        // The starting leader and two idle gaps of a leader each, three bytes
+        let wav = cassette.take_recording();
+        let len = (3.0 * LEADER_SECONDS + 3.0 * FRAME_BITS as f64 / 300.0)
+            * RECORD_SAMPLE_RATE as f64;
+        assert!((wav.samples.len() as f64 - len).abs() < 10.0);
+        assert_eq!(decoded(&wav, KANSAS_CITY), b"ABC");
+    }
+}
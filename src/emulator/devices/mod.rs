@@ -0,0 +1,13 @@
+pub mod cassette;
+pub mod pic;
+pub mod ppi;
+pub mod serial;
+pub mod timer;
+pub mod usart;
+
+pub use self::cassette::{Cassette, CassetteFormat, CUTS, KANSAS_CITY};
+pub use self::pic::Pic8259;
+pub use self::ppi::{Ppi8255, PpiPort};
+pub use self::serial::{open_backend, SerialBackend};
+pub use self::timer::Timer8253;
+pub use self::usart::Usart8251;
@@ -0,0 +1,472 @@
+use emulator::cpu::*;
+
+const ICW1: u8 = 0x10;
+const ICW1_IC4: u8 = 0x01;
+const ICW1_SINGLE: u8 = 0x02;
+const ICW1_INTERVAL_4: u8 = 0x04;
+const ICW1_LEVEL: u8 = 0x08;
+
+const ICW4_8086: u8 = 0x01;
+const ICW4_AUTO_EOI: u8 = 0x02;
+
+const OCW3: u8 = 0x08;
+const OCW3_POLL: u8 = 0x04;
+const OCW3_READ: u8 = 0x02;
+const OCW3_READ_ISR: u8 = 0x01;
+const OCW3_SET_SPECIAL_MASK: u8 = 0x40;
+const OCW3_SPECIAL_MASK: u8 = 0x20;
+
+const OCW2_ROTATE: u8 = 0x80;
+const OCW2_SPECIFIC: u8 = 0x40;
+const OCW2_EOI: u8 = 0x20;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Expect {
+    Icw1,
+    Icw2,
+    Icw3,
+    Icw4,
+    Ready,
+}
+
+/// An Intel 8259A programmable interrupt controller on two consecutive ports.
+///
+/// It watches the CPU's eight interrupt request lines, which peripherals
+/// drive with `Interrupt::Line`, and answers the CPU's acknowledge with a
+/// CALL in 8080 mode. In 8086 mode, which has no vector an 8080 could use,
+/// IR n is answered with RST n instead.
+pub struct Pic8259 {
+    base_port: usize,
+
+    expect: Expect,
+    icw1: u8,
+    icw2: u8,
+    icw4: u8,
+
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    last_lines: u8,
+
+    lowest_priority: u8,
+    rotate_on_auto_eoi: bool,
+    special_mask: bool,
+    read_isr: bool,
+    poll: bool,
+}
+
+impl Pic8259 {
+    pub fn new(base_port: usize) -> Pic8259 {
+        Pic8259 {
+            base_port,
+            expect: Expect::Icw1,
+            icw1: 0,
+            icw2: 0,
+            icw4: 0,
+            irr: 0,
+            isr: 0,
+            imr: 0xff,
+            last_lines: 0,
+            lowest_priority: 7,
+            rotate_on_auto_eoi: false,
+            special_mask: false,
+            read_isr: false,
+            poll: false,
+        }
+    }
+
+    pub fn irr(&self) -> u8 {
+        self.irr
+    }
+
+    pub fn isr(&self) -> u8 {
+        self.isr
+    }
+
+    pub fn imr(&self) -> u8 {
+        self.imr
+    }
+
+    /// The INT pin: a request that beats everything in service
+    pub fn int(&self) -> bool {
+        self.expect == Expect::Ready && self.pending().is_some()
+    }
+
+    /// Lines from highest to lowest priority
+    fn by_priority(&self) -> Vec<u8> {
+        (1..9).map(|i| (self.lowest_priority + i) % 8).collect()
+    }
+
+    fn pending(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+
+        for line in self.by_priority() {
+            let bit = 1 << line;
+
+            // In special mask mode masked lines stop blocking lower priorities
+            if self.isr & bit != 0 && !(self.special_mask && self.imr & bit != 0) {
+                return None;
+            }
+
+            if requests & bit != 0 {
+                return Some(line);
+            }
+        }
+
+        None
+    }
+
+    fn sample_lines(&mut self, lines: u8) {
+        if self.icw1 & ICW1_LEVEL != 0 {
+            self.irr = lines;
+        } else {
+            let rising = lines & !self.last_lines;
+            // A request that goes away before it is acknowledged is lost
+            self.irr = (self.irr | rising) & lines;
+        }
+
+        self.last_lines = lines;
+    }
+
+    /// The INTA sequence, returning the line being serviced
+    fn acknowledge(&mut self) -> Option<u8> {
+        let line = self.pending()?;
+        let bit = 1 << line;
+
+        if self.icw1 & ICW1_LEVEL == 0 {
+            self.irr &= !bit;
+        }
+
+        if self.icw4 & ICW4_AUTO_EOI != 0 {
+            if self.rotate_on_auto_eoi {
+                self.lowest_priority = line;
+            }
+        } else {
+            self.isr |= bit;
+        }
+
+        Some(line)
+    }
+
+    /// Address of the CALL the controller answers IR `line` with in 8080 mode
+    pub fn call_address(&self, line: u8) -> u16 {
+        let low = if self.icw1 & ICW1_INTERVAL_4 != 0 {
+            (self.icw1 & 0xe0) | (line << 2)
+        } else {
+            (self.icw1 & 0xc0) | (line << 3)
+        };
+
+        ((self.icw2 as u16) << 8) | low as u16
+    }
+
+    fn deliver(&mut self, cpu: &mut CPU) {
+        if let Some(line) = self.acknowledge() {
+            if self.icw4 & ICW4_8086 != 0 {
+                cpu.interrupt(line);
+            } else {
+                cpu.interrupt_call(self.call_address(line));
+            }
+        }
+    }
+
+    fn write(&mut self, a0: bool, val: u8) {
+        if !a0 && val & ICW1 != 0 {
+            self.icw1 = val;
+            self.icw4 = 0;
+            self.imr = 0;
+            self.isr = 0;
+            self.irr = 0;
+            self.lowest_priority = 7;
+            self.special_mask = false;
+            self.read_isr = false;
+            self.poll = false;
+            self.expect = Expect::Icw2;
+            return;
+        }
+
+        match self.expect {
+            Expect::Icw1 => (),
+            Expect::Icw2 => {
+                self.icw2 = val;
+                self.expect = if self.icw1 & ICW1_SINGLE != 0 {
+                    self.after_icw3()
+                } else {
+                    Expect::Icw3
+                };
+            }
+            // Cascading has nothing to talk to, the byte is accepted and ignored
+            Expect::Icw3 => self.expect = self.after_icw3(),
+            Expect::Icw4 => {
+                self.icw4 = val;
+                self.expect = Expect::Ready;
+            }
+            Expect::Ready if a0 => self.imr = val,
+            Expect::Ready if val & OCW3 != 0 => self.write_ocw3(val),
+            Expect::Ready => self.write_ocw2(val),
+        }
+    }
+
+    fn after_icw3(&self) -> Expect {
+        if self.icw1 & ICW1_IC4 != 0 {
+            Expect::Icw4
+        } else {
+            Expect::Ready
+        }
+    }
+
+    fn write_ocw2(&mut self, val: u8) {
+        let level = val & 0x07;
+        let rotate = val & OCW2_ROTATE != 0;
+
+        match (val & OCW2_SPECIFIC != 0, val & OCW2_EOI != 0) {
+            (false, true) => {
+                let highest = self
+                    .by_priority()
+                    .into_iter()
+                    .find(|l| self.isr & (1 << l) != 0);
+
+                if let Some(line) = highest {
+                    self.isr &= !(1 << line);
+                    if rotate {
+                        self.lowest_priority = line;
+                    }
+                }
+            }
+            (true, true) => {
+                self.isr &= !(1 << level);
+                if rotate {
+                    self.lowest_priority = level;
+                }
+            }
+            (true, false) => {
+                if rotate {
+                    self.lowest_priority = level;
+                }
+            }
+            (false, false) => self.rotate_on_auto_eoi = rotate,
+        }
+    }
+
+    fn write_ocw3(&mut self, val: u8) {
+        if val & OCW3_SET_SPECIAL_MASK != 0 {
+            self.special_mask = val & OCW3_SPECIAL_MASK != 0;
+        }
+
+        if val & OCW3_READ != 0 {
+            self.read_isr = val & OCW3_READ_ISR != 0;
+        }
+
+        self.poll = val & OCW3_POLL != 0;
+    }
+
+    fn read_value(&self, a0: bool) -> u8 {
+        if a0 {
+            return self.imr;
+        }
+
+        if self.poll {
+            return match self.pending() {
+                Some(line) => 0x80 | line,
+                None => 0,
+            };
+        }
+
+        if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
+    }
+}
+
+impl PortDevice for Pic8259 {
+    fn update(&mut self, cpu: &mut CPU, _cycles: u64) {
+        // A poll read counts as the acknowledge
+        if cpu.was_in_port_read(self.base_port) && self.poll {
+            self.poll = false;
+            self.acknowledge();
+        }
+
+        if let (v, true) = cpu.get_out_port(self.base_port) {
+            self.write(false, v);
+        }
+        if let (v, true) = cpu.get_out_port(self.base_port + 1) {
+            self.write(true, v);
+        }
+
+        self.sample_lines(cpu.irq_lines());
+
+        if self.int() && cpu.interrupts_enabled() && !self.poll {
+            self.deliver(cpu);
+        }
+
+        cpu.set_in_port(self.base_port, self.read_value(false));
+        cpu.set_in_port(self.base_port + 1, self.read_value(true));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn out(pic: &mut Pic8259, cpu: &mut CPU, port: usize, val: u8) {
+        cpu.set_out_port(port, val);
+        pic.update(cpu, 0);
+    }
+
+    /// Single 8259 in 8080 mode with vectors every 4 bytes from 0x1000
+    fn init(pic: &mut Pic8259, cpu: &mut CPU) {
+        out(pic, cpu, 0, 0x16);
+        out(pic, cpu, 1, 0x10);
+        out(pic, cpu, 1, 0x00);
+    }
+
+    fn cpu_waiting(decoder: OpcodeDecoder) -> CPU {
+        let mut cpu = CPU::new(decoder);
+
+        #[rustfmt::skip]
+        cpu.set_memory(0x100, &[
+            0x31, 0x00, 0x02,   // LXI SP, 0x200
+            0xfb,               // EI
+            0xc3, 0x04, 0x01,   // JMP $
+        ]);
+        cpu.set_pc(0x100);
+
+        for _ in 0..3 {
+            cpu.tick();
+        }
+
+        cpu
+    }
+
+    #[test]
+    fn test_call_vector() {
+        let mut cpu = cpu_waiting(init_decoder());
+        let mut pic = Pic8259::new(0);
+        init(&mut pic, &mut cpu);
+
+        cpu.set_irq_line(5, true);
+        pic.update(&mut cpu, 0);
+
+        assert_eq!(cpu.pc(), 0x1014);
+        assert_eq!(pic.isr(), 0x20);
+        assert_eq!(pic.irr(), 0x00);
+        assert!(!cpu.interrupts_enabled());
+    }
+
+    #[test]
+    fn test_rst_mode() {
+        let mut cpu = cpu_waiting(init_decoder());
+        let mut pic = Pic8259::new(0);
+
+        out(&mut pic, &mut cpu, 0, 0x13); // edge triggered, single, ICW4
+        out(&mut pic, &mut cpu, 1, 0x00);
+        out(&mut pic, &mut cpu, 1, ICW4_8086 | ICW4_AUTO_EOI);
+
+        cpu.set_irq_line(6, true);
+        pic.update(&mut cpu, 0);
+
+        assert_eq!(cpu.pc(), 0x30);
+        assert_eq!(pic.isr(), 0x00);
+    }
+
+    #[test]
+    fn test_priority_and_eoi() {
+        let mut cpu = cpu_waiting(init_decoder());
+        let mut pic = Pic8259::new(0);
+        init(&mut pic, &mut cpu);
+
+        cpu.set_irq_line(3, true);
+        cpu.set_irq_line(1, true);
+        pic.update(&mut cpu, 0);
+        assert_eq!(pic.isr(), 0x02);
+
+        // IR3 waits for IR1 to finish even with interrupts enabled again
+        cpu.set_irq_line(1, false);
+        cpu.set_pc(0x104);
+        cpu.set_memory(0x104, &[0xfb]);
+        cpu.tick();
+        pic.update(&mut cpu, 0);
+        assert_eq!(pic.isr(), 0x02);
+        assert_eq!(pic.irr(), 0x08);
+
+        out(&mut pic, &mut cpu, 0, OCW2_EOI);
+        assert_eq!(pic.isr(), 0x08);
+        assert_eq!(cpu.pc(), 0x100c);
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut cpu = cpu_waiting(init_decoder());
+        let mut pic = Pic8259::new(0);
+        init(&mut pic, &mut cpu);
+
+        out(&mut pic, &mut cpu, 1, 0x01); // mask IR0
+        cpu.set_irq_line(0, true);
+        pic.update(&mut cpu, 0);
+
+        assert!(!pic.int());
+        assert_eq!(pic.irr(), 0x01);
+        assert_eq!(cpu.get_in_port(1), 0x01);
+
+        out(&mut pic, &mut cpu, 1, 0x00);
+        assert_eq!(cpu.pc(), 0x1000);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut pic = Pic8259::new(0);
+        init(&mut pic, &mut cpu);
+
+        out(&mut pic, &mut cpu, 0, OCW2_ROTATE | OCW2_SPECIFIC | 4);
+        cpu.set_irq_line(2, true);
+        cpu.set_irq_line(5, true);
+        pic.update(&mut cpu, 0);
+
+        assert_eq!(pic.pending(), Some(5));
+    }
+
+    #[test]
+    fn test_poll_and_read_isr() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut pic = Pic8259::new(0);
+        init(&mut pic, &mut cpu);
+
+        cpu.set_irq_line(4, true);
+        out(&mut pic, &mut cpu, 0, OCW3 | OCW3_POLL);
+        assert_eq!(cpu.get_in_port(0), 0x84);
+
+        cpu.set_memory(0, &[0xdb, 0x00]); // IN 0
+        cpu.set_pc(0);
+        cpu.tick();
+        pic.update(&mut cpu, 0);
+
+        out(&mut pic, &mut cpu, 0, OCW3 | OCW3_READ | OCW3_READ_ISR);
+        assert_eq!(cpu.get_in_port(0), 0x10);
+    }
+
+    #[test]
+    fn test_shared_with_devices() {
+        use emulator::devices::Usart8251;
+
+        let mut cpu = cpu_waiting(init_decoder());
+        let mut pic = Pic8259::new(0x20);
+        let mut usart = Usart8251::new(0x10, 0x11);
+        usart.set_interrupt(Some(Interrupt::Line(2)));
+
+        out(&mut pic, &mut cpu, 0x20, 0x16);
+        out(&mut pic, &mut cpu, 0x21, 0x10);
+        out(&mut pic, &mut cpu, 0x11, 0x4e);
+        usart.update(&mut cpu, 0);
+        out(&mut pic, &mut cpu, 0x11, 0x04);
+        usart.update(&mut cpu, 0);
+
+        usart.push_input(b"z");
+        usart.update(&mut cpu, 0);
+        pic.update(&mut cpu, 0);
+
+        assert_eq!(cpu.pc(), 0x1008);
+    }
+}
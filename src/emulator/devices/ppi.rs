@@ -0,0 +1,564 @@
+use emulator::cpu::*;
+
+const CONTROL_MODE_SET: u8 = 0x80;
+const CONTROL_A_MODE: u8 = 0x60;
+const CONTROL_A_INPUT: u8 = 0x10;
+const CONTROL_C_UPPER_INPUT: u8 = 0x08;
+const CONTROL_B_MODE: u8 = 0x04;
+const CONTROL_B_INPUT: u8 = 0x02;
+const CONTROL_C_LOWER_INPUT: u8 = 0x01;
+
+// Port C handshake lines
+const PC_INTR_B: u8 = 0x01;
+const PC_BUF_B: u8 = 0x02;
+const PC_INTE_B: u8 = 0x04;
+const PC_INTR_A: u8 = 0x08;
+const PC_INTE_A_IN: u8 = 0x10;
+const PC_IBF_A: u8 = 0x20;
+const PC_INTE_A_OUT: u8 = 0x40;
+const PC_OBF_A: u8 = 0x80;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PpiPort {
+    A,
+    B,
+    C,
+}
+
+impl PpiPort {
+    fn index(self) -> usize {
+        match self {
+            PpiPort::A => 0,
+            PpiPort::B => 1,
+            PpiPort::C => 2,
+        }
+    }
+}
+
+pub type ReadCallback = Box<dyn FnMut() -> u8>;
+pub type WriteCallback = Box<dyn FnMut(u8)>;
+
+/// Handshake state of a port in mode 1 or 2
+#[derive(Default)]
+struct Strobed {
+    input: u8,
+    input_full: bool,
+    output_full: bool,
+    // The peripheral took the last byte written, INTR waits for the next write
+    acknowledged: bool,
+}
+
+/// An Intel 8255 programmable peripheral interface on four consecutive
+/// ports: A, B, C and the control word.
+///
+/// Input lines are read from host callbacks whenever the CPU might IN from
+/// them, and output callbacks see every change to an output latch. Strobed
+/// devices in modes 1 and 2 use `strobe` and `acknowledge` instead. INTR_A
+/// and INTR_B can be wired to an RST or to an interrupt controller line.
+pub struct Ppi8255 {
+    base_port: usize,
+    control: u8,
+
+    latches: [u8; 3],
+    strobed: [Strobed; 2],
+
+    on_read: [Option<ReadCallback>; 3],
+    on_write: [Option<WriteCallback>; 3],
+    interrupts: [Option<Interrupt>; 2],
+}
+
+impl Ppi8255 {
+    pub fn new(base_port: usize) -> Ppi8255 {
+        Ppi8255 {
+            base_port,
+            // Every port starts as an input in mode 0
+            control: 0x9b,
+            latches: [0; 3],
+            strobed: [Strobed::default(), Strobed::default()],
+            on_read: [None, None, None],
+            on_write: [None, None, None],
+            interrupts: [None; 2],
+        }
+    }
+
+    pub fn on_read(&mut self, port: PpiPort, callback: ReadCallback) {
+        self.on_read[port.index()] = Some(callback);
+    }
+
+    pub fn on_write(&mut self, port: PpiPort, callback: WriteCallback) {
+        self.on_write[port.index()] = Some(callback);
+    }
+
+    /// Where INTR of port A or B goes, None to leave it unconnected
+    pub fn set_interrupt(&mut self, port: PpiPort, interrupt: Option<Interrupt>) {
+        if port != PpiPort::C {
+            self.interrupts[port.index()] = interrupt;
+        }
+    }
+
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    /// The value in an output latch
+    pub fn output(&self, port: PpiPort) -> u8 {
+        self.latches[port.index()]
+    }
+
+    /// A peripheral strobing a byte into port A or B, false if the input buffer was still full
+    pub fn strobe(&mut self, port: PpiPort, val: u8) -> bool {
+        if port == PpiPort::C || !self.is_strobed_input(port) {
+            return false;
+        }
+
+        let strobed = &mut self.strobed[port.index()];
+
+        if strobed.input_full {
+            return false;
+        }
+
+        strobed.input = val;
+        strobed.input_full = true;
+        true
+    }
+
+    /// A peripheral taking the byte written to port A or B, if there is one
+    pub fn acknowledge(&mut self, port: PpiPort) -> Option<u8> {
+        if port == PpiPort::C || !self.is_strobed_output(port) {
+            return None;
+        }
+
+        let val = self.latches[port.index()];
+        let strobed = &mut self.strobed[port.index()];
+
+        if !strobed.output_full {
+            return None;
+        }
+
+        strobed.output_full = false;
+        strobed.acknowledged = true;
+        Some(val)
+    }
+
+    /// The INTR line of port A or B
+    pub fn intr(&self, port: PpiPort) -> bool {
+        if port == PpiPort::C {
+            return false;
+        }
+
+        let strobed = &self.strobed[port.index()];
+        let input = self.is_strobed_input(port) && strobed.input_full && self.inte_in(port);
+        let output = self.is_strobed_output(port) && strobed.acknowledged && self.inte_out(port);
+
+        input || output
+    }
+
+    fn mode(&self, port: PpiPort) -> u8 {
+        match port {
+            PpiPort::A => match (self.control & CONTROL_A_MODE) >> 5 {
+                0 => 0,
+                1 => 1,
+                _ => 2,
+            },
+            PpiPort::B => (self.control & CONTROL_B_MODE) >> 2,
+            PpiPort::C => 0,
+        }
+    }
+
+    fn is_input(&self, port: PpiPort) -> bool {
+        match port {
+            PpiPort::A => self.control & CONTROL_A_INPUT != 0,
+            PpiPort::B => self.control & CONTROL_B_INPUT != 0,
+            PpiPort::C => false,
+        }
+    }
+
+    fn is_strobed_input(&self, port: PpiPort) -> bool {
+        match self.mode(port) {
+            1 => self.is_input(port),
+            2 => true,
+            _ => false,
+        }
+    }
+
+    fn is_strobed_output(&self, port: PpiPort) -> bool {
+        match self.mode(port) {
+            1 => !self.is_input(port),
+            2 => true,
+            _ => false,
+        }
+    }
+
+    fn inte_in(&self, port: PpiPort) -> bool {
+        let bit = match port {
+            PpiPort::A => PC_INTE_A_IN,
+            _ => PC_INTE_B,
+        };
+        self.latches[2] & bit != 0
+    }
+
+    fn inte_out(&self, port: PpiPort) -> bool {
+        let bit = match port {
+            PpiPort::A => PC_INTE_A_OUT,
+            _ => PC_INTE_B,
+        };
+        self.latches[2] & bit != 0
+    }
+
+    /// Port C bits used for handshaking rather than I/O
+    fn handshake_mask(&self) -> u8 {
+        let mut mask = 0;
+
+        match self.mode(PpiPort::A) {
+            1 if self.is_input(PpiPort::A) => mask |= PC_INTR_A | PC_INTE_A_IN | PC_IBF_A,
+            1 => mask |= PC_INTR_A | PC_INTE_A_OUT | PC_OBF_A,
+            2 => mask |= 0xf8,
+            _ => (),
+        }
+
+        if self.mode(PpiPort::B) == 1 {
+            mask |= PC_INTR_B | PC_BUF_B | PC_INTE_B;
+        }
+
+        mask
+    }
+
+    fn write_control(&mut self, val: u8) {
+        if val & CONTROL_MODE_SET != 0 {
+            self.control = val;
+            self.latches = [0; 3];
+            self.strobed = [Strobed::default(), Strobed::default()];
+
+            for port in [PpiPort::A, PpiPort::B, PpiPort::C].iter() {
+                self.notify(*port);
+            }
+            return;
+        }
+
+        // Bit set/reset on port C
+        let bit = 1 << ((val >> 1) & 0x07);
+        if val & 0x01 != 0 {
+            self.latches[2] |= bit;
+        } else {
+            self.latches[2] &= !bit;
+        }
+
+        self.notify(PpiPort::C);
+    }
+
+    fn write_port(&mut self, port: PpiPort, val: u8) {
+        let i = port.index();
+
+        if port == PpiPort::C {
+            // Handshake lines can only be changed with bit set/reset
+            let mask = self.handshake_mask();
+            self.latches[i] = (self.latches[i] & mask) | (val & !mask);
+        } else {
+            self.latches[i] = val;
+
+            if self.is_strobed_output(port) {
+                let strobed = &mut self.strobed[i];
+                strobed.output_full = true;
+                strobed.acknowledged = false;
+            }
+        }
+
+        self.notify(port);
+    }
+
+    fn read_port(&mut self, port: PpiPort) {
+        if port != PpiPort::C && self.is_strobed_input(port) {
+            self.strobed[port.index()].input_full = false;
+        }
+    }
+
+    /// Lets the write callback see an output latch
+    fn notify(&mut self, port: PpiPort) {
+        let val = self.latches[port.index()];
+        if let Some(ref mut callback) = self.on_write[port.index()] {
+            callback(val);
+        }
+    }
+
+    fn read_lines(&mut self, port: PpiPort) -> u8 {
+        match self.on_read[port.index()] {
+            Some(ref mut callback) => callback(),
+            None => 0xff,
+        }
+    }
+
+    /// The byte an IN from the port returns now
+    fn value(&mut self, port: PpiPort) -> u8 {
+        match port {
+            PpiPort::C => self.port_c_value(),
+            _ => {
+                let i = port.index();
+
+                if self.is_strobed_input(port) {
+                    self.strobed[i].input
+                } else if self.is_input(port) {
+                    self.read_lines(port)
+                } else {
+                    self.latches[i]
+                }
+            }
+        }
+    }
+
+    fn port_c_value(&mut self) -> u8 {
+        let mut input_mask = 0;
+        if self.control & CONTROL_C_UPPER_INPUT != 0 {
+            input_mask |= 0xf0;
+        }
+        if self.control & CONTROL_C_LOWER_INPUT != 0 {
+            input_mask |= 0x0f;
+        }
+
+        let handshake = self.handshake_mask();
+        input_mask &= !handshake;
+
+        let lines = if input_mask != 0 {
+            self.read_lines(PpiPort::C)
+        } else {
+            0
+        };
+
+        let mut val = (lines & input_mask) | (self.latches[2] & !input_mask & !handshake);
+
+        // Status of the handshake lines, INTE reads back from its latch bit
+        let a = &self.strobed[0];
+        let b = &self.strobed[1];
+        let latched = self.latches[2];
+
+        match self.mode(PpiPort::A) {
+            0 => (),
+            mode => {
+                if self.intr(PpiPort::A) {
+                    val |= PC_INTR_A;
+                }
+
+                let input = mode == 2 || self.is_input(PpiPort::A);
+                let output = mode == 2 || !self.is_input(PpiPort::A);
+
+                if input {
+                    val |= latched & PC_INTE_A_IN;
+                    if a.input_full {
+                        val |= PC_IBF_A;
+                    }
+                }
+                if output {
+                    val |= latched & PC_INTE_A_OUT;
+                    if !a.output_full {
+                        val |= PC_OBF_A;
+                    }
+                }
+            }
+        }
+
+        if self.mode(PpiPort::B) == 1 {
+            if self.intr(PpiPort::B) {
+                val |= PC_INTR_B;
+            }
+            val |= latched & PC_INTE_B;
+
+            let buffer = if self.is_input(PpiPort::B) {
+                b.input_full
+            } else {
+                !b.output_full
+            };
+            if buffer {
+                val |= PC_BUF_B;
+            }
+        }
+
+        val
+    }
+}
+
+impl PortDevice for Ppi8255 {
+    fn update(&mut self, cpu: &mut CPU, _cycles: u64) {
+        if let (v, true) = cpu.get_out_port(self.base_port + 3) {
+            self.write_control(v);
+        }
+
+        let ports = [PpiPort::A, PpiPort::B, PpiPort::C];
+
+        for port in ports.iter() {
+            let cpu_port = self.base_port + port.index();
+
+            if cpu.was_in_port_read(cpu_port) {
+                self.read_port(*port);
+            }
+
+            if let (v, true) = cpu.get_out_port(cpu_port) {
+                self.write_port(*port, v);
+            }
+        }
+
+        for port in ports.iter() {
+            let val = self.value(*port);
+            cpu.set_in_port(self.base_port + port.index(), val);
+        }
+
+        for port in [PpiPort::A, PpiPort::B].iter() {
+            if let Some(interrupt) = self.interrupts[port.index()] {
+                cpu.signal(interrupt, self.intr(*port));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn out(ppi: &mut Ppi8255, cpu: &mut CPU, port: usize, val: u8) {
+        cpu.set_out_port(port, val);
+        ppi.update(cpu, 0);
+    }
+
+    /// An IN as the CPU would do it, after the host changed things
+    fn read(ppi: &mut Ppi8255, cpu: &mut CPU, port: usize) -> u8 {
+        ppi.update(cpu, 0);
+        cpu.set_memory(0, &[0xdb, port as u8]);
+        cpu.set_pc(0);
+        cpu.tick();
+        ppi.update(cpu, 0);
+        cpu.a()
+    }
+
+    #[test]
+    fn test_keyboard_matrix() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0);
+
+        // Column select on port C, the key at column 2 row 5 is held down
+        let column = Rc::new(Cell::new(0xffu8));
+        let write_column = column.clone();
+        ppi.on_write(PpiPort::C, Box::new(move |v| write_column.set(v)));
+        ppi.on_read(
+            PpiPort::A,
+            Box::new(move || if column.get() == !0x04 { !0x20 } else { 0xff }),
+        );
+
+        out(&mut ppi, &mut cpu, 3, 0x90); // A input, B and C output, mode 0
+
+        out(&mut ppi, &mut cpu, 2, !0x01);
+        assert_eq!(read(&mut ppi, &mut cpu, 0), 0xff);
+
+        out(&mut ppi, &mut cpu, 2, !0x04);
+        assert_eq!(read(&mut ppi, &mut cpu, 0), !0x20);
+    }
+
+    #[test]
+    fn test_bit_set_reset() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0x10);
+
+        let leds = Rc::new(Cell::new(0u8));
+        let write_leds = leds.clone();
+        ppi.on_write(PpiPort::C, Box::new(move |v| write_leds.set(v)));
+
+        out(&mut ppi, &mut cpu, 0x13, 0x80);
+        out(&mut ppi, &mut cpu, 0x13, 0x0f); // set PC7
+        out(&mut ppi, &mut cpu, 0x13, 0x03); // set PC1
+        out(&mut ppi, &mut cpu, 0x13, 0x0e); // reset PC7
+
+        assert_eq!(leds.get(), 0x02);
+        assert_eq!(read(&mut ppi, &mut cpu, 0x12), 0x02);
+    }
+
+    #[test]
+    fn test_mode_1_input() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0);
+
+        out(&mut ppi, &mut cpu, 3, 0xb0); // A mode 1 input
+        out(&mut ppi, &mut cpu, 3, 0x09); // INTE A
+
+        assert!(ppi.strobe(PpiPort::A, 0x42));
+        assert!(!ppi.strobe(PpiPort::A, 0x43));
+        assert!(ppi.intr(PpiPort::A));
+
+        let status = read(&mut ppi, &mut cpu, 2);
+        assert_eq!(status & (PC_IBF_A | PC_INTR_A), PC_IBF_A | PC_INTR_A);
+
+        assert_eq!(read(&mut ppi, &mut cpu, 0), 0x42);
+        assert!(!ppi.intr(PpiPort::A));
+        assert_eq!(read(&mut ppi, &mut cpu, 2) & PC_IBF_A, 0);
+    }
+
+    #[test]
+    fn test_mode_1_output() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0);
+
+        out(&mut ppi, &mut cpu, 3, 0x84); // B mode 1 output
+        out(&mut ppi, &mut cpu, 3, 0x05); // INTE B
+
+        assert_eq!(ppi.acknowledge(PpiPort::B), None);
+        assert_eq!(read(&mut ppi, &mut cpu, 2) & PC_BUF_B, PC_BUF_B);
+
+        out(&mut ppi, &mut cpu, 1, 0x99);
+        assert_eq!(read(&mut ppi, &mut cpu, 2) & PC_BUF_B, 0);
+        assert!(!ppi.intr(PpiPort::B));
+
+        assert_eq!(ppi.acknowledge(PpiPort::B), Some(0x99));
+        assert!(ppi.intr(PpiPort::B));
+        assert_eq!(read(&mut ppi, &mut cpu, 2) & PC_BUF_B, PC_BUF_B);
+    }
+
+    #[test]
+    fn test_mode_2() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0);
+
+        out(&mut ppi, &mut cpu, 3, 0xc0);
+        out(&mut ppi, &mut cpu, 0, 0x11);
+        assert!(ppi.strobe(PpiPort::A, 0x22));
+
+        let status = read(&mut ppi, &mut cpu, 2);
+        assert_eq!(status & (PC_IBF_A | PC_OBF_A), PC_IBF_A);
+
+        assert_eq!(ppi.acknowledge(PpiPort::A), Some(0x11));
+        assert_eq!(read(&mut ppi, &mut cpu, 0), 0x22);
+
+        // Interrupts stay masked until INTE is set
+        assert!(!ppi.intr(PpiPort::A));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut ppi = Ppi8255::new(0);
+        ppi.set_interrupt(PpiPort::A, Some(Interrupt::Rst(5)));
+
+        out(&mut ppi, &mut cpu, 3, 0xb0);
+        out(&mut ppi, &mut cpu, 3, 0x09);
+
+        #[rustfmt::skip]
+        cpu.set_memory(0x100, &[
+            0x31, 0x00, 0x02,   // LXI SP, 0x200
+            0xfb,               // EI
+            0xc3, 0x04, 0x01,   // JMP $
+        ]);
+        cpu.set_memory(0x28, &[0xdb, 0x00, 0x76]); // IN 0, HLT
+        cpu.set_pc(0x100);
+
+        for _ in 0..5 {
+            cpu.tick();
+            ppi.update(&mut cpu, 0);
+        }
+        ppi.strobe(PpiPort::A, 0x37);
+        for _ in 0..5 {
+            cpu.tick();
+            ppi.update(&mut cpu, 0);
+        }
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.a(), 0x37);
+    }
+}
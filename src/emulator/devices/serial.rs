@@ -0,0 +1,429 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::fs::File;
+
+const TELNET_IAC: u8 = 255;
+const TELNET_SB: u8 = 250;
+const TELNET_SE: u8 = 240;
+const TELNET_WILL: u8 = 251;
+const TELNET_DONT: u8 = 254;
+const TELNET_ECHO: u8 = 1;
+const TELNET_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Host side of a serial line. Neither call may block.
+pub trait SerialBackend {
+    fn read(&mut self) -> Option<u8>;
+    fn write(&mut self, val: u8);
+}
+
+/// Opens a backend by name: `stdio`, `pty` or `tcp:<port>`
+pub fn open_backend(spec: &str) -> Result<Box<dyn SerialBackend>, String> {
+    if spec == "stdio" {
+        return Ok(Box::new(StdioBackend::new()));
+    }
+
+    if spec == "pty" {
+        return PtyBackend::open().map(|b| Box::new(b) as Box<dyn SerialBackend>);
+    }
+
+    if let Some(port) = spec.strip_prefix("tcp:") {
+        let port = port
+            .parse()
+            .map_err(|_| format!("Invalid TCP port in {}", spec))?;
+        return TcpBackend::listen(port).map(|b| Box::new(b) as Box<dyn SerialBackend>);
+    }
+
+    Err(format!("Unknown serial backend {}", spec))
+}
+
+/// The terminal e8080 runs in, with newlines turned into carriage returns
+pub struct StdioBackend {
+    input: mpsc::Receiver<u8>,
+}
+
+impl StdioBackend {
+    pub fn new() -> StdioBackend {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                let sent = match byte {
+                    Ok(b'\n') => tx.send(b'\r'),
+                    Ok(b) => tx.send(b),
+                    Err(_) => break,
+                };
+
+                if sent.is_err() {
+                    break;
+                }
+            }
+        });
+
+        StdioBackend { input: rx }
+    }
+}
+
+impl Default for StdioBackend {
+    fn default() -> StdioBackend {
+        StdioBackend::new()
+    }
+}
+
+impl SerialBackend for StdioBackend {
+    fn read(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn write(&mut self, val: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[val & 0x7f]);
+        let _ = stdout.flush();
+    }
+}
+
+// Where the client's byte stream is within telnet commands
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Telnet {
+    Data,
+    // A carriage return, whose NUL or LF the telnet client added
+    Cr,
+    Iac,
+    // The option byte of WILL, WONT, DO or DONT
+    Option,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// A telnet-friendly listener on localhost, one client at a time.
+///
+/// Clients that don't send telnet commands, like netcat, get a clean 8 bit
+/// line for binary transfers.
+pub struct TcpBackend {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    telnet: Telnet,
+    // Whether the client has sent a telnet command
+    is_telnet: bool,
+}
+
+impl TcpBackend {
+    pub fn listen(port: u16) -> Result<TcpBackend, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .and_then(|l| l.set_nonblocking(true).map(|_| l))
+            .map_err(|e| format!("Could not listen on port {}: {}", port, e))?;
+
+        Ok(TcpBackend {
+            listener,
+            client: None,
+            telnet: Telnet::Data,
+            is_telnet: false,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+
+    fn accept(&mut self) {
+        if let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                return;
+            }
+
+            // Character at a time, the machine does the echoing
+            let mut stream = stream;
+            let _ = stream.write_all(&[
+                TELNET_IAC,
+                TELNET_WILL,
+                TELNET_ECHO,
+                TELNET_IAC,
+                TELNET_WILL,
+                TELNET_SUPPRESS_GO_AHEAD,
+            ]);
+
+            self.client = Some(stream);
+            self.telnet = Telnet::Data;
+            self.is_telnet = false;
+        }
+    }
+
+    /// Drops telnet commands and the NUL or LF a telnet client sends after a
+    /// carriage return, and unescapes IAC IAC to 0xff
+    fn filter(&mut self, val: u8) -> Option<u8> {
+        let (next, out) = match (self.telnet, val) {
+            (Telnet::Data, TELNET_IAC) | (Telnet::Cr, TELNET_IAC) => {
+                self.is_telnet = true;
+                (Telnet::Iac, None)
+            }
+            (Telnet::Cr, 0) | (Telnet::Cr, b'\n') if self.is_telnet => (Telnet::Data, None),
+            (Telnet::Data, b'\r') | (Telnet::Cr, b'\r') => (Telnet::Cr, Some(val)),
+            (Telnet::Data, _) | (Telnet::Cr, _) => (Telnet::Data, Some(val)),
+            (Telnet::Iac, TELNET_IAC) => (Telnet::Data, Some(val)),
+            (Telnet::Iac, TELNET_SB) => (Telnet::Subnegotiation, None),
+            (Telnet::Iac, TELNET_WILL..=TELNET_DONT) => (Telnet::Option, None),
+            (Telnet::Iac, _) | (Telnet::Option, _) => (Telnet::Data, None),
+            (Telnet::Subnegotiation, TELNET_IAC) => (Telnet::SubnegotiationIac, None),
+            (Telnet::Subnegotiation, _) => (Telnet::Subnegotiation, None),
+            (Telnet::SubnegotiationIac, TELNET_SE) => (Telnet::Data, None),
+            (Telnet::SubnegotiationIac, _) => (Telnet::Subnegotiation, None),
+        };
+
+        self.telnet = next;
+        out
+    }
+}
+
+impl SerialBackend for TcpBackend {
+    fn read(&mut self) -> Option<u8> {
+        if self.client.is_none() {
+            self.accept();
+        }
+
+        let mut buf = [0u8];
+        loop {
+            let result = match self.client {
+                Some(ref mut client) => client.read(&mut buf),
+                None => return None,
+            };
+
+            match result {
+                Ok(1) => {
+                    if let Some(val) = self.filter(buf[0]) {
+                        return Some(val);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                _ => {
+                    self.client = None;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        let failed = match self.client {
+            Some(ref mut client) => client.write_all(&[val]).is_err(),
+            None => false,
+        };
+
+        if failed {
+            self.client = None;
+        }
+    }
+}
+
+/// A pseudo-terminal, for attaching screen, minicom or a terminal emulator
+#[cfg(unix)]
+pub struct PtyBackend {
+    master: File,
+    path: String,
+}
+
+#[cfg(unix)]
+impl PtyBackend {
+    pub fn open() -> Result<PtyBackend, String> {
+        use std::ffi::CStr;
+        use std::mem;
+        use std::os::unix::io::FromRawFd;
+
+        let error = |what: &str| format!("Could not {}: {}", what, io::Error::last_os_error());
+
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(error("open a pseudo-terminal"));
+            }
+
+            let master = File::from_raw_fd(fd);
+
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(error("unlock the pseudo-terminal"));
+            }
+
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(error("name the pseudo-terminal"));
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) != 0 {
+                return Err(error("make the pseudo-terminal non-blocking"));
+            }
+
+            Ok(PtyBackend { master, path })
+        }
+    }
+
+    /// The slave device to point a terminal program at
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl SerialBackend for PtyBackend {
+    fn read(&mut self) -> Option<u8> {
+        let mut buf = [0u8];
+        match self.master.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        let _ = self.master.write_all(&[val]);
+    }
+}
+
+#[cfg(not(unix))]
+pub struct PtyBackend;
+
+#[cfg(not(unix))]
+impl PtyBackend {
+    pub fn open() -> Result<PtyBackend, String> {
+        Err("Pseudo-terminals are only supported on Unix".to_string())
+    }
+
+    pub fn path(&self) -> &str {
+        ""
+    }
+}
+
+#[cfg(not(unix))]
+impl SerialBackend for PtyBackend {
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, _val: u8) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time;
+
+    #[test]
+    fn test_tcp() {
+        let mut backend = TcpBackend::listen(0).unwrap();
+        let mut client = TcpStream::connect(("127.0.0.1", backend.port())).unwrap();
+        client
+            .write_all(&[
+                TELNET_IAC,
+                TELNET_DONT,
+                TELNET_ECHO,
+                b'A',
+                b'\r',
+                b'\n',
+                b'B',
+            ])
+            .unwrap();
+
+        let mut input = Vec::new();
+        for _ in 0..100 {
+            if let Some(b) = backend.read() {
+                input.push(b);
+            }
+            if input.len() == 3 {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        backend.write(b'!');
+        let mut reply = [0u8; 7];
+        client.read_exact(&mut reply).unwrap();
+
+        assert_eq!(input, b"A\rB");
+        assert_eq!(reply[0], TELNET_IAC);
+        assert_eq!(reply[6], b'!');
+    }
+
+    #[test]
+    fn test_telnet_filter() {
+        let mut backend = TcpBackend::listen(0).unwrap();
+        let input = [
+            TELNET_IAC,
+            TELNET_DONT,
+            TELNET_ECHO,
+            b'x',
+            TELNET_IAC,
+            241,
+            b'y',
+        ];
+
+        let filtered: Vec<u8> = input.iter().filter_map(|b| backend.filter(*b)).collect();
+
+        assert_eq!(filtered, b"xy");
+    }
+
+    #[test]
+    fn test_telnet_escaped_iac() {
+        let mut backend = TcpBackend::listen(0).unwrap();
+        let input = [b'x', TELNET_IAC, TELNET_IAC, b'y'];
+
+        let filtered: Vec<u8> = input.iter().filter_map(|b| backend.filter(*b)).collect();
+
+        assert_eq!(filtered, [b'x', 0xff, b'y']);
+    }
+
+    #[test]
+    fn test_telnet_subnegotiation() {
+        let mut backend = TcpBackend::listen(0).unwrap();
+        // Terminal type "XT", with an escaped IAC in the payload
+        let input = [
+            TELNET_IAC, TELNET_SB, 24, 0, b'X', TELNET_IAC, TELNET_IAC, b'T', TELNET_IAC,
+            TELNET_SE, b'z',
+        ];
+
+        let filtered: Vec<u8> = input.iter().filter_map(|b| backend.filter(*b)).collect();
+
+        assert_eq!(filtered, b"z");
+    }
+
+    #[test]
+    fn test_telnet_carriage_return() {
+        let mut backend = TcpBackend::listen(0).unwrap();
+        let input = [
+            TELNET_IAC,
+            TELNET_DONT,
+            TELNET_ECHO,
+            b'\r',
+            0,
+            b'\r',
+            b'\n',
+            b'\n',
+            0,
+        ];
+
+        let filtered: Vec<u8> = input.iter().filter_map(|b| backend.filter(*b)).collect();
+
+        assert_eq!(filtered, [b'\r', b'\r', b'\n', 0]);
+    }
+
+    #[test]
+    fn test_raw_binary() {
+        // A client that never speaks telnet gets every byte, as XMODEM needs
+        let mut backend = TcpBackend::listen(0).unwrap();
+        let input = [0x01, b'\r', 0, b'\r', b'\n', 0xfe];
+
+        let filtered: Vec<u8> = input.iter().filter_map(|b| backend.filter(*b)).collect();
+
+        assert_eq!(filtered, input);
+    }
+}
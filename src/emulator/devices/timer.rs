@@ -0,0 +1,700 @@
+use emulator::cpu::*;
+
+pub const COUNTERS: usize = 3;
+
+const CONTROL_SELECT: u8 = 0xc0;
+const CONTROL_READ_BACK: u8 = 0xc0;
+const CONTROL_ACCESS: u8 = 0x30;
+const CONTROL_MODE: u8 = 0x0e;
+const CONTROL_BCD: u8 = 0x01;
+
+const READ_BACK_NO_COUNT: u8 = 0x20;
+const READ_BACK_NO_STATUS: u8 = 0x10;
+
+const ACCESS_LATCH: u8 = 0;
+const ACCESS_LSB: u8 = 1;
+const ACCESS_MSB: u8 = 2;
+const ACCESS_WORD: u8 = 3;
+
+struct Counter {
+    mode: u8,
+    access: u8,
+    bcd: bool,
+
+    count: u16,
+    reload: u16,
+    // Written count waiting for the next clock, or for a gate trigger in modes 1 and 5
+    pending: Option<u16>,
+    write_lsb: Option<u8>,
+    armed: bool,
+    running: bool,
+
+    read_msb: bool,
+    latched_count: Option<u16>,
+    latched_status: Option<u8>,
+
+    gate: bool,
+    gate_rose: bool,
+    out: bool,
+    strobe: bool,
+}
+
+impl Counter {
+    fn new() -> Counter {
+        Counter {
+            mode: 0,
+            access: ACCESS_WORD,
+            bcd: false,
+            count: 0,
+            reload: 0,
+            pending: None,
+            write_lsb: None,
+            armed: false,
+            running: false,
+            read_msb: false,
+            latched_count: None,
+            latched_status: None,
+            gate: true,
+            gate_rose: false,
+            out: false,
+            strobe: false,
+        }
+    }
+
+    fn program(&mut self, control: u8) {
+        let mode = (control & CONTROL_MODE) >> 1;
+
+        self.mode = if mode > 5 { mode - 4 } else { mode };
+        self.access = (control & CONTROL_ACCESS) >> 4;
+        self.bcd = control & CONTROL_BCD != 0;
+        self.pending = None;
+        self.write_lsb = None;
+        self.armed = false;
+        self.running = false;
+        self.read_msb = false;
+        self.latched_count = None;
+        self.out = self.mode != 0;
+    }
+
+    fn write(&mut self, val: u8) {
+        let count = match self.access {
+            ACCESS_LSB => val as u16,
+            ACCESS_MSB => (val as u16) << 8,
+            _ => match self.write_lsb.take() {
+                Some(lsb) => lsb as u16 | ((val as u16) << 8),
+                None => {
+                    self.write_lsb = Some(val);
+                    if self.mode == 0 {
+                        self.out = false;
+                        self.running = false;
+                    }
+                    return;
+                }
+            },
+        };
+
+        self.reload = count;
+
+        match self.mode {
+            0 => {
+                self.out = false;
+                self.pending = Some(count);
+            }
+            4 => self.pending = Some(count),
+            // Take effect at the end of the current period
+            2 | 3 if self.running => (),
+            2 | 3 => self.pending = Some(count),
+            _ => self.armed = true,
+        }
+    }
+
+    fn latch_count(&mut self) {
+        if self.latched_count.is_none() {
+            self.latched_count = Some(self.count);
+        }
+    }
+
+    fn latch_status(&mut self) {
+        if self.latched_status.is_none() {
+            let mut status = (self.access << 4) | (self.mode << 1);
+            if self.out {
+                status |= 0x80;
+            }
+            if self.pending.is_some() || self.write_lsb.is_some() {
+                status |= 0x40; // null count
+            }
+            if self.bcd {
+                status |= 0x01;
+            }
+            self.latched_status = Some(status);
+        }
+    }
+
+    /// The byte an IN would return now
+    fn peek(&self) -> u8 {
+        if let Some(status) = self.latched_status {
+            return status;
+        }
+
+        let count = self.latched_count.unwrap_or(self.count);
+        let msb = match self.access {
+            ACCESS_MSB => true,
+            ACCESS_WORD => self.read_msb,
+            _ => false,
+        };
+
+        if msb {
+            (count >> 8) as u8
+        } else {
+            count as u8
+        }
+    }
+
+    fn read(&mut self) {
+        if self.latched_status.take().is_some() {
+            return;
+        }
+
+        if self.access == ACCESS_WORD && !self.read_msb {
+            self.read_msb = true;
+        } else {
+            self.read_msb = false;
+            self.latched_count = None;
+        }
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate {
+            self.gate_rose = true;
+        }
+
+        self.gate = gate;
+
+        // A low gate forces the output high right away in modes 2 and 3
+        if !gate && (self.mode == 2 || self.mode == 3) {
+            self.out = true;
+        }
+    }
+
+    fn decrement(&mut self, by: u16) {
+        if !self.bcd {
+            self.count = self.count.wrapping_sub(by);
+            return;
+        }
+
+        let mut digits = bcd_to_binary(self.count);
+        digits = (digits + 10000 - by as u32) % 10000;
+        self.count = binary_to_bcd(digits);
+    }
+
+    fn load(&mut self, count: u16) {
+        self.count = count;
+        self.running = true;
+    }
+
+    /// One clock pulse
+    fn clock(&mut self) {
+        let gate_rose = self.gate_rose;
+        self.gate_rose = false;
+
+        if self.strobe {
+            self.strobe = false;
+            self.out = true;
+        }
+
+        match self.mode {
+            0 | 4 => {
+                if let Some(count) = self.pending.take() {
+                    self.load(count);
+                    return;
+                }
+
+                if !self.running || !self.gate {
+                    return;
+                }
+
+                self.decrement(1);
+                if self.count == 0 {
+                    if self.mode == 0 {
+                        self.out = true;
+                    } else {
+                        self.out = false;
+                        self.strobe = true;
+                    }
+                }
+            }
+            1 | 5 => {
+                if gate_rose && self.armed {
+                    let reload = self.reload;
+                    self.load(reload);
+                    if self.mode == 1 {
+                        self.out = false;
+                    }
+                    return;
+                }
+
+                if !self.running {
+                    return;
+                }
+
+                self.decrement(1);
+                if self.count == 0 {
+                    if self.mode == 1 {
+                        self.out = true;
+                    } else {
+                        self.out = false;
+                        self.strobe = true;
+                    }
+                }
+            }
+            2 => {
+                if let Some(count) = self.pending.take() {
+                    self.load(count);
+                    return;
+                }
+
+                if gate_rose && self.running {
+                    let reload = self.reload;
+                    self.load(reload);
+                    return;
+                }
+
+                if !self.running || !self.gate {
+                    return;
+                }
+
+                self.decrement(1);
+                if self.count == 1 {
+                    self.out = false;
+                    self.strobe = true;
+                } else if self.count == 0 {
+                    let reload = self.reload;
+                    self.load(reload);
+                }
+            }
+            _ => {
+                if let Some(count) = self.pending.take() {
+                    self.out = true;
+                    self.load_half_period(count);
+                    return;
+                }
+
+                if gate_rose && self.running {
+                    self.out = true;
+                    let reload = self.reload;
+                    self.load_half_period(reload);
+                    return;
+                }
+
+                if !self.running || !self.gate {
+                    return;
+                }
+
+                self.decrement(2);
+                if self.count == 0 {
+                    self.out = !self.out;
+                    let reload = self.reload;
+                    self.load_half_period(reload);
+                }
+            }
+        }
+    }
+
+    /// Clocks until OUT may change, never more than it takes. None if it
+    /// stays as it is until the CPU or the gate does something.
+    fn clocks_to_out_change(&self) -> Option<u64> {
+        if self.strobe || self.pending.is_some() || self.gate_rose {
+            return Some(1);
+        }
+
+        let gated = self.mode != 1 && self.mode != 5;
+        if !self.running || (gated && !self.gate) {
+            return None;
+        }
+
+        // Clocks until the count gets to zero
+        let to_zero = match (self.bcd, self.count) {
+            (false, 0) => 0x10000,
+            (false, count) => count as u64,
+            (true, count) => match bcd_to_binary(count) {
+                0 => 10000,
+                digits => digits as u64,
+            },
+        };
+
+        match self.mode {
+            0 | 1 if self.out => None,
+            2 if to_zero > 1 => Some(to_zero - 1),
+            2 => Some(1),
+            3 => Some(to_zero.div_ceil(2)),
+            _ => Some(to_zero),
+        }
+    }
+
+    /// Odd counts spend one clock longer high than low
+    fn load_half_period(&mut self, count: u16) {
+        let odd = count & 1 != 0;
+        let count = match (odd, self.out) {
+            (true, true) => count.wrapping_add(1),
+            (true, false) => count.wrapping_sub(1),
+            _ => count,
+        };
+
+        self.load(count);
+    }
+}
+
+fn bcd_to_binary(val: u16) -> u32 {
+    let mut result = 0;
+    for shift in [12, 8, 4, 0].iter() {
+        result = result * 10 + ((val >> shift) & 0x0f) as u32;
+    }
+    result
+}
+
+fn binary_to_bcd(val: u32) -> u16 {
+    let mut result = 0;
+    let mut val = val;
+    for shift in [0, 4, 8, 12].iter() {
+        result |= ((val % 10) as u16) << shift;
+        val /= 10;
+    }
+    result
+}
+
+/// An Intel 8253/8254 programmable interval timer on four consecutive ports:
+/// the three counters followed by the control word.
+///
+/// The counters are clocked every `cycles_per_clock` CPU cycles. A counter
+/// wired to an RST raises it when its output goes high, as soon as the CPU
+/// has interrupts enabled; one wired to a controller line drives it with OUT.
+pub struct Timer8253 {
+    base_port: usize,
+    cycles_per_clock: u64,
+
+    counters: [Counter; COUNTERS],
+    interrupts: [Option<Interrupt>; COUNTERS],
+    pending_interrupts: [bool; COUNTERS],
+
+    last_cycles: Option<u64>,
+    leftover: u64,
+}
+
+impl Timer8253 {
+    pub fn new(base_port: usize, cycles_per_clock: u64) -> Timer8253 {
+        Timer8253 {
+            base_port,
+            cycles_per_clock: cycles_per_clock.max(1),
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+            interrupts: [None; COUNTERS],
+            pending_interrupts: [false; COUNTERS],
+            last_cycles: None,
+            leftover: 0,
+        }
+    }
+
+    /// The GATE input of a counter, high unless the host pulls it low
+    pub fn set_gate(&mut self, counter: usize, gate: bool) {
+        self.counters[counter].set_gate(gate);
+    }
+
+    /// The OUT pin of a counter
+    pub fn out(&self, counter: usize) -> bool {
+        self.counters[counter].out
+    }
+
+    pub fn count(&self, counter: usize) -> u16 {
+        self.counters[counter].count
+    }
+
+    /// Where a counter's OUT goes, None to leave it unconnected
+    pub fn set_interrupt(&mut self, counter: usize, interrupt: Option<Interrupt>) {
+        self.interrupts[counter] = interrupt;
+        self.pending_interrupts[counter] = false;
+    }
+
+    /// Clocks every counter once
+    pub fn clock(&mut self) {
+        for (i, counter) in self.counters.iter_mut().enumerate() {
+            let was_out = counter.out;
+            counter.clock();
+
+            if counter.out && !was_out && self.interrupts[i].is_some() {
+                self.pending_interrupts[i] = true;
+            }
+        }
+    }
+
+    fn write_control(&mut self, val: u8) {
+        if val & CONTROL_SELECT != CONTROL_READ_BACK {
+            let counter = &mut self.counters[(val >> 6) as usize];
+
+            if (val & CONTROL_ACCESS) >> 4 == ACCESS_LATCH {
+                counter.latch_count();
+            } else {
+                counter.program(val);
+            }
+            return;
+        }
+
+        for (i, counter) in self.counters.iter_mut().enumerate() {
+            if val & (0x02 << i) == 0 {
+                continue;
+            }
+
+            if val & READ_BACK_NO_COUNT == 0 {
+                counter.latch_count();
+            }
+            if val & READ_BACK_NO_STATUS == 0 {
+                counter.latch_status();
+            }
+        }
+    }
+}
+
+impl PortDevice for Timer8253 {
+    /// When the OUT of a counter wired to an interrupt next changes, or
+    /// straight away while an RST waits for the CPU to enable interrupts
+    fn next_event(&self) -> Option<u64> {
+        let last = match self.last_cycles {
+            Some(last) => last,
+            None => return Some(0),
+        };
+
+        if self.pending_interrupts.iter().any(|&pending| pending) {
+            return Some(last);
+        }
+
+        (0..COUNTERS)
+            .filter(|&i| self.interrupts[i].is_some())
+            .filter_map(|i| self.counters[i].clocks_to_out_change())
+            .min()
+            .map(|clocks| last + clocks * self.cycles_per_clock - self.leftover)
+    }
+
+    fn update(&mut self, cpu: &mut CPU, cycles: u64) {
+        if let (v, true) = cpu.get_out_port(self.base_port + COUNTERS) {
+            self.write_control(v);
+        }
+
+        for i in 0..COUNTERS {
+            let port = self.base_port + i;
+
+            if cpu.was_in_port_read(port) {
+                self.counters[i].read();
+            }
+
+            if let (v, true) = cpu.get_out_port(port) {
+                self.counters[i].write(v);
+            }
+        }
+
+        let last = *self.last_cycles.get_or_insert(cycles);
+        self.leftover += cycles - last;
+        self.last_cycles = Some(cycles);
+
+        while self.leftover >= self.cycles_per_clock {
+            self.leftover -= self.cycles_per_clock;
+            self.clock();
+        }
+
+        for i in 0..COUNTERS {
+            cpu.set_in_port(self.base_port + i, self.counters[i].peek());
+
+            match self.interrupts[i] {
+                Some(Interrupt::Rst(n))
+                    if self.pending_interrupts[i] && cpu.interrupts_enabled() =>
+                {
+                    self.pending_interrupts[i] = false;
+                    cpu.interrupt(n);
+                }
+                Some(Interrupt::Line(line)) => cpu.set_irq_line(line, self.counters[i].out),
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn out(timer: &mut Timer8253, cpu: &mut CPU, port: usize, val: u8) {
+        cpu.set_out_port(port, val);
+        timer.update(cpu, 0);
+    }
+
+    /// Programs counter 0 with a 16 bit count and returns OUT for each of `clocks` clocks
+    fn run_mode(mode: u8, count: u16, clocks: usize) -> Vec<bool> {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0, 1);
+
+        out(&mut timer, &mut cpu, 3, 0x30 | (mode << 1));
+        out(&mut timer, &mut cpu, 0, count as u8);
+        out(&mut timer, &mut cpu, 0, (count >> 8) as u8);
+
+        (0..clocks)
+            .map(|_| {
+                timer.clock();
+                timer.out(0)
+            })
+            .collect()
+    }
+
+    fn pattern(outs: &[bool]) -> String {
+        outs.iter().map(|o| if *o { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn test_mode_0() {
+        assert_eq!(pattern(&run_mode(0, 3, 6)), "000111");
+    }
+
+    #[test]
+    fn test_mode_2() {
+        assert_eq!(pattern(&run_mode(2, 3, 8)), "11011011");
+    }
+
+    #[test]
+    fn test_mode_3() {
+        assert_eq!(pattern(&run_mode(3, 4, 9)), "110011001");
+        assert_eq!(pattern(&run_mode(3, 5, 11)), "11100111001");
+    }
+
+    #[test]
+    fn test_mode_4() {
+        assert_eq!(pattern(&run_mode(4, 2, 5)), "11011");
+    }
+
+    #[test]
+    fn test_gate_triggered_modes() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0, 1);
+
+        out(&mut timer, &mut cpu, 3, 0x52); // counter 1, LSB, mode 1
+        out(&mut timer, &mut cpu, 1, 2);
+        out(&mut timer, &mut cpu, 3, 0x9a); // counter 2, LSB, mode 5
+        out(&mut timer, &mut cpu, 2, 2);
+
+        timer.clock();
+        assert!(timer.out(1));
+
+        timer.set_gate(1, false);
+        timer.set_gate(2, false);
+        timer.set_gate(1, true);
+        timer.set_gate(2, true);
+
+        let mut one_shot = Vec::new();
+        let mut strobe = Vec::new();
+        for _ in 0..5 {
+            timer.clock();
+            one_shot.push(timer.out(1));
+            strobe.push(timer.out(2));
+        }
+
+        assert_eq!(pattern(&one_shot), "00111");
+        assert_eq!(pattern(&strobe), "11011");
+    }
+
+    #[test]
+    fn test_latch_and_read_back() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0x40, 1);
+
+        out(&mut timer, &mut cpu, 0x43, 0x34); // counter 0, word, mode 2
+        out(&mut timer, &mut cpu, 0x40, 0x34);
+        out(&mut timer, &mut cpu, 0x40, 0x12);
+        timer.clock();
+        timer.clock();
+
+        out(&mut timer, &mut cpu, 0x43, 0x00); // latch counter 0
+        timer.clock();
+
+        #[rustfmt::skip]
+        cpu.set_memory(0, &[
+            0xdb, 0x40,         // IN 0x40
+            0x47,               // MOV B, A
+            0xdb, 0x40,         // IN 0x40
+        ]);
+        for _ in 0..3 {
+            cpu.tick();
+            timer.update(&mut cpu, 0);
+        }
+
+        assert_eq!(cpu.bc() >> 8, 0x33);
+        assert_eq!(cpu.a(), 0x12);
+
+        out(&mut timer, &mut cpu, 0x43, 0xe2); // read back status of counter 0
+        assert_eq!(cpu.get_in_port(0x40), 0x80 | 0x34);
+    }
+
+    #[test]
+    fn test_bcd() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0, 1);
+
+        out(&mut timer, &mut cpu, 3, 0x11); // counter 0, LSB, mode 0, BCD
+        out(&mut timer, &mut cpu, 0, 0x10);
+        timer.clock();
+        timer.clock();
+
+        assert_eq!(timer.count(0), 0x09);
+    }
+
+    #[test]
+    fn test_next_event() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0, 2);
+        assert_eq!(timer.next_event(), Some(0));
+
+        timer.update(&mut cpu, 0);
+        out(&mut timer, &mut cpu, 3, 0x30); // counter 0, word, mode 0
+        out(&mut timer, &mut cpu, 0, 100);
+        out(&mut timer, &mut cpu, 0, 0);
+        assert_eq!(timer.next_event(), None);
+
+        // Loads on the next clock, then counts down to the underflow
+        timer.set_interrupt(0, Some(Interrupt::Rst(1)));
+        assert_eq!(timer.next_event(), Some(2));
+        timer.update(&mut cpu, 3);
+        assert_eq!(timer.next_event(), Some(202));
+
+        // The RST waits for the CPU to enable interrupts
+        timer.update(&mut cpu, 202);
+        assert!(timer.out(0));
+        assert_eq!(timer.next_event(), Some(202));
+    }
+
+    #[test]
+    fn test_interrupt_from_cycles() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut timer = Timer8253::new(0, 2);
+        timer.set_interrupt(0, Some(Interrupt::Rst(1)));
+
+        #[rustfmt::skip]
+        cpu.set_memory(0x100, &[
+            0x31, 0x00, 0x02,   // LXI SP, 0x200
+            0x3e, 0x30,         // MVI A, 0x30 - counter 0, word, mode 0
+            0xd3, 0x03,         // OUT 3
+            0x3e, 0x64,         // MVI A, 100
+            0xd3, 0x00,         // OUT 0
+            0xaf,               // XRA A
+            0xd3, 0x00,         // OUT 0
+            0xfb,               // EI
+            0xc3, 0x0f, 0x01,   // JMP $
+        ]);
+        cpu.set_memory(0x08, &[0x76]); // HLT
+        cpu.set_pc(0x100);
+
+        let mut cycles = 0;
+        while !cpu.is_halted() && cycles < 1000 {
+            cycles += cpu.tick() as u64;
+            timer.update(&mut cpu, cycles);
+        }
+
+        // About 60 cycles of setup, then 100 timer clocks at two CPU cycles each
+        assert!(cpu.is_halted());
+        assert!(cycles > 250 && cycles < 280);
+    }
+}
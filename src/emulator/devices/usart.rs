@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+
+use emulator::cpu::*;
+use emulator::devices::serial::SerialBackend;
+
+pub const STATUS_TXRDY: u8 = 0x01;
+pub const STATUS_RXRDY: u8 = 0x02;
+pub const STATUS_TXEMPTY: u8 = 0x04;
+pub const STATUS_DSR: u8 = 0x80;
+
+const COMMAND_TXEN: u8 = 0x01;
+const COMMAND_RXE: u8 = 0x04;
+const COMMAND_INTERNAL_RESET: u8 = 0x40;
+
+const MODE_BAUD_FACTOR: u8 = 0x03;
+const MODE_SINGLE_SYNC: u8 = 0x80;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Expect {
+    Mode,
+    Sync(u8),
+    Command,
+}
+
+/// An Intel 8251 USART with the data register on one port and mode,
+/// command and status on another.
+///
+/// Bytes come from and go to the host through an optional backend, or
+/// through `push_input` and `take_output`. The RxRDY pin can be wired to an
+/// RST or to an interrupt controller line.
+pub struct Usart8251 {
+    data_port: usize,
+    control_port: usize,
+
+    expect: Expect,
+    mode: u8,
+    command: u8,
+
+    rx: Option<u8>,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    backend: Option<Box<dyn SerialBackend>>,
+
+    interrupt: Option<Interrupt>,
+}
+
+impl Usart8251 {
+    pub fn new(data_port: usize, control_port: usize) -> Usart8251 {
+        Usart8251 {
+            data_port,
+            control_port,
+            expect: Expect::Mode,
+            mode: 0,
+            command: 0,
+            rx: None,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            backend: None,
+            interrupt: None,
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Box<dyn SerialBackend>) {
+        self.backend = Some(backend);
+    }
+
+    /// Where RxRDY goes, None to leave the pin unconnected
+    pub fn set_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        self.interrupt = interrupt;
+    }
+
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.input.extend(data);
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.split_off(0)
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn command(&self) -> u8 {
+        self.command
+    }
+
+    /// The RxRDY pin, only active while the receiver is enabled
+    pub fn rx_ready(&self) -> bool {
+        self.rx.is_some() && self.command & COMMAND_RXE != 0
+    }
+
+    /// The TxRDY pin, only active while the transmitter is enabled
+    pub fn tx_ready(&self) -> bool {
+        self.command & COMMAND_TXEN != 0
+    }
+
+    pub fn status(&self) -> u8 {
+        // Characters go out instantly, so the transmitter is always empty
+        let mut status = STATUS_TXRDY | STATUS_TXEMPTY | STATUS_DSR;
+
+        if self.rx_ready() {
+            status |= STATUS_RXRDY;
+        }
+
+        status
+    }
+
+    fn write_control(&mut self, val: u8) {
+        match self.expect {
+            Expect::Mode => {
+                self.mode = val;
+                self.expect = if val & MODE_BAUD_FACTOR != 0 {
+                    Expect::Command
+                } else if val & MODE_SINGLE_SYNC != 0 {
+                    Expect::Sync(1)
+                } else {
+                    Expect::Sync(2)
+                };
+            }
+            Expect::Sync(left) => {
+                self.expect = if left > 1 {
+                    Expect::Sync(left - 1)
+                } else {
+                    Expect::Command
+                };
+            }
+            Expect::Command => {
+                if val & COMMAND_INTERNAL_RESET != 0 {
+                    self.expect = Expect::Mode;
+                    self.command = 0;
+                    return;
+                }
+
+                self.command = val;
+            }
+        }
+    }
+
+    fn transmit(&mut self, val: u8) {
+        if self.command & COMMAND_TXEN == 0 {
+            return;
+        }
+
+        match self.backend {
+            Some(ref mut backend) => backend.write(val),
+            None => self.output.push(val),
+        }
+    }
+}
+
+impl PortDevice for Usart8251 {
+    fn update(&mut self, cpu: &mut CPU, _cycles: u64) {
+        if let (v, true) = cpu.get_out_port(self.control_port) {
+            self.write_control(v);
+        }
+
+        if let (v, true) = cpu.get_out_port(self.data_port) {
+            self.transmit(v);
+        }
+
+        if cpu.was_in_port_read(self.data_port) {
+            self.rx = None;
+        }
+
+        if let Some(ref mut backend) = self.backend {
+            if let Some(val) = backend.read() {
+                self.input.push_back(val);
+            }
+        }
+
+        // The host waits for the CPU, so there are no overruns
+        if self.rx.is_none() && self.command & COMMAND_RXE != 0 {
+            self.rx = self.input.pop_front();
+        }
+
+        cpu.set_in_port(self.control_port, self.status());
+        cpu.set_in_port(self.data_port, self.rx.unwrap_or(0));
+
+        if let Some(interrupt) = self.interrupt {
+            cpu.signal(interrupt, self.rx_ready());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn out(usart: &mut Usart8251, cpu: &mut CPU, port: usize, val: u8) {
+        cpu.set_out_port(port, val);
+        usart.update(cpu, 0);
+    }
+
+    #[rustfmt::skip]
+    const ECHO: [u8; 22] = [
+        0x3e, 0x4e,         // MVI A, 0x4e - async, 8N1, x16
+        0xd3, 0x01,         // OUT 1
+        0x3e, 0x05,         // MVI A, 0x05 - TxEN, RxE
+        0xd3, 0x01,         // OUT 1
+        0xdb, 0x01,         // IN 1
+        0xe6, 0x02,         // ANI 0x02
+        0xca, 0x08, 0x00,   // JZ 8
+        0xdb, 0x00,         // IN 0
+        0xd3, 0x00,         // OUT 0
+        0xc3, 0x08, 0x00,   // JMP 8
+    ];
+
+    #[test]
+    fn test_echo() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut usart = Usart8251::new(0, 1);
+        cpu.set_memory(0, &ECHO);
+        usart.push_input(b"ok");
+
+        let mut cycles = 0;
+        while cycles < 2000 {
+            cycles += cpu.tick() as u64;
+            usart.update(&mut cpu, cycles);
+        }
+
+        assert_eq!(usart.take_output(), b"ok");
+    }
+
+    #[test]
+    fn test_mode_and_command() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut usart = Usart8251::new(0, 1);
+
+        out(&mut usart, &mut cpu, 1, 0x4e);
+        out(&mut usart, &mut cpu, 1, 0x37);
+        assert_eq!(usart.mode(), 0x4e);
+        assert_eq!(usart.command(), 0x37);
+
+        out(&mut usart, &mut cpu, 1, COMMAND_INTERNAL_RESET);
+        out(&mut usart, &mut cpu, 1, 0x00); // sync mode, two sync characters
+        out(&mut usart, &mut cpu, 1, 0x16);
+        out(&mut usart, &mut cpu, 1, 0x16);
+        out(&mut usart, &mut cpu, 1, 0x05);
+
+        assert_eq!(usart.mode(), 0x00);
+        assert_eq!(usart.command(), 0x05);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut usart = Usart8251::new(0, 1);
+        usart.push_input(b"ab");
+
+        out(&mut usart, &mut cpu, 1, 0x4e);
+        out(&mut usart, &mut cpu, 1, 0x00);
+        assert_eq!(cpu.get_in_port(1) & STATUS_RXRDY, 0);
+
+        out(&mut usart, &mut cpu, 1, COMMAND_RXE);
+        assert_eq!(cpu.get_in_port(1) & STATUS_RXRDY, STATUS_RXRDY);
+        assert_eq!(cpu.get_in_port(0), b'a');
+
+        // Turning the receiver off hides the byte but keeps it
+        out(&mut usart, &mut cpu, 1, COMMAND_TXEN);
+        assert_eq!(cpu.get_in_port(1) & STATUS_RXRDY, 0);
+        assert_eq!(cpu.get_in_port(1) & STATUS_TXRDY, STATUS_TXRDY);
+    }
+
+    #[test]
+    fn test_rx_interrupt() {
+        let mut cpu = CPU::new(init_decoder());
+        let mut usart = Usart8251::new(0, 1);
+        usart.set_interrupt(Some(Interrupt::Rst(7)));
+
+        #[rustfmt::skip]
+        cpu.set_memory(0x100, &[
+            0x31, 0x00, 0x02,   // LXI SP, 0x200
+            0xfb,               // EI
+            0xc3, 0x04, 0x01,   // JMP $
+        ]);
+        cpu.set_memory(0x38, &[0xdb, 0x00, 0x76]); // IN 0, HLT
+        cpu.set_pc(0x100);
+
+        out(&mut usart, &mut cpu, 1, 0x4e);
+        out(&mut usart, &mut cpu, 1, COMMAND_RXE);
+
+        for _ in 0..10 {
+            cpu.tick();
+            usart.update(&mut cpu, 0);
+        }
+        assert!(!cpu.is_halted());
+
+        usart.push_input(b"x");
+        for _ in 0..10 {
+            cpu.tick();
+            usart.update(&mut cpu, 0);
+        }
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.a(), b'x');
+    }
+}
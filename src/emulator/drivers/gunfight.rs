@@ -0,0 +1,376 @@
+use emulator::cpu::CPU;
+use emulator::drivers::{MachineDriver, Shifter};
+use emulator::input::Input;
+use emulator::sound::SoundBoard;
+use emulator::{MID_SCREEN_LINE, VBLANK_LINE};
+
+const SOUND_PORT: usize = 1;
+const SHIFT_BY_BITS_PORT: usize = 2;
+const SHIFTED_VALUE_PORT: usize = 3;
+const VALUE_TO_SHIFT_PORT: usize = 4;
+
+const DIP_PORT: usize = 2;
+const DIP_MASK: u8 = 0b0011_1111;
+const COIN: u8 = 6;
+const START: u8 = 7;
+
+const IN_PORTS: [u8; 4] = [0, 1, DIP_PORT as u8, SHIFTED_VALUE_PORT as u8];
+const OUT_PORTS: [u8; 3] = [
+    SOUND_PORT as u8,
+    SHIFT_BY_BITS_PORT as u8,
+    VALUE_TO_SHIFT_PORT as u8,
+];
+
+const INPUTS: [Input; 16] = [
+    Input::Coin,
+    Input::Start1,
+    Input::Up1,
+    Input::Down1,
+    Input::Left1,
+    Input::Right1,
+    Input::AimUp1,
+    Input::AimDown1,
+    Input::Fire1,
+    Input::Up2,
+    Input::Down2,
+    Input::Left2,
+    Input::Right2,
+    Input::AimUp2,
+    Input::AimDown2,
+    Input::Fire2,
+];
+
+const KEY_BINDINGS: &str = "
+bind D5 coin
+bind D1 start1
+
+bind W up1
+bind S down1
+bind A left1
+bind D right1
+bind R aim_up1
+bind F aim_down1
+bind Space fire1
+
+bind Up up2
+bind Down down2
+bind Left left2
+bind Right right2
+bind PageUp aim_up2
+bind PageDown aim_down2
+bind Return fire2
+";
+
+const INTERRUPTS: [(u64, u8); 2] = [(MID_SCREEN_LINE, 1), (VBLANK_LINE, 2)];
+
+// Each player's port has the stick and the trigger active low, with the
+// gun's position encoded in between
+const STICK_AND_TRIGGER: u8 = 0b1000_1111;
+const GUN_SHIFT: u8 = 4;
+
+// What the gun lever reads in each of its seven notches, aiming up to down
+const GUN_POSITIONS: [u8; 7] = [0x06, 0x02, 0x00, 0x04, 0x05, 0x01, 0x03];
+const GUN_LEVEL: usize = 3;
+
+const COINAGE: [&str; 7] = ["1/1", "2/1", "3/1", "4/1", "1/2", "1/3", "1/4"];
+const COINAGE_BITS: [u8; 7] = [0x00, 0x01, 0x02, 0x03, 0x04, 0x08, 0x0c];
+const GAME_TIME: [&str; 4] = ["60", "70", "80", "90"];
+const GAME_TIME_BITS: [u8; 4] = [0x00, 0x10, 0x20, 0x30];
+
+const SWITCHES: [(&str, &[&str]); 2] = [("coinage", &COINAGE), ("game_time", &GAME_TIME)];
+const SWITCH_BITS: [&[u8]; 2] = [&COINAGE_BITS, &GAME_TIME_BITS];
+
+/// Midway Gun Fight, two cowboys each with an 8 way stick to walk and a lever
+/// to aim the gun up or down.
+///
+/// Sound goes to OUT 1 but isn't emulated. The `reversed_shift` option reads
+/// the shifter back mirrored.
+pub struct GunFight {
+    shifter: Shifter,
+    // Index into the values of each of `SWITCHES`
+    dips: [usize; 2],
+    // The notch each player's gun lever is in, 0 is aiming highest
+    guns: [usize; 2],
+}
+
+impl GunFight {
+    pub fn new() -> GunFight {
+        GunFight {
+            shifter: Shifter::new(),
+            dips: [0, 1],
+            guns: [GUN_LEVEL; 2],
+        }
+    }
+
+    /// Reads the shifter back mirrored, for boards wired that way
+    pub fn set_reversed_shift(&mut self, reversed: bool) {
+        self.shifter.set_reversed(reversed);
+    }
+
+    fn player_port(input: Input) -> usize {
+        match input {
+            Input::Up2
+            | Input::Down2
+            | Input::Left2
+            | Input::Right2
+            | Input::AimUp2
+            | Input::AimDown2
+            | Input::Fire2 => 1,
+            _ => 0,
+        }
+    }
+
+    fn aim(&mut self, cpu: &mut CPU, player: usize, up: bool) {
+        let gun = self.guns[player];
+        self.guns[player] = if up {
+            gun.saturating_sub(1)
+        } else {
+            (gun + 1).min(GUN_POSITIONS.len() - 1)
+        };
+
+        let port = cpu.get_in_port(player) & STICK_AND_TRIGGER;
+        cpu.set_in_port(player, port | GUN_POSITIONS[self.guns[player]] << GUN_SHIFT);
+    }
+
+    fn update_dip_port(&self, cpu: &mut CPU) {
+        let bits = (0..SWITCHES.len()).fold(0, |bits, i| bits | SWITCH_BITS[i][self.dips[i]]);
+
+        let port = cpu.get_in_port(DIP_PORT) & !DIP_MASK;
+        cpu.set_in_port(DIP_PORT, port | bits);
+    }
+}
+
+impl Default for GunFight {
+    fn default() -> GunFight {
+        GunFight::new()
+    }
+}
+
+impl MachineDriver for GunFight {
+    fn name(&self) -> &'static str {
+        "gunfight"
+    }
+
+    fn power_on(&mut self, cpu: &mut CPU) {
+        for player in 0..2 {
+            let gun = GUN_POSITIONS[self.guns[player]] << GUN_SHIFT;
+            cpu.set_in_port(player, STICK_AND_TRIGGER | gun);
+        }
+
+        cpu.set_in_port(DIP_PORT, 0);
+        self.update_dip_port(cpu);
+    }
+
+    fn update(&mut self, cpu: &mut CPU, _sound: Option<&mut SoundBoard>, _cycles: u64) {
+        let (offset, mut should_update_shift) = cpu.get_out_port(SHIFT_BY_BITS_PORT);
+
+        if let (v, true) = cpu.get_out_port(VALUE_TO_SHIFT_PORT) {
+            self.shifter.write_data(v);
+            should_update_shift = true;
+        }
+
+        if should_update_shift {
+            self.shifter.set_offset(offset);
+            cpu.set_in_port(SHIFTED_VALUE_PORT, self.shifter.result());
+        }
+
+        cpu.get_out_port(SOUND_PORT);
+    }
+
+    fn in_ports(&self) -> &'static [u8] {
+        &IN_PORTS
+    }
+
+    fn out_ports(&self) -> &'static [u8] {
+        &OUT_PORTS
+    }
+
+    fn inputs(&self) -> &'static [Input] {
+        &INPUTS
+    }
+
+    fn set_input(&mut self, cpu: &mut CPU, input: Input, down: bool) {
+        let port = GunFight::player_port(input);
+
+        match input {
+            Input::Coin => cpu.set_in_port_bit(DIP_PORT, COIN, down),
+            Input::Start1 => cpu.set_in_port_bit(DIP_PORT, START, down),
+            Input::Up1 | Input::Up2 => cpu.set_in_port_bit(port, 0, !down),
+            Input::Down1 | Input::Down2 => cpu.set_in_port_bit(port, 1, !down),
+            Input::Left1 | Input::Left2 => cpu.set_in_port_bit(port, 2, !down),
+            Input::Right1 | Input::Right2 => cpu.set_in_port_bit(port, 3, !down),
+            Input::Fire1 | Input::Fire2 => cpu.set_in_port_bit(port, 7, !down),
+            // The lever moves a notch per press and stays there
+            Input::AimUp1 | Input::AimUp2 if down => self.aim(cpu, port, true),
+            Input::AimDown1 | Input::AimDown2 if down => self.aim(cpu, port, false),
+            _ => (),
+        }
+    }
+
+    fn key_bindings(&self) -> &'static str {
+        KEY_BINDINGS
+    }
+
+    fn interrupts(&self) -> &'static [(u64, u8)] {
+        &INTERRUPTS
+    }
+
+    fn is_rotated(&self) -> bool {
+        false
+    }
+
+    fn dip_switches(&self) -> &'static [(&'static str, &'static [&'static str])] {
+        &SWITCHES
+    }
+
+    fn dip(&self, name: &str) -> Option<String> {
+        SWITCHES
+            .iter()
+            .position(|&(n, _)| n == name)
+            .map(|i| SWITCHES[i].1[self.dips[i]].to_string())
+    }
+
+    fn set_dip(&mut self, cpu: &mut CPU, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap().trim();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("Expected name=value: {}", setting))?
+            .trim();
+
+        let i = SWITCHES
+            .iter()
+            .position(|&(n, _)| n == name)
+            .ok_or_else(|| format!("Unknown DIP switch: {}", name))?;
+        let values = SWITCHES[i].1;
+
+        self.dips[i] = values.iter().position(|&v| v == value).ok_or_else(|| {
+            format!(
+                "{} must be one of {}, not {}",
+                name,
+                values.join(", "),
+                value
+            )
+        })?;
+
+        self.update_dip_port(cpu);
+        Ok(())
+    }
+
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        match option {
+            "reversed_shift" => self.set_reversed_shift(true),
+            _ => return Err(format!("gunfight has no option {}", option)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::ArcadeMachine;
+    use opcode_decoder::*;
+
+    fn machine() -> ArcadeMachine {
+        ArcadeMachine::with_driver(init_decoder(), &[], Box::new(GunFight::new()))
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut machine = machine();
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01010101);
+        machine.update_ports();
+        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+        machine.update_ports();
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b10101000);
+
+        let mut gunfight = GunFight::new();
+        gunfight.set_option("reversed_shift").unwrap();
+        assert!(gunfight.set_option("reversed").is_err());
+        let mut machine = ArcadeMachine::with_driver(init_decoder(), &[], Box::new(gunfight));
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
+        machine.update_ports();
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01010101);
+        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+        machine.update_ports();
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b11010101);
+    }
+
+    #[test]
+    fn test_sticks_and_triggers() {
+        let mut machine = machine();
+        let idle = [machine.cpu.get_in_port(0), machine.cpu.get_in_port(1)];
+        assert_eq!(idle[0], 0b1100_1111);
+        assert_eq!(idle[1], idle[0]);
+
+        machine.set_input(Input::Up1, true);
+        machine.set_input(Input::Right1, true);
+        machine.set_input(Input::Fire2, true);
+        machine.set_input(Input::Left2, true);
+        assert_eq!(machine.cpu.get_in_port(0), idle[0] & !0b0000_1001);
+        assert_eq!(machine.cpu.get_in_port(1), idle[1] & !0b1000_0100);
+
+        machine.set_input(Input::Up1, false);
+        machine.set_input(Input::Right1, false);
+        machine.set_input(Input::Fire2, false);
+        machine.set_input(Input::Left2, false);
+        assert_eq!(machine.cpu.get_in_port(0), idle[0]);
+        assert_eq!(machine.cpu.get_in_port(1), idle[1]);
+
+        // Not on this cabinet
+        machine.set_input(Input::Tilt, true);
+        machine.set_input(Input::Start2, true);
+        assert_eq!(machine.cpu.get_in_port(0), idle[0]);
+        assert_eq!(machine.cpu.get_in_port(DIP_PORT) & !DIP_MASK, 0);
+    }
+
+    #[test]
+    fn test_gun_lever() {
+        let mut machine = machine();
+        let gun = |machine: &mut ArcadeMachine, player| {
+            (machine.cpu.get_in_port(player) >> GUN_SHIFT) & 0x07
+        };
+
+        // Held keys move one notch, and stop at the end
+        machine.set_input(Input::AimUp1, true);
+        machine.set_input(Input::AimUp1, false);
+        assert_eq!(gun(&mut machine, 0), GUN_POSITIONS[2]);
+        for _ in 0..5 {
+            machine.set_input(Input::AimUp1, true);
+        }
+        assert_eq!(gun(&mut machine, 0), GUN_POSITIONS[0]);
+
+        machine.set_input(Input::AimDown2, true);
+        assert_eq!(gun(&mut machine, 1), GUN_POSITIONS[4]);
+        assert_eq!(
+            machine.cpu.get_in_port(1) & STICK_AND_TRIGGER,
+            STICK_AND_TRIGGER
+        );
+    }
+
+    #[test]
+    fn test_coin_start_and_dips() {
+        let mut machine = machine();
+        assert_eq!(machine.cpu.get_in_port(DIP_PORT), 0x10);
+
+        machine.set_input(Input::Coin, true);
+        machine.set_input(Input::Start1, true);
+        machine.set_dips("coinage = 1/3\ngame_time = 90").unwrap();
+        assert_eq!(machine.cpu.get_in_port(DIP_PORT), 0b1111_1000);
+        assert_eq!(machine.dip("game_time").unwrap(), "90");
+
+        assert!(machine.set_dip("game_time=100").is_err());
+        assert!(machine.set_dip("ships=3").is_err());
+        assert!(machine.set_cabinet(::emulator::Cabinet::Cocktail).is_err());
+    }
+
+    #[test]
+    fn test_landscape() {
+        let mut machine = machine();
+        let framebuffer = machine.run_frame();
+        assert_eq!((framebuffer.width(), framebuffer.height()), (256, 224));
+    }
+}
@@ -0,0 +1,605 @@
+use emulator::cpu::CPU;
+use emulator::drivers::{MachineDriver, Shifter};
+use emulator::input::Input;
+use emulator::sound::{SoundBoard, SOUND_PORT_1, SOUND_PORT_2};
+use emulator::{Cabinet, MID_SCREEN_LINE, VBLANK_LINE};
+
+const SHIFT_BY_BITS_PORT: usize = 2;
+const SHIFTED_VALUE_PORT: usize = 3;
+const VALUE_TO_SHIFT_PORT: usize = 4;
+const WATCHDOG_PORT: usize = 6;
+const DIP_PORT: usize = 2;
+
+const IN_PORTS: [u8; 4] = [0, 1, 2, SHIFTED_VALUE_PORT as u8];
+const OUT_PORTS: [u8; 5] = [
+    SHIFT_BY_BITS_PORT as u8,
+    SOUND_PORT_1 as u8,
+    VALUE_TO_SHIFT_PORT as u8,
+    SOUND_PORT_2 as u8,
+    WATCHDOG_PORT as u8,
+];
+
+const INPUTS: [Input; 11] = [
+    Input::Coin,
+    Input::Start1,
+    Input::Fire1,
+    Input::Left1,
+    Input::Right1,
+    Input::Start2,
+    Input::Fire2,
+    Input::Left2,
+    Input::Right2,
+    Input::Tilt,
+    Input::Service,
+];
+const KEY_BINDINGS: &str = "
+bind C coin
+bind S start1
+bind Space fire1
+bind Left left1
+bind Right right1
+bind D2 start2
+bind W fire2
+bind A left2
+bind D right2
+bind T tilt
+bind F1 service
+";
+
+const INTERRUPTS: [(u64, u8); 2] = [(MID_SCREEN_LINE, 1), (VBLANK_LINE, 2)];
+
+// Fire, left and right, at the same bits for both players
+const CONTROL_MASK: u8 = 0b0111_0000;
+const FLIP_SCREEN: u8 = 0x20;
+
+const SHIPS_MASK: u8 = 0b0000_0011;
+const EXTRA_SHIP_1000: u8 = 0b0000_1000;
+const COIN_INFO_OFF: u8 = 0b1000_0000;
+
+// The bits of the DIP port that are switches
+const DIP_MASK: u8 = SHIPS_MASK | EXTRA_SHIP_1000 | COIN_INFO_OFF;
+
+// Each switch by name with the values `set` takes
+const SWITCHES: [(&str, &[&str]); 3] = [
+    ("ships", &["3", "4", "5", "6"]),
+    ("extra_ship_at", &["1000", "1500"]),
+    ("coin_info", &["on", "off"]),
+];
+
+/// The Space Invaders DIP switches, read through port 2
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DipSwitches {
+    ships: u8,
+    extra_ship_at: u16,
+    coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    fn default() -> DipSwitches {
+        DipSwitches {
+            ships: 3,
+            extra_ship_at: 1000,
+            coin_info: true,
+        }
+    }
+}
+
+impl DipSwitches {
+    pub fn ships(&self) -> u8 {
+        self.ships
+    }
+
+    /// 3 to 6 ships per game
+    pub fn set_ships(&mut self, ships: u8) -> Result<(), String> {
+        if !(3..=6).contains(&ships) {
+            return Err(format!("Ships must be 3 to 6, not {}", ships));
+        }
+
+        self.ships = ships;
+        Ok(())
+    }
+
+    pub fn extra_ship_at(&self) -> u16 {
+        self.extra_ship_at
+    }
+
+    /// 1000 or 1500 points
+    pub fn set_extra_ship_at(&mut self, score: u16) -> Result<(), String> {
+        if score != 1000 && score != 1500 {
+            return Err(format!("Extra ship must be at 1000 or 1500, not {}", score));
+        }
+
+        self.extra_ship_at = score;
+        Ok(())
+    }
+
+    /// Whether the attract mode shows the coin information
+    pub fn coin_info(&self) -> bool {
+        self.coin_info
+    }
+
+    pub fn set_coin_info(&mut self, on: bool) {
+        self.coin_info = on;
+    }
+
+    /// The switch bits as the port reads them, see `DIP_MASK`
+    pub fn port_bits(&self) -> u8 {
+        let mut bits = (self.ships - 3) & SHIPS_MASK;
+
+        if self.extra_ship_at == 1000 {
+            bits |= EXTRA_SHIP_1000;
+        }
+
+        if !self.coin_info {
+            bits |= COIN_INFO_OFF;
+        }
+
+        bits
+    }
+
+    /// The value of a switch as `set` takes it
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "ships" => Some(self.ships.to_string()),
+            "extra_ship_at" => Some(self.extra_ship_at.to_string()),
+            "coin_info" => Some(if self.coin_info { "on" } else { "off" }.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Applies one `name=value` setting, as given on the command line
+    pub fn set(&mut self, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap().trim();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("Expected name=value: {}", setting))?
+            .trim();
+
+        match name {
+            "ships" => self.set_ships(parse_number(value)?),
+            "extra_ship_at" => self.set_extra_ship_at(parse_number(value)?),
+            "coin_info" => {
+                self.set_coin_info(match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("coin_info must be on or off, not {}", value)),
+                });
+                Ok(())
+            }
+            _ => Err(format!("Unknown DIP switch: {}", name)),
+        }
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Expected a number: {}", value))
+}
+
+/// Space Invaders, in an upright or a cocktail table
+pub struct SpaceInvaders {
+    shifter: Shifter,
+    dips: DipSwitches,
+    cabinet: Cabinet,
+    controls: [u8; 2],
+    flip: bool,
+}
+
+impl SpaceInvaders {
+    pub fn new() -> SpaceInvaders {
+        SpaceInvaders {
+            shifter: Shifter::new(),
+            dips: DipSwitches::default(),
+            cabinet: Cabinet::Upright,
+            controls: [0; 2],
+            flip: false,
+        }
+    }
+
+    pub fn dip_switches(&self) -> DipSwitches {
+        self.dips
+    }
+
+    /// Can be changed at any time, though the game only reads some switches
+    /// when a game starts
+    pub fn set_dip_switches(&mut self, cpu: &mut CPU, dips: DipSwitches) {
+        self.dips = dips;
+
+        let port = cpu.get_in_port(DIP_PORT) & !DIP_MASK;
+        cpu.set_in_port(DIP_PORT, port | dips.port_bits());
+    }
+
+    fn set_control(&mut self, cpu: &mut CPU, player: usize, bit: u8, down: bool) {
+        if down {
+            self.controls[player] |= 1 << bit;
+        } else {
+            self.controls[player] &= !(1 << bit);
+        }
+
+        self.update_control_ports(cpu);
+    }
+
+    // Player 1's panel is on port 1. Port 2 gets player 2's panel in a
+    // cocktail table, an upright only has the one panel for both players.
+    fn update_control_ports(&mut self, cpu: &mut CPU) {
+        let port2_controls = match self.cabinet {
+            Cabinet::Upright => self.controls[0] | self.controls[1],
+            Cabinet::Cocktail => self.controls[1],
+        };
+
+        let port1 = cpu.get_in_port(1) & !CONTROL_MASK;
+        cpu.set_in_port(1, port1 | self.controls[0]);
+
+        let port2 = cpu.get_in_port(2) & !CONTROL_MASK;
+        cpu.set_in_port(2, port2 | port2_controls);
+    }
+}
+
+impl Default for SpaceInvaders {
+    fn default() -> SpaceInvaders {
+        SpaceInvaders::new()
+    }
+}
+
+impl MachineDriver for SpaceInvaders {
+    fn name(&self) -> &'static str {
+        "invaders"
+    }
+
+    fn power_on(&mut self, cpu: &mut CPU) {
+        cpu.set_in_port(0, 0b00001110);
+        cpu.set_in_port(1, 0b00001000);
+
+        let dips = self.dips;
+        self.set_dip_switches(cpu, dips);
+    }
+
+    fn update(&mut self, cpu: &mut CPU, mut sound: Option<&mut SoundBoard>, cycles: u64) {
+        let (offset, mut should_update_shift) = cpu.get_out_port(SHIFT_BY_BITS_PORT);
+
+        if let (v, true) = cpu.get_out_port(VALUE_TO_SHIFT_PORT) {
+            self.shifter.write_data(v);
+            should_update_shift = true;
+        }
+
+        if should_update_shift {
+            self.shifter.set_offset(offset);
+            cpu.set_in_port(SHIFTED_VALUE_PORT, self.shifter.result());
+        }
+
+        for &port in &[SOUND_PORT_1, SOUND_PORT_2] {
+            if let (v, true) = cpu.get_out_port(port) {
+                if port == SOUND_PORT_2 {
+                    self.flip = v & FLIP_SCREEN != 0;
+                }
+
+                if let Some(ref mut sound) = sound {
+                    sound.write(port, v, cycles);
+                }
+            }
+        }
+    }
+
+    fn in_ports(&self) -> &'static [u8] {
+        &IN_PORTS
+    }
+
+    fn out_ports(&self) -> &'static [u8] {
+        &OUT_PORTS
+    }
+
+    fn watchdog_port(&self) -> Option<usize> {
+        Some(WATCHDOG_PORT)
+    }
+
+    fn inputs(&self) -> &'static [Input] {
+        &INPUTS
+    }
+
+    fn set_input(&mut self, cpu: &mut CPU, input: Input, down: bool) {
+        match input {
+            Input::Coin => cpu.set_in_port_bit(1, 0, down),
+            Input::Start1 => cpu.set_in_port_bit(1, 2, down),
+            Input::Start2 => cpu.set_in_port_bit(1, 1, down),
+            Input::Fire1 => self.set_control(cpu, 0, 4, down),
+            Input::Left1 => self.set_control(cpu, 0, 5, down),
+            Input::Right1 => self.set_control(cpu, 0, 6, down),
+            Input::Fire2 => self.set_control(cpu, 1, 4, down),
+            Input::Left2 => self.set_control(cpu, 1, 5, down),
+            Input::Right2 => self.set_control(cpu, 1, 6, down),
+            Input::Tilt => cpu.set_in_port_bit(2, 2, down),
+            Input::Service => cpu.set_in_port_bit(0, 0, down),
+            _ => (),
+        }
+    }
+
+    fn key_bindings(&self) -> &'static str {
+        KEY_BINDINGS
+    }
+
+    fn interrupts(&self) -> &'static [(u64, u8)] {
+        &INTERRUPTS
+    }
+
+    // The game flips the picture for player 2 through OUT 5, only a cocktail
+    // table has the wiring for it
+    fn is_flipped(&self) -> bool {
+        self.flip && self.cabinet == Cabinet::Cocktail
+    }
+
+    fn cabinet(&self) -> Cabinet {
+        self.cabinet
+    }
+
+    fn set_cabinet(&mut self, cpu: &mut CPU, cabinet: Cabinet) -> Result<(), String> {
+        self.cabinet = cabinet;
+        self.update_control_ports(cpu);
+        Ok(())
+    }
+
+    fn dip_switches(&self) -> &'static [(&'static str, &'static [&'static str])] {
+        &SWITCHES
+    }
+
+    fn dip(&self, name: &str) -> Option<String> {
+        self.dips.get(name)
+    }
+
+    fn set_dip(&mut self, cpu: &mut CPU, setting: &str) -> Result<(), String> {
+        let mut dips = self.dips;
+        dips.set(setting)?;
+        self.set_dip_switches(cpu, dips);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::drivers::VRAM_ADDR;
+    use emulator::headless::{run_frames, InputScript};
+    use emulator::ArcadeMachine;
+    use opcode_decoder::*;
+
+    #[test]
+    fn test_port_bits() {
+        // What the machine powered up with before the switches were settable
+        assert_eq!(DipSwitches::default().port_bits(), 0b0000_1000);
+
+        let mut dips = DipSwitches::default();
+        dips.set_ships(6).unwrap();
+        dips.set_extra_ship_at(1500).unwrap();
+        dips.set_coin_info(false);
+        assert_eq!(dips.port_bits(), 0b1000_0011);
+
+        dips.set_ships(4).unwrap();
+        assert_eq!(dips.port_bits(), 0b1000_0001);
+
+        assert!(dips.set_ships(2).is_err());
+        assert!(dips.set_ships(7).is_err());
+        assert!(dips.set_extra_ship_at(2000).is_err());
+        assert_eq!(dips.ships(), 4);
+    }
+
+    #[test]
+    fn test_dip_set() {
+        let mut dips = DipSwitches::default();
+        dips.set("ships = 5").unwrap();
+        dips.set("extra_ship_at=1500").unwrap();
+        dips.set("coin_info = off").unwrap();
+        assert_eq!(dips.ships(), 5);
+        assert_eq!(dips.extra_ship_at(), 1500);
+        assert!(!dips.coin_info());
+
+        assert!(dips.set("ships").is_err());
+        assert!(dips.set("ships = many").is_err());
+        assert!(dips.set("lives = 3").is_err());
+        assert!(dips.set("coin_info = maybe").is_err());
+
+        for &(name, values) in SWITCHES.iter() {
+            let mut dips = DipSwitches::default();
+            for value in values {
+                dips.set(&format!("{}={}", name, value)).unwrap();
+                assert_eq!(dips.get(name).unwrap(), *value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
+        machine.update_ports();
+
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110011);
+    }
+
+    #[test]
+    fn test_shift_register_offset() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
+        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+        machine.update_ports();
+
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b10011000);
+    }
+
+    #[test]
+    fn test_shift_register_multiple() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
+        machine.update_ports();
+
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110011);
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01010101);
+
+        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+        machine.cpu.get_out_port(SHIFT_BY_BITS_PORT);
+
+        machine.update_ports();
+
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b10101011);
+    }
+
+    #[test]
+    fn test_shift_register_multiple2() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x38);
+        machine.update_ports();
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0xF1);
+        machine.update_ports();
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0xFF);
+        machine.update_ports();
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x80);
+        machine.update_ports();
+        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x0E);
+        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+        machine.update_ports();
+
+        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110100);
+    }
+
+    #[test]
+    fn test_player_inputs() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let port1 = machine.cpu.get_in_port(1);
+        let port2 = machine.cpu.get_in_port(2);
+
+        machine.set_input(Input::Start2, true);
+        machine.set_input(Input::Start1, true);
+        assert_eq!(machine.cpu.get_in_port(1), port1 | 0b00000110);
+
+        machine.set_input(Input::Fire2, true);
+        machine.set_input(Input::Left2, true);
+        machine.set_input(Input::Right2, true);
+        assert_eq!(machine.cpu.get_in_port(2), port2 | 0b01110000);
+        assert_eq!(machine.cpu.get_in_port(1), port1 | 0b00000110);
+
+        machine.set_input(Input::Left2, false);
+        machine.set_input(Input::Start2, false);
+        assert_eq!(machine.cpu.get_in_port(2), port2 | 0b01010000);
+        assert_eq!(machine.cpu.get_in_port(1), port1 | 0b00000100);
+    }
+
+    #[test]
+    fn test_tilt() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let port2 = machine.cpu.get_in_port(2);
+
+        machine.set_input(Input::Tilt, true);
+        assert_eq!(machine.cpu.get_in_port(2), port2 | 0b00000100);
+
+        // Survives the switches and controls sharing the port
+        machine.set_input(Input::Fire2, true);
+        machine.set_dip("ships=3").unwrap();
+        machine.set_cabinet(Cabinet::Cocktail).unwrap();
+        assert_eq!(machine.cpu.get_in_port(2), port2 | 0b00010100);
+
+        machine.set_input(Input::Tilt, false);
+        machine.set_input(Input::Fire2, false);
+        assert_eq!(machine.cpu.get_in_port(2), port2);
+    }
+
+    #[test]
+    fn test_tilt_script() {
+        // Waits for the tilt bit like the game does, then marks the screen
+        #[rustfmt::skip]
+        let rom = [
+            0xdb, 0x02,         // IN 2
+            0xe6, 0x04,         // ANI 4
+            0xca, 0x00, 0x00,   // JZ 0
+            0x3e, 0xff,         // MVI A, 0xff
+            0x32, 0x00, 0x24,   // STA 0x2400
+            0x76,               // HLT
+        ];
+        let mut machine = ArcadeMachine::new(init_decoder(), &rom);
+        let script = InputScript::parse("3 tilt down\n5 tilt up").unwrap();
+
+        let mut tilted = Vec::new();
+        run_frames(&mut machine, &script, 8, None, |_, framebuffer| {
+            tilted.push(framebuffer.pixel(0, framebuffer.height() - 1));
+            Ok(())
+        })
+        .unwrap();
+
+        // Seen a frame later, as the first line is latched when a frame starts
+        assert_eq!(
+            tilted,
+            vec![false, false, false, false, true, true, true, true]
+        );
+    }
+
+    #[test]
+    fn test_service_switch() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let port0 = machine.cpu.get_in_port(0);
+
+        machine.set_input(Input::Service, true);
+        assert_eq!(machine.cpu.get_in_port(0), port0 | 0b00000001);
+
+        machine.set_input(Input::Service, false);
+        assert_eq!(machine.cpu.get_in_port(0), port0);
+    }
+
+    #[test]
+    fn test_dip_switches() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        assert_eq!(machine.cpu.get_in_port(DIP_PORT), 0b00001000);
+
+        machine.set_input(Input::Fire2, true);
+        machine.set_dips("ships = 5\ncoin_info = off").unwrap();
+
+        assert_eq!(machine.cpu.get_in_port(DIP_PORT), 0b10011010);
+        assert_eq!(machine.dip("ships").unwrap(), "5");
+        assert_eq!(machine.dip("coin_info").unwrap(), "off");
+
+        assert!(machine.set_dip("ships=9").is_err());
+        assert!(machine.set_dip("speed=9").is_err());
+        assert_eq!(machine.dip("ships").unwrap(), "5");
+    }
+
+    #[test]
+    fn test_cabinet_controls() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.set_input(Input::Left1, true);
+        assert_eq!(machine.cpu.get_in_port(1) & CONTROL_MASK, 0b00100000);
+        assert_eq!(machine.cpu.get_in_port(2) & CONTROL_MASK, 0b00100000);
+
+        machine.set_cabinet(Cabinet::Cocktail).unwrap();
+        assert_eq!(machine.cpu.get_in_port(2) & CONTROL_MASK, 0);
+
+        machine.set_input(Input::Fire2, true);
+        assert_eq!(machine.cpu.get_in_port(1) & CONTROL_MASK, 0b00100000);
+        assert_eq!(machine.cpu.get_in_port(2) & CONTROL_MASK, 0b00010000);
+        assert_eq!(machine.cpu.get_in_port(2) & DIP_MASK, 0b00001000);
+    }
+
+    #[test]
+    fn test_cocktail_flip() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[0xc3, 0x00, 0x00]); // JMP 0
+        machine.cpu.set_memory(VRAM_ADDR, &[0x01]);
+
+        machine.cpu.set_out_port(SOUND_PORT_2, FLIP_SCREEN);
+        machine.update_ports();
+        let framebuffer = machine.run_frame();
+        assert!(framebuffer.pixel(0, framebuffer.height() - 1));
+
+        machine.set_cabinet(Cabinet::Cocktail).unwrap();
+        let framebuffer = machine.run_frame();
+        assert!(framebuffer.pixel(framebuffer.width() - 1, 0));
+        assert!(!framebuffer.pixel(0, framebuffer.height() - 1));
+
+        machine.cpu.set_out_port(SOUND_PORT_2, 0);
+        machine.update_ports();
+        let framebuffer = machine.run_frame();
+        assert!(framebuffer.pixel(0, framebuffer.height() - 1));
+    }
+}
@@ -0,0 +1,232 @@
+pub mod gunfight;
+pub mod invaders;
+pub mod shifter;
+
+pub use self::gunfight::GunFight;
+pub use self::invaders::SpaceInvaders;
+pub use self::shifter::Shifter;
+
+use emulator::cpu::CPU;
+use emulator::input::Input;
+use emulator::sound::SoundBoard;
+use emulator::Cabinet;
+
+/// The drivers `by_name` knows
+pub const NAMES: [&str; 2] = ["invaders", "gunfight"];
+
+/// Where the video RAM is on every Midway 8080 board so far
+pub const VRAM_ADDR: u16 = 0x2400;
+
+/// One game on the Midway 8080 board.
+///
+/// `ArcadeMachine` runs the CPU, the beam and the watchdog counter; the
+/// driver says where the ROM goes, what is wired to the ports and when the
+/// interrupts fire. `update` is called after every IN or OUT and picks up
+/// the OUTs it cares about.
+pub trait MachineDriver {
+    fn name(&self) -> &'static str;
+
+    /// Maps memory and loads the program, ROM at 0 and RAM above by default
+    fn load_rom(&self, cpu: &mut CPU, rom: &[u8]) {
+        cpu.set_memory(0, rom);
+    }
+
+    /// Sets what the input ports read with nothing pressed
+    fn power_on(&mut self, cpu: &mut CPU);
+
+    fn update(&mut self, cpu: &mut CPU, sound: Option<&mut SoundBoard>, cycles: u64);
+
+    /// The ports something answers on, everything else counts as unhandled
+    fn in_ports(&self) -> &'static [u8];
+    fn out_ports(&self) -> &'static [u8];
+
+    /// The port the game writes to keep the watchdog from resetting it
+    fn watchdog_port(&self) -> Option<usize> {
+        None
+    }
+
+    /// The inputs the cabinet has, `set_input` ignores the others
+    fn inputs(&self) -> &'static [Input];
+    fn set_input(&mut self, cpu: &mut CPU, input: Input, down: bool);
+
+    /// Default keys for the inputs, in the `InputMap` config syntax
+    fn key_bindings(&self) -> &'static str {
+        ""
+    }
+
+    /// The RST each interrupt line fires at, up to and including vblank
+    fn interrupts(&self) -> &'static [(u64, u8)];
+
+    fn vram_addr(&self) -> u16 {
+        VRAM_ADDR
+    }
+
+    /// Whether the monitor is turned on its side, see `Framebuffer`
+    fn is_rotated(&self) -> bool {
+        true
+    }
+
+    /// Whether the picture is turned upside down at the moment
+    fn is_flipped(&self) -> bool {
+        false
+    }
+
+    fn cabinet(&self) -> Cabinet {
+        Cabinet::Upright
+    }
+
+    fn set_cabinet(&mut self, _cpu: &mut CPU, cabinet: Cabinet) -> Result<(), String> {
+        match cabinet {
+            Cabinet::Upright => Ok(()),
+            _ => Err(format!("{} only comes as an upright", self.name())),
+        }
+    }
+
+    /// Each DIP switch by name, with the values it takes
+    fn dip_switches(&self) -> &'static [(&'static str, &'static [&'static str])] {
+        &[]
+    }
+
+    fn dip(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    /// Applies one `name=value` setting
+    fn set_dip(&mut self, _cpu: &mut CPU, setting: &str) -> Result<(), String> {
+        let name = setting.split('=').next().unwrap().trim();
+        Err(format!("{} has no DIP switch {}", self.name(), name))
+    }
+
+    /// Turns on a variation in how the board is wired, before it runs
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        Err(format!("{} has no option {}", self.name(), option))
+    }
+}
+
+/// Calls `set` with each `name=value` line of `text`. Blank lines and
+/// anything after `#` are ignored.
+pub fn parse_settings<F>(text: &str, mut set: F) -> Result<(), String>
+where
+    F: FnMut(&str) -> Result<(), String>,
+{
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if !line.is_empty() {
+            set(line).map_err(|e| format!("Line {}: {}", i + 1, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn by_name(name: &str) -> Result<Box<dyn MachineDriver>, String> {
+    match name {
+        "invaders" => Ok(Box::new(SpaceInvaders::new())),
+        "gunfight" => Ok(Box::new(GunFight::new())),
+        _ => Err(format!(
+            "Unknown driver: {}, expected one of {}",
+            name,
+            NAMES.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::ArcadeMachine;
+    use opcode_decoder::*;
+
+    // Coin on port 7, echoes OUT 1 back on IN 1, RST 3 at line 10
+    struct Echo;
+
+    impl MachineDriver for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn power_on(&mut self, cpu: &mut CPU) {
+            cpu.set_in_port(7, 0x80);
+        }
+
+        fn update(&mut self, cpu: &mut CPU, _sound: Option<&mut SoundBoard>, _cycles: u64) {
+            if let (v, true) = cpu.get_out_port(1) {
+                cpu.set_in_port(1, v);
+            }
+        }
+
+        fn in_ports(&self) -> &'static [u8] {
+            &[1, 7]
+        }
+
+        fn out_ports(&self) -> &'static [u8] {
+            &[1]
+        }
+
+        fn inputs(&self) -> &'static [Input] {
+            &[Input::Coin]
+        }
+
+        fn set_input(&mut self, cpu: &mut CPU, input: Input, down: bool) {
+            if input == Input::Coin {
+                cpu.set_in_port_bit(7, 0, down);
+            }
+        }
+
+        fn interrupts(&self) -> &'static [(u64, u8)] {
+            &[(10, 3)]
+        }
+    }
+
+    #[test]
+    fn test_custom_driver() {
+        #[rustfmt::skip]
+        let rom = [
+            0x31, 0x00, 0x24,   // LXI SP, 0x2400
+            0xfb,               // EI
+            0xc3, 0x04, 0x00,   // JMP $
+            0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x3e, 0x42,         // RST 3: MVI A, 0x42
+            0xd3, 0x01,         // OUT 1
+            0xdb, 0x07,         // IN 7
+            0xc9,               // RET
+        ];
+        let mut machine = ArcadeMachine::with_driver(init_decoder(), &rom, Box::new(Echo));
+        machine.set_io_logging(true);
+        machine.set_input(Input::Fire1, true);
+        machine.set_input(Input::Coin, true);
+
+        machine.run_frame();
+        assert_eq!(machine.cpu.get_in_port(1), 0x42);
+        assert_eq!(machine.cpu.a(), 0x81);
+        assert!(machine.take_diagnostics().is_empty());
+        assert!(machine.set_dip("ships=3").is_err());
+        assert!(machine.set_cabinet(Cabinet::Cocktail).is_err());
+        assert!(Echo.set_option("reversed_shift").is_err());
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let text = "# cabinet 2\nships = 5\n\ncoin_info = off # demo only\n";
+        let mut lines = Vec::new();
+        parse_settings(text, |line| {
+            lines.push(line.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["ships = 5", "coin_info = off"]);
+
+        let err = parse_settings("\nships = many", |_| Err("no".to_string()));
+        assert_eq!(err, Err("Line 2: no".to_string()));
+    }
+
+    #[test]
+    fn test_by_name() {
+        for name in NAMES.iter() {
+            assert_eq!(by_name(name).unwrap().name(), *name);
+        }
+        assert!(by_name("pacman").is_err());
+    }
+}
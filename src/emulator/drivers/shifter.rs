@@ -0,0 +1,44 @@
+/// The MB14241 barrel shifter the 8080 uses to draw sprites at any bit offset.
+///
+/// Each data write pushes a byte in from the top, the result is the 8 bits
+/// `offset` below the most recent byte. Some boards wire the result pins
+/// in reverse, which mirrors the bits read back.
+#[derive(Debug, Default)]
+pub struct Shifter {
+    data: u16,
+    offset: u8,
+    reversed: bool,
+}
+
+impl Shifter {
+    pub fn new() -> Shifter {
+        Shifter::default()
+    }
+
+    pub fn write_data(&mut self, val: u8) {
+        self.data = (self.data >> 8) | ((val as u16) << 8);
+    }
+
+    /// Only the low 3 bits count
+    pub fn set_offset(&mut self, offset: u8) {
+        self.offset = offset & 0x07;
+    }
+
+    pub fn set_reversed(&mut self, reversed: bool) {
+        self.reversed = reversed;
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+
+    pub fn result(&self) -> u8 {
+        let result = ((self.data << self.offset) >> 8) as u8;
+
+        if self.reversed {
+            result.reverse_bits()
+        } else {
+            result
+        }
+    }
+}
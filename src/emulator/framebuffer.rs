@@ -0,0 +1,376 @@
+use image;
+
+/// The picture on a monitor turned on its side, the usual way
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelFormat {
+    /// One bit per pixel, leftmost pixel in the high bit
+    Mono1,
+    /// One byte per pixel, 0 or 255
+    Luma8,
+    /// Four bytes per pixel, white or black with full alpha
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_row(self, width: usize) -> usize {
+        match self {
+            PixelFormat::Mono1 => width / 8,
+            PixelFormat::Luma8 => width,
+            PixelFormat::Rgba8888 => width * 4,
+        }
+    }
+}
+
+/// The picture decoded from video memory, 32 bytes per line with the low
+/// bit of each byte first.
+///
+/// On a rotated monitor the lines run up the screen, giving an upright
+/// 224x256 picture. Otherwise the picture is 256x224, line by line.
+pub struct Framebuffer {
+    format: PixelFormat,
+    data: Vec<u8>,
+    dirty: Vec<bool>,
+    flip: bool,
+    rotated: bool,
+}
+
+impl Framebuffer {
+    pub fn new(format: PixelFormat) -> Framebuffer {
+        let mut framebuffer = Framebuffer {
+            format,
+            data: Vec::new(),
+            dirty: Vec::new(),
+            flip: false,
+            rotated: true,
+        };
+        framebuffer.set_format(format);
+        framebuffer
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Switches format, clearing the picture and marking every row dirty
+    pub fn set_format(&mut self, format: PixelFormat) {
+        self.format = format;
+        self.data = vec![0; format.bytes_per_row(self.width()) * self.height()];
+        self.dirty = vec![true; self.height()];
+
+        if format == PixelFormat::Rgba8888 {
+            for pixel in self.data.chunks_mut(4) {
+                pixel[3] = 0xff;
+            }
+        }
+
+        self.mark_dirty();
+    }
+
+    /// Marks every row dirty, for a frontend that lost its copy
+    pub fn mark_dirty(&mut self) {
+        for dirty in &mut self.dirty {
+            *dirty = true;
+        }
+    }
+
+    /// Turns the picture upside down from the next update, as a cocktail
+    /// table does for player 2
+    pub fn set_flip(&mut self, flip: bool) {
+        self.flip = flip;
+    }
+
+    pub fn is_flipped(&self) -> bool {
+        self.flip
+    }
+
+    /// Switches between a rotated and a landscape monitor, clearing the
+    /// picture like `set_format`
+    pub fn set_rotated(&mut self, rotated: bool) {
+        self.rotated = rotated;
+        let format = self.format;
+        self.set_format(format);
+    }
+
+    pub fn is_rotated(&self) -> bool {
+        self.rotated
+    }
+
+    pub fn width(&self) -> usize {
+        if self.rotated {
+            WIDTH
+        } else {
+            HEIGHT
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.rotated {
+            HEIGHT
+        } else {
+            WIDTH
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn row(&self, y: usize) -> &[u8] {
+        let len = self.format.bytes_per_row(self.width());
+        &self.data[y * len..(y + 1) * len]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let row = self.row(y);
+
+        match self.format {
+            PixelFormat::Mono1 => row[x / 8] & (0x80 >> (x % 8)) != 0,
+            PixelFormat::Luma8 => row[x] != 0,
+            PixelFormat::Rgba8888 => row[x * 4] != 0,
+        }
+    }
+
+    /// Whether the row changed in the last update
+    pub fn is_row_dirty(&self, y: usize) -> bool {
+        self.dirty[y]
+    }
+
+    pub fn dirty_rows(&self) -> Vec<usize> {
+        (0..self.height()).filter(|&y| self.dirty[y]).collect()
+    }
+
+    /// FNV-1a of the size and the lit pixels, the same in every pixel format
+    pub fn hash(&self) -> u64 {
+        let (width, height) = (self.width(), self.height());
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut add = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        };
+
+        for &n in &[width, height] {
+            add(n as u8);
+            add((n >> 8) as u8);
+        }
+
+        for y in 0..height {
+            for x in (0..width).step_by(8) {
+                let byte = (x..(x + 8).min(width))
+                    .filter(|&x| self.pixel(x, y))
+                    .fold(0, |byte, px| byte | (0x80 >> (px % 8)));
+                add(byte);
+            }
+        }
+
+        hash
+    }
+
+    /// Writes the picture out, in shades of gray unless it holds RGBA
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        let (width, height) = (self.width(), self.height());
+
+        let result = match self.format {
+            PixelFormat::Rgba8888 => image::save_buffer(
+                path,
+                &self.data,
+                width as u32,
+                height as u32,
+                image::RGBA(8),
+            ),
+            _ => {
+                let mut luma = Vec::with_capacity(width * height);
+                for y in 0..height {
+                    for x in 0..width {
+                        luma.push(if self.pixel(x, y) { 0xff } else { 0x00 });
+                    }
+                }
+                image::save_buffer(path, &luma, width as u32, height as u32, image::Gray(8))
+            }
+        };
+
+        result.map_err(|e| format!("Failed to write {}: {}", path, e))
+    }
+
+    /// Decodes video memory, marking exactly the rows that changed as dirty
+    pub fn update(&mut self, vram: &[u8]) {
+        let len = self.format.bytes_per_row(self.width());
+        let mut row = vec![0; len];
+
+        for y in 0..self.height() {
+            self.decode_row(vram, y, &mut row);
+
+            let current = &mut self.data[y * len..(y + 1) * len];
+            self.dirty[y] = *current != row[..];
+            if self.dirty[y] {
+                current.copy_from_slice(&row);
+            }
+        }
+    }
+
+    fn decode_row(&self, vram: &[u8], y: usize, row: &mut [u8]) {
+        let (width, height) = (self.width(), self.height());
+
+        for v in row.iter_mut() {
+            *v = 0;
+        }
+
+        for x in 0..width {
+            let (x_on_screen, y_on_screen) = if self.flip {
+                (width - 1 - x, height - 1 - y)
+            } else {
+                (x, y)
+            };
+
+            // Video memory line and position along it
+            let (line, pos) = if self.rotated {
+                (x_on_screen, height - 1 - y_on_screen)
+            } else {
+                (y_on_screen, x_on_screen)
+            };
+
+            let on = (vram[line * (HEIGHT / 8) + pos / 8] >> (pos % 8)) & 0x01 != 0;
+
+            match self.format {
+                PixelFormat::Mono1 => {
+                    if on {
+                        row[x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+                PixelFormat::Luma8 => row[x] = if on { 0xff } else { 0x00 },
+                PixelFormat::Rgba8888 => {
+                    let val = if on { 0xff } else { 0x00 };
+                    row[x * 4..x * 4 + 4].copy_from_slice(&[val, val, val, 0xff]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VRAM_LEN: usize = WIDTH * HEIGHT / 8;
+
+    // The bottom left pixel is the low bit of the first byte, the top left
+    // the high bit of byte 31
+    fn corners() -> Vec<u8> {
+        let mut vram = vec![0; VRAM_LEN];
+        vram[0] = 0x01;
+        vram[31] = 0x80;
+        vram[VRAM_LEN - 1] = 0x80;
+        vram
+    }
+
+    #[test]
+    fn test_decode() {
+        for &format in &[
+            PixelFormat::Mono1,
+            PixelFormat::Luma8,
+            PixelFormat::Rgba8888,
+        ] {
+            let mut framebuffer = Framebuffer::new(format);
+            framebuffer.update(&corners());
+
+            assert_eq!(
+                framebuffer.data().len(),
+                format.bytes_per_row(WIDTH) * HEIGHT
+            );
+            assert!(framebuffer.pixel(0, HEIGHT - 1));
+            assert!(framebuffer.pixel(0, 0));
+            assert!(framebuffer.pixel(WIDTH - 1, 0));
+            assert!(!framebuffer.pixel(1, 0));
+            assert!(!framebuffer.pixel(WIDTH - 1, HEIGHT - 1));
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut hashes = Vec::new();
+
+        for &format in &[
+            PixelFormat::Mono1,
+            PixelFormat::Luma8,
+            PixelFormat::Rgba8888,
+        ] {
+            let mut framebuffer = Framebuffer::new(format);
+            framebuffer.update(&corners());
+            hashes.push(framebuffer.hash());
+
+            framebuffer.update(&vec![0; VRAM_LEN]);
+            assert_ne!(framebuffer.hash(), hashes[0]);
+        }
+
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(hashes[0], hashes[2]);
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut framebuffer = Framebuffer::new(PixelFormat::Mono1);
+        framebuffer.update(&corners());
+        assert_eq!(framebuffer.row(0)[0], 0x80);
+        assert_eq!(framebuffer.row(0)[WIDTH / 8 - 1], 0x01);
+
+        framebuffer.set_format(PixelFormat::Luma8);
+        framebuffer.update(&corners());
+        assert_eq!(&framebuffer.row(0)[..2], &[0xff, 0x00]);
+
+        framebuffer.set_format(PixelFormat::Rgba8888);
+        framebuffer.update(&corners());
+        assert_eq!(
+            &framebuffer.row(0)[..8],
+            &[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_flip() {
+        let mut framebuffer = Framebuffer::new(PixelFormat::Luma8);
+        framebuffer.set_flip(true);
+        framebuffer.update(&corners());
+
+        assert!(framebuffer.pixel(WIDTH - 1, 0));
+        assert!(framebuffer.pixel(WIDTH - 1, HEIGHT - 1));
+        assert!(framebuffer.pixel(0, HEIGHT - 1));
+        assert!(!framebuffer.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_landscape() {
+        let mut framebuffer = Framebuffer::new(PixelFormat::Mono1);
+        framebuffer.set_rotated(false);
+        framebuffer.update(&corners());
+
+        assert_eq!((framebuffer.width(), framebuffer.height()), (HEIGHT, WIDTH));
+        assert_eq!(framebuffer.data().len(), HEIGHT / 8 * WIDTH);
+        assert!(framebuffer.pixel(0, 0));
+        assert!(framebuffer.pixel(HEIGHT - 1, 0));
+        assert!(framebuffer.pixel(HEIGHT - 1, WIDTH - 1));
+        assert!(!framebuffer.pixel(0, WIDTH - 1));
+
+        framebuffer.set_flip(true);
+        framebuffer.update(&corners());
+        assert!(framebuffer.pixel(HEIGHT - 1, WIDTH - 1));
+        assert!(framebuffer.pixel(0, WIDTH - 1));
+        assert!(!framebuffer.pixel(HEIGHT - 1, 0));
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let mut framebuffer = Framebuffer::new(PixelFormat::Luma8);
+        assert_eq!(framebuffer.dirty_rows().len(), HEIGHT);
+
+        framebuffer.update(&vec![0; VRAM_LEN]);
+        assert!(framebuffer.dirty_rows().is_empty());
+
+        framebuffer.update(&corners());
+        assert_eq!(framebuffer.dirty_rows(), vec![0, HEIGHT - 1]);
+
+        framebuffer.update(&corners());
+        assert!(framebuffer.dirty_rows().is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+use image;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use emulator::framebuffer::Framebuffer;
+use emulator::headless::{run_frames, InputScript};
+use emulator::ArcadeMachine;
+
+/// Checks frames against the `Framebuffer::hash` recorded for them in
+/// hashes.txt, lines like `100 0123456789abcdef`, or against PNGs named like
+/// the headless dumps, frame_00100.png. A PNG wins when there is both.
+///
+/// On a mismatch the frame goes to a separate directory, as frame_00100.png
+/// for a hash and frame_00100.diff.png for an image. Pixels that only the
+/// golden image has come out red in the diff, pixels only the emulator drew
+/// come out green and matching lit pixels dim gray.
+pub struct Golden {
+    dir: PathBuf,
+    diff_dir: PathBuf,
+    update: bool,
+}
+
+impl Golden {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, diff_dir: Q) -> Golden {
+        Golden {
+            dir: dir.as_ref().to_path_buf(),
+            diff_dir: diff_dir.as_ref().to_path_buf(),
+            update: false,
+        }
+    }
+
+    /// Records the hashes of missing frames instead of failing on them
+    pub fn set_update(&mut self, update: bool) {
+        self.update = update;
+    }
+
+    pub fn path(&self, frame: u64) -> PathBuf {
+        self.dir.join(format!("frame_{:05}.png", frame))
+    }
+
+    pub fn hashes_path(&self) -> PathBuf {
+        self.dir.join("hashes.txt")
+    }
+
+    /// Where a frame that does not match its hash is written
+    pub fn actual_path(&self, frame: u64) -> PathBuf {
+        self.diff_dir.join(format!("frame_{:05}.png", frame))
+    }
+
+    pub fn diff_path(&self, frame: u64) -> PathBuf {
+        self.diff_dir.join(format!("frame_{:05}.diff.png", frame))
+    }
+
+    pub fn check(&self, frame: u64, framebuffer: &Framebuffer) -> Result<(), String> {
+        let path = self.path(frame);
+
+        if !path.exists() {
+            return self.check_hash(frame, framebuffer);
+        }
+
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let golden = load(&path, width, height)?;
+        let mismatches = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| golden[y * width + x] != framebuffer.pixel(x, y))
+            .count();
+
+        if mismatches == 0 {
+            return Ok(());
+        }
+
+        let diff_path = self.diff_path(frame);
+        save_diff(&diff_path, &golden, framebuffer)?;
+
+        Err(format!(
+            "Frame {}: {} pixels differ from {}, see {}",
+            frame,
+            mismatches,
+            path.display(),
+            diff_path.display()
+        ))
+    }
+
+    fn check_hash(&self, frame: u64, framebuffer: &Framebuffer) -> Result<(), String> {
+        let hashes_path = self.hashes_path();
+        let hash = framebuffer.hash();
+
+        match load_hashes(&hashes_path)?.iter().find(|h| h.0 == frame) {
+            Some(&(_, expected)) if expected == hash => Ok(()),
+            Some(&(_, expected)) => {
+                let actual_path = self.actual_path(frame);
+                framebuffer.save_png(&actual_path.to_string_lossy())?;
+
+                Err(format!(
+                    "Frame {}: hash {:016x} is not {:016x} from {}, see {}",
+                    frame,
+                    hash,
+                    expected,
+                    hashes_path.display(),
+                    actual_path.display()
+                ))
+            }
+            None if self.update => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&hashes_path)
+                .and_then(|mut f| writeln!(f, "{} {:016x}", frame, hash))
+                .map_err(|e| format!("Failed to write {}: {}", hashes_path.display(), e)),
+            None => Err(format!(
+                "Frame {}: no golden hash in {} or image at {}",
+                frame,
+                hashes_path.display(),
+                self.path(frame).display()
+            )),
+        }
+    }
+
+    /// Runs `frames` frames under `script`, checking every checkpoint frame
+    /// and reporting all the ones that failed
+    pub fn run(
+        &self,
+        machine: &mut ArcadeMachine,
+        script: &InputScript,
+        frames: u64,
+        checkpoints: &[u64],
+    ) -> Result<(), String> {
+        let mut failures = Vec::new();
+
+        run_frames(machine, script, frames, None, |frame, framebuffer| {
+            if checkpoints.contains(&frame) {
+                if let Err(e) = self.check(frame, framebuffer) {
+                    failures.push(e);
+                }
+            }
+            Ok(())
+        })?;
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("\n"))
+        }
+    }
+}
+
+/// (frame, hash) pairs, none if the file does not exist yet
+fn load_hashes(path: &Path) -> Result<Vec<(u64, u64)>, String> {
+    let mut text = String::new();
+    match File::open(path) {
+        Ok(mut f) => f
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut hashes = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let frame = parts.next().and_then(|f| f.parse().ok());
+        let hash = parts.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+
+        match (frame, hash, parts.next()) {
+            (Some(frame), Some(hash), None) => hashes.push((frame, hash)),
+            _ => {
+                return Err(format!(
+                    "{} line {}: expected <frame> <hash>: {}",
+                    path.display(),
+                    i + 1,
+                    line
+                ))
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+fn load(path: &Path, width: usize, height: usize) -> Result<Vec<bool>, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .to_luma();
+
+    if image.dimensions() != (width as u32, height as u32) {
+        return Err(format!("{} is not {}x{}", path.display(), width, height));
+    }
+
+    Ok(image.pixels().map(|p| p.data[0] > 0x7f).collect())
+}
+
+fn save_diff(path: &Path, golden: &[bool], framebuffer: &Framebuffer) -> Result<(), String> {
+    let (width, height) = (framebuffer.width(), framebuffer.height());
+    let mut data = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = match (golden[y * width + x], framebuffer.pixel(x, y)) {
+                (true, true) => [0x40, 0x40, 0x40, 0xff],
+                (true, false) => [0xff, 0x00, 0x00, 0xff],
+                (false, true) => [0x00, 0xff, 0x00, 0xff],
+                (false, false) => [0x00, 0x00, 0x00, 0xff],
+            };
+            data.extend_from_slice(&color);
+        }
+    }
+
+    image::save_buffer(path, &data, width as u32, height as u32, image::RGBA(8))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+    use std::env;
+    use std::fs;
+
+    // Copies the coin switch to the bottom left pixel
+    fn coin_machine() -> ArcadeMachine {
+        #[rustfmt::skip]
+        let rom = [
+            0xdb, 0x01,         // IN 1
+            0x32, 0x00, 0x24,   // STA 0x2400
+            0xc3, 0x00, 0x00,   // JMP 0
+        ];
+        ArcadeMachine::new(init_decoder(), &rom)
+    }
+
+    #[test]
+    fn test_golden() {
+        let dir = env::temp_dir().join(format!("e8080-golden-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut golden = Golden::new(&dir, &dir);
+        assert!(golden
+            .run(&mut coin_machine(), &InputScript::new(), 3, &[2])
+            .is_err());
+
+        golden.set_update(true);
+        golden
+            .run(&mut coin_machine(), &InputScript::new(), 3, &[2])
+            .unwrap();
+        assert_eq!(load_hashes(&golden.hashes_path()).unwrap().len(), 1);
+        assert!(!golden.path(2).exists());
+
+        golden.set_update(false);
+        golden
+            .run(&mut coin_machine(), &InputScript::new(), 3, &[2])
+            .unwrap();
+        assert!(!golden.actual_path(2).exists());
+
+        let script = InputScript::parse("0 coin down").unwrap();
+        let err = golden
+            .run(&mut coin_machine(), &script, 3, &[2])
+            .unwrap_err();
+        assert!(err.contains("Frame 2: hash"));
+        assert!(golden.actual_path(2).exists());
+
+        // The frame with the coin in, now as a golden image
+        fs::rename(golden.actual_path(2), golden.path(2)).unwrap();
+        let err = golden
+            .run(&mut coin_machine(), &InputScript::new(), 3, &[2])
+            .unwrap_err();
+        assert!(err.contains("1 pixels differ"));
+        assert!(golden.diff_path(2).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bad_hashes() {
+        let dir = env::temp_dir().join(format!("e8080-hashes-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let golden = Golden::new(&dir, &dir);
+        fs::write(golden.hashes_path(), "# frame hash\n2 12ab\n3 xyz\n").unwrap();
+        let err = load_hashes(&golden.hashes_path()).unwrap_err();
+        assert!(err.contains("line 3"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+use emulator::framebuffer::Framebuffer;
+use emulator::input::Input;
+use emulator::sound::AudioSink;
+use emulator::ArcadeMachine;
+
+/// Input changes keyed on frame number, read from lines like `120 coin down`.
+/// Blank lines and anything after `#` are ignored.
+pub struct InputScript {
+    events: Vec<(u64, Input, bool)>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript { events: Vec::new() }
+    }
+
+    pub fn open(path: &str) -> Result<InputScript, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+        InputScript::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut script = InputScript::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let err = |msg: String| format!("Line {}: {}", i + 1, msg);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3 {
+                return Err(err(format!("expected <frame> <input> down|up: {}", line)));
+            }
+
+            let frame = parts[0]
+                .parse()
+                .map_err(|_| err(format!("bad frame number: {}", parts[0])))?;
+            let input = Input::from_name(parts[1]).map_err(&err)?;
+            let down = match parts[2] {
+                "down" => true,
+                "up" => false,
+                s => return Err(err(format!("expected down or up: {}", s))),
+            };
+
+            script.push(frame, input, down);
+        }
+
+        Ok(script)
+    }
+
+    pub fn push(&mut self, frame: u64, input: Input, down: bool) {
+        self.events.push((frame, input, down));
+    }
+
+    /// Applies every change for `frame` in the order they were given
+    pub fn apply(&self, frame: u64, machine: &mut ArcadeMachine) {
+        for &(_, input, down) in self.events.iter().filter(|e| e.0 == frame) {
+            machine.set_input(input, down);
+        }
+    }
+}
+
+impl Default for InputScript {
+    fn default() -> InputScript {
+        InputScript::new()
+    }
+}
+
+/// Runs `frames` frames without a window, applying the script before each
+/// one and handing every finished frame to `on_frame` and its audio to `sink`
+pub fn run_frames<F>(
+    machine: &mut ArcadeMachine,
+    script: &InputScript,
+    frames: u64,
+    mut sink: Option<&mut dyn AudioSink>,
+    mut on_frame: F,
+) -> Result<(), String>
+where
+    F: FnMut(u64, &Framebuffer) -> Result<(), String>,
+{
+    for frame in 0..frames {
+        script.apply(frame, machine);
+        on_frame(frame, machine.run_frame())?;
+
+        if let Some(ref mut sink) = sink {
+            sink.write(&machine.take_audio())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::sound::SamplePlayer;
+    use opcode_decoder::*;
+
+    #[test]
+    fn test_parse() {
+        let script =
+            InputScript::parse("# insert a coin\n10 coin down\n\n12 coin up # done\n").unwrap();
+        assert_eq!(
+            script.events,
+            vec![(10, Input::Coin, true), (12, Input::Coin, false)]
+        );
+
+        assert!(InputScript::parse("10 coin").is_err());
+        assert!(InputScript::parse("x coin down").is_err());
+        assert!(InputScript::parse("10 nudge down").is_err());
+        assert!(InputScript::parse("10 coin sideways").is_err());
+    }
+
+    #[test]
+    fn test_run_frames() {
+        #[rustfmt::skip]
+        let rom = [
+            0xdb, 0x01,         // IN 1
+            0x32, 0x00, 0x24,   // STA 0x2400
+            0xc3, 0x00, 0x00,   // JMP 0
+        ];
+        let mut machine = ArcadeMachine::new(init_decoder(), &rom);
+        let script = InputScript::parse("2 coin down\n3 coin up").unwrap();
+
+        let mut bottom_left = Vec::new();
+        run_frames(&mut machine, &script, 5, None, |_, framebuffer| {
+            bottom_left.push(framebuffer.pixel(0, framebuffer.height() - 1));
+            Ok(())
+        })
+        .unwrap();
+
+        // The first line is latched as the frame starts, a frame behind the input
+        assert_eq!(bottom_left, vec![false, false, false, true, false]);
+    }
+
+    struct Frames(Vec<usize>);
+
+    impl AudioSink for Frames {
+        fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+            self.0.push(samples.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_run_frames_audio() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[0xc3, 0x00, 0x00]); // JMP 0
+        machine.attach_sound(Box::new(SamplePlayer::new(6000)));
+
+        let script = InputScript::new();
+        let mut sink = Frames(Vec::new());
+        run_frames(&mut machine, &script, 3, Some(&mut sink), |_, _| Ok(())).unwrap();
+
+        // 6000 samples a second at 60 frames a second
+        assert_eq!(sink.0, vec![100, 100, 100]);
+    }
+}
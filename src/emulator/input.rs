@@ -0,0 +1,79 @@
+/// The cabinet controls the game reads
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Coin,
+    Start1,
+    Fire1,
+    Left1,
+    Right1,
+    Start2,
+    Fire2,
+    Left2,
+    Right2,
+    Tilt,
+    Service,
+    Up1,
+    Down1,
+    AimUp1,
+    AimDown1,
+    Up2,
+    Down2,
+    AimUp2,
+    AimDown2,
+}
+
+impl Input {
+    pub const ALL: [Input; 19] = [
+        Input::Coin,
+        Input::Start1,
+        Input::Fire1,
+        Input::Left1,
+        Input::Right1,
+        Input::Start2,
+        Input::Fire2,
+        Input::Left2,
+        Input::Right2,
+        Input::Tilt,
+        Input::Service,
+        Input::Up1,
+        Input::Down1,
+        Input::AimUp1,
+        Input::AimDown1,
+        Input::Up2,
+        Input::Down2,
+        Input::AimUp2,
+        Input::AimDown2,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Input::Coin => "coin",
+            Input::Start1 => "start1",
+            Input::Fire1 => "fire1",
+            Input::Left1 => "left1",
+            Input::Right1 => "right1",
+            Input::Start2 => "start2",
+            Input::Fire2 => "fire2",
+            Input::Left2 => "left2",
+            Input::Right2 => "right2",
+            Input::Tilt => "tilt",
+            Input::Service => "service",
+            Input::Up1 => "up1",
+            Input::Down1 => "down1",
+            Input::AimUp1 => "aim_up1",
+            Input::AimDown1 => "aim_down1",
+            Input::Up2 => "up2",
+            Input::Down2 => "down2",
+            Input::AimUp2 => "aim_up2",
+            Input::AimDown2 => "aim_down2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Input, String> {
+        Input::ALL
+            .iter()
+            .find(|input| input.name() == name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown input: {}", name))
+    }
+}
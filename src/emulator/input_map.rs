@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+
+use emulator::drivers::{MachineDriver, SpaceInvaders};
+use emulator::input::Input;
+use emulator::ArcadeMachine;
+
+const DEFAULT_TURBO_RATE: u64 = 4;
+
+// Frame offset, input and whether it goes down
+type Steps = Vec<(u64, Input, bool)>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Hold(Input),
+    Turbo(Input),
+    Play(String),
+    Record(String),
+}
+
+#[derive(Default)]
+struct InputState {
+    held: u32,
+    turbo: u32,
+    playing: bool,
+    down: bool,
+}
+
+/// Maps host keys to cabinet inputs in front of `ArcadeMachine`.
+///
+/// Keys are plain names, so any frontend can feed it. Configuration has one
+/// entry per line, blank lines and anything after `#` are ignored:
+///
+/// * `bind <key> <input>` holds the input while the key is down, a key may
+///   drive several inputs and an input may have several keys
+/// * `turbo <key> <input>` repeatedly presses the input while the key is down
+/// * `turbo_rate <frames>` is how long each turbo press and release lasts
+/// * `macro <name> <frame> <input> down|up` adds a step to a macro
+/// * `play <key> <macro>` runs the macro when the key goes down
+/// * `record <key> <macro>` records inputs into the macro between two presses
+pub struct InputMap {
+    bindings: HashMap<String, Vec<Action>>,
+    turbo_rate: u64,
+    macros: HashMap<String, Steps>,
+
+    states: HashMap<Input, InputState>,
+    keys_down: HashSet<String>,
+    frame: u64,
+    playing: Vec<(u64, String)>,
+    recording: Option<(String, u64, Steps)>,
+    recorded: Option<String>,
+}
+
+impl InputMap {
+    pub fn new() -> InputMap {
+        InputMap {
+            bindings: HashMap::new(),
+            turbo_rate: DEFAULT_TURBO_RATE,
+            macros: HashMap::new(),
+            states: HashMap::new(),
+            keys_down: HashSet::new(),
+            frame: 0,
+            playing: Vec::new(),
+            recording: None,
+            recorded: None,
+        }
+    }
+
+    /// The keys the window always had, for both players
+    pub fn default_bindings() -> InputMap {
+        InputMap::for_driver(&SpaceInvaders::new())
+    }
+
+    /// The driver's own keys for its cabinet
+    pub fn for_driver(driver: &dyn MachineDriver) -> InputMap {
+        InputMap::parse(driver.key_bindings()).unwrap()
+    }
+
+    pub fn open(path: &str) -> Result<InputMap, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+        InputMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<InputMap, String> {
+        let mut map = InputMap::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if !line.is_empty() {
+                map.parse_line(line)
+                    .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            }
+        }
+
+        Ok(map)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+
+        match (parts[0], parts.len()) {
+            ("bind", 3) => self.bind(parts[1], Action::Hold(Input::from_name(parts[2])?)),
+            ("turbo", 3) => self.bind(parts[1], Action::Turbo(Input::from_name(parts[2])?)),
+            ("play", 3) => self.bind(parts[1], Action::Play(parts[2].to_string())),
+            ("record", 3) => self.bind(parts[1], Action::Record(parts[2].to_string())),
+            ("turbo_rate", 2) => match parts[1].parse() {
+                Ok(rate) if rate > 0 => self.turbo_rate = rate,
+                _ => return Err(format!("Bad turbo rate: {}", parts[1])),
+            },
+            ("macro", 5) => {
+                let frame = parts[2]
+                    .parse()
+                    .map_err(|_| format!("Bad frame number: {}", parts[2]))?;
+                let input = Input::from_name(parts[3])?;
+                let down = match parts[4] {
+                    "down" => true,
+                    "up" => false,
+                    s => return Err(format!("Expected down or up: {}", s)),
+                };
+                self.add_macro_step(parts[1], frame, input, down);
+            }
+            _ => return Err(format!("Can't understand: {}", line)),
+        }
+
+        Ok(())
+    }
+
+    fn bind(&mut self, key: &str, action: Action) {
+        self.bindings
+            .entry(key.to_lowercase())
+            .or_default()
+            .push(action);
+    }
+
+    pub fn bind_input(&mut self, key: &str, input: Input) {
+        self.bind(key, Action::Hold(input));
+    }
+
+    pub fn bind_turbo(&mut self, key: &str, input: Input) {
+        self.bind(key, Action::Turbo(input));
+    }
+
+    pub fn set_turbo_rate(&mut self, frames: u64) {
+        self.turbo_rate = frames.max(1);
+    }
+
+    pub fn add_macro_step(&mut self, name: &str, frame: u64, input: Input, down: bool) {
+        let steps = self.macros.entry(name.to_string()).or_default();
+        steps.push((frame, input, down));
+        steps.sort_by_key(|step| step.0);
+    }
+
+    /// The macro in the config file syntax
+    pub fn macro_text(&self, name: &str) -> Option<String> {
+        self.macros.get(name).map(|steps| {
+            steps
+                .iter()
+                .map(|&(frame, input, down)| {
+                    format!(
+                        "macro {} {} {} {}\n",
+                        name,
+                        frame,
+                        input.name(),
+                        if down { "down" } else { "up" }
+                    )
+                })
+                .collect()
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// The macro that finished recording since the last call, in the config
+    /// file syntax, so it can be kept
+    pub fn take_recorded(&mut self) -> Option<String> {
+        let name = self.recorded.take()?;
+        self.macro_text(&name)
+    }
+
+    /// Whether the key does anything here, so the frontend can leave it alone
+    pub fn is_bound(&self, key: &str) -> bool {
+        self.bindings.contains_key(&key.to_lowercase())
+    }
+
+    /// Repeats of a key that is already down are ignored
+    pub fn key_down(&mut self, key: &str, machine: &mut ArcadeMachine) {
+        if !self.keys_down.insert(key.to_lowercase()) {
+            return;
+        }
+
+        for action in self.actions(key) {
+            match action {
+                Action::Hold(input) => self.state(input).held += 1,
+                Action::Turbo(input) => self.state(input).turbo += 1,
+                Action::Play(name) => self.play(&name),
+                Action::Record(name) => self.toggle_recording(&name),
+            }
+        }
+
+        self.apply(machine);
+    }
+
+    pub fn key_up(&mut self, key: &str, machine: &mut ArcadeMachine) {
+        if !self.keys_down.remove(&key.to_lowercase()) {
+            return;
+        }
+
+        for action in self.actions(key) {
+            match action {
+                Action::Hold(input) => {
+                    let state = self.state(input);
+                    state.held = state.held.saturating_sub(1);
+                }
+                Action::Turbo(input) => {
+                    let state = self.state(input);
+                    state.turbo = state.turbo.saturating_sub(1);
+                }
+                _ => (),
+            }
+        }
+
+        self.apply(machine);
+    }
+
+    pub fn play(&mut self, name: &str) {
+        if self.macros.contains_key(name) {
+            self.playing.push((self.frame, name.to_string()));
+        }
+    }
+
+    fn toggle_recording(&mut self, name: &str) {
+        match self.recording.take() {
+            Some((name, _, steps)) => {
+                self.macros.insert(name.clone(), steps);
+                self.recorded = Some(name);
+            }
+            None => self.recording = Some((name.to_string(), self.frame, Vec::new())),
+        }
+    }
+
+    /// Moves turbo and macros on by a frame, call it once before each frame
+    pub fn update(&mut self, machine: &mut ArcadeMachine) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        let mut steps = Vec::new();
+        for &(start, ref name) in &self.playing {
+            for &(offset, input, down) in &self.macros[name] {
+                if start + offset + 1 == frame {
+                    steps.push((input, down));
+                }
+            }
+        }
+
+        for (input, down) in steps {
+            self.state(input).playing = down;
+        }
+
+        let macros = &self.macros;
+        self.playing.retain(|&(start, ref name)| {
+            macros[name]
+                .last()
+                .is_some_and(|step| start + step.0 + 1 > frame)
+        });
+
+        self.apply(machine);
+    }
+
+    fn actions(&self, key: &str) -> Vec<Action> {
+        self.bindings
+            .get(&key.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn state(&mut self, input: Input) -> &mut InputState {
+        self.states.entry(input).or_default()
+    }
+
+    // Sends every input whose combined state changed, in `Input::ALL` order
+    fn apply(&mut self, machine: &mut ArcadeMachine) {
+        let turbo_on = (self.frame / self.turbo_rate).is_multiple_of(2);
+
+        for &input in Input::ALL.iter() {
+            let state = match self.states.get_mut(&input) {
+                Some(state) => state,
+                None => continue,
+            };
+
+            let down = state.held > 0 || state.playing || (state.turbo > 0 && turbo_on);
+            if down == state.down {
+                continue;
+            }
+
+            state.down = down;
+            machine.set_input(input, down);
+
+            if let Some((_, start, ref mut steps)) = self.recording {
+                steps.push((self.frame - start, input, down));
+            }
+        }
+    }
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        InputMap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opcode_decoder::*;
+
+    fn fire(machine: &mut ArcadeMachine) -> bool {
+        machine.cpu.get_in_port(1) & 0x10 != 0
+    }
+
+    #[test]
+    fn test_multiple_bindings() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let mut map = InputMap::parse("bind Space fire1\nbind Up fire1").unwrap();
+
+        map.key_down("Space", &mut machine);
+        map.key_down("up", &mut machine);
+        map.key_up("Space", &mut machine);
+        assert!(fire(&mut machine));
+
+        map.key_up("Up", &mut machine);
+        assert!(!fire(&mut machine));
+
+        // Unbound keys do nothing
+        map.key_down("Q", &mut machine);
+        assert!(!fire(&mut machine));
+    }
+
+    #[test]
+    fn test_key_repeat() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let mut map = InputMap::parse("bind Space fire1").unwrap();
+
+        // Held keys repeat, but one release lets go
+        map.key_down("Space", &mut machine);
+        map.key_down("Space", &mut machine);
+        map.key_up("Space", &mut machine);
+        assert!(!fire(&mut machine));
+
+        // Releasing a key that never went down does nothing
+        map.key_down("Space", &mut machine);
+        map.key_up("Enter", &mut machine);
+        assert!(fire(&mut machine));
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let mut map = InputMap::parse("turbo X fire1\nturbo_rate 2").unwrap();
+
+        map.key_down("X", &mut machine);
+        let mut pattern = Vec::new();
+        for _ in 0..8 {
+            map.update(&mut machine);
+            pattern.push(fire(&mut machine));
+        }
+        assert_eq!(
+            pattern,
+            vec![true, false, false, true, true, false, false, true]
+        );
+
+        map.key_up("X", &mut machine);
+        assert!(!fire(&mut machine));
+    }
+
+    #[test]
+    fn test_macro() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let config = "
+            macro shot 0 fire1 down
+            macro shot 2 fire1 up
+            play M shot
+        ";
+        let mut map = InputMap::parse(config).unwrap();
+
+        map.key_down("M", &mut machine);
+        let mut pattern = Vec::new();
+        for _ in 0..4 {
+            map.update(&mut machine);
+            pattern.push(fire(&mut machine));
+        }
+        assert_eq!(pattern, vec![true, true, false, false]);
+        assert!(map.playing.is_empty());
+    }
+
+    #[test]
+    fn test_record() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let mut map = InputMap::parse("bind Space fire1\nrecord F5 mine").unwrap();
+
+        map.key_down("F5", &mut machine);
+        map.key_up("F5", &mut machine);
+        assert!(map.is_recording());
+        map.update(&mut machine);
+        map.key_down("Space", &mut machine);
+        map.update(&mut machine);
+        map.key_up("Space", &mut machine);
+        map.key_down("F5", &mut machine);
+        assert!(!map.is_recording());
+
+        assert_eq!(
+            map.take_recorded().unwrap(),
+            "macro mine 1 fire1 down\nmacro mine 2 fire1 up\n"
+        );
+        assert_eq!(map.take_recorded(), None);
+    }
+
+    #[test]
+    fn test_record_order() {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+        let config = "bind X right1\nbind X fire1\nbind X coin\nrecord R both";
+        let mut map = InputMap::parse(config).unwrap();
+
+        map.key_down("R", &mut machine);
+        map.key_up("R", &mut machine);
+        map.key_down("X", &mut machine);
+        map.key_down("R", &mut machine);
+
+        assert_eq!(
+            map.take_recorded().unwrap(),
+            "macro both 0 coin down\nmacro both 0 fire1 down\nmacro both 0 right1 down\n"
+        );
+        assert!(map.is_bound("x"));
+        assert!(!map.is_bound("F2"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(InputMap::parse("bind Space").is_err());
+        assert!(InputMap::parse("bind Space jump").is_err());
+        assert!(InputMap::parse("turbo_rate 0").is_err());
+        assert!(InputMap::parse("macro m x fire1 down").is_err());
+        assert!(InputMap::parse("press Space").is_err());
+        assert!(InputMap::default_bindings().actions("space") == vec![Action::Hold(Input::Fire1)]);
+    }
+}
@@ -14,6 +14,13 @@ pub fn add_8(x: u8, y: u8) -> (u8, bool, bool) {
     (result as u8, carry, acarry)
 }
 
+pub fn add_8_with_carry(x: u8, y: u8, carry: bool) -> (u8, bool, bool) {
+    let carry = carry as u16;
+    let result: u16 = (x as u16) + (y as u16) + carry;
+    let acarry = ((x & 0x0F) as u16) + ((y & 0x0F) as u16) + carry > 0x0F;
+    (result as u8, result > 0xFF, acarry)
+}
+
 pub fn add_16(x: u16, y: u16) -> (u16, bool) {
     let result: u32 = (x as u32) + (y as u32);
     let carry = (result & 0xFFFF0000) > 0;
@@ -26,6 +33,11 @@ pub fn sub_8(x: u8, y: u8) -> (u8, bool, bool) {
     (result, !(carry || c_neg), (acarry || ac_neg))
 }
 
+pub fn sub_8_with_borrow(x: u8, y: u8, borrow: bool) -> (u8, bool, bool) {
+    let (result, carry, acarry) = add_8_with_carry(x, !y, !borrow);
+    (result, !carry, acarry)
+}
+
 pub fn negate_8(x: u8) -> (u8, bool, bool) {
     let x = !x;
     add_8(x, 1)
@@ -85,7 +97,23 @@ mod test {
     fn test_sub8() {
         let (result, carry, acarry) = sub_8(5, 0);
         assert_eq!(result, 5);
+        assert!(!carry);
+        assert!(acarry);
+    }
+
+    #[test]
+    fn test_add8_with_carry() {
+        let (result, carry, acarry) = add_8_with_carry(0xFF, 0xFF, true);
+        assert_eq!(result, 0xFF);
         assert!(carry);
         assert!(acarry);
     }
+
+    #[test]
+    fn test_sub8_with_borrow() {
+        let (result, carry, acarry) = sub_8_with_borrow(0x00, 0xFF, true);
+        assert_eq!(result, 0x00);
+        assert!(carry);
+        assert!(!acarry);
+    }
 }
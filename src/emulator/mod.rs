@@ -1,185 +1,576 @@
+pub mod altair;
+pub mod cpm;
 pub mod cpu;
+pub mod devices;
+pub mod drivers;
+pub mod framebuffer;
+pub mod golden;
+pub mod headless;
+pub mod input;
+pub mod input_map;
 pub mod math;
+pub mod scheduler;
+pub mod sound;
+pub mod wav;
 
 use self::cpu::*;
+use self::drivers::{MachineDriver, SpaceInvaders};
+use self::framebuffer::{Framebuffer, PixelFormat};
+use self::input::Input;
+use self::scheduler::Scheduler;
+use self::sound::{SoundBoard, SoundGenerator};
 use opcode_decoder::*;
+use std::collections::HashSet;
 
 const CPU_HZ: i32 = 2000000;
 
-const SHIFTED_VALUE_PORT: usize = 3;
-const VALUE_TO_SHIFT_PORT: usize = 4;
-const SHIFT_BY_BITS_PORT: usize = 2;
+pub const CYCLES_PER_FRAME: u64 = 33_333;
+pub const LINES_PER_FRAME: u64 = 262;
+pub const MID_SCREEN_LINE: u64 = 96;
+pub const VBLANK_LINE: u64 = 224;
 
+/// How long the game may go without writing the watchdog, the counter on the
+/// board is clocked by vblank
+pub const WATCHDOG_FRAMES: u32 = 255;
+
+const BYTES_PER_LINE: usize = 32;
+const VRAM_LEN: usize = VBLANK_LINE as usize * BYTES_PER_LINE;
+
+/// Something for the machine to do once the CPU reaches a given cycle
+pub enum Event {
+    Rst(u8),
+    /// The beam reaching a line of the frame the cycle falls in
+    Scanline(u64),
+    Callback(Box<dyn FnOnce(&mut ArcadeMachine)>),
+}
+
+/// The Midway 8080 board running the game its `MachineDriver` describes
 pub struct ArcadeMachine {
     cpu: CPU,
+    driver: Box<dyn MachineDriver>,
+
+    cycles: u64,
+    scheduler: Scheduler<Event>,
+
+    // Video memory as the beam saw it, one line at a time
+    screen: Vec<u8>,
+    framebuffer: Framebuffer,
+
+    sound: Option<SoundBoard>,
 
-    shift_register: u16,
+    watchdog: bool,
+    watchdog_frames: u32,
+    io_logging: bool,
+    // (is OUT, port, PC) for every unhandled port access already logged
+    logged_ports: HashSet<(bool, u8, u16)>,
+    diagnostics: Vec<String>,
 }
 
 impl ArcadeMachine {
+    /// Space Invaders
     pub fn new(decoder: OpcodeDecoder, rom: &[u8]) -> ArcadeMachine {
+        ArcadeMachine::with_driver(decoder, rom, Box::new(SpaceInvaders::new()))
+    }
+
+    pub fn with_driver(
+        decoder: OpcodeDecoder,
+        rom: &[u8],
+        mut driver: Box<dyn MachineDriver>,
+    ) -> ArcadeMachine {
         let mut cpu = CPU::new(decoder);
-        cpu.set_memory(0, &rom);
+        driver.load_rom(&mut cpu, rom);
+        driver.power_on(&mut cpu);
 
-        cpu.set_in_port(0, 0b00001110);
-        cpu.set_in_port(1, 0b00001000);
-        cpu.set_in_port(2, 0b00001000);
+        let mut framebuffer = Framebuffer::new(PixelFormat::Rgba8888);
+        framebuffer.set_rotated(driver.is_rotated());
 
-        ArcadeMachine {
+        let mut machine = ArcadeMachine {
             cpu,
-            shift_register: 0,
+            driver,
+            cycles: 0,
+            scheduler: Scheduler::new(),
+            screen: vec![0; VRAM_LEN],
+            framebuffer,
+            sound: None,
+            watchdog: false,
+            watchdog_frames: 0,
+            io_logging: false,
+            logged_ports: HashSet::new(),
+            diagnostics: Vec::new(),
+        };
+
+        machine.schedule(0, Event::Scanline(0));
+        machine
+    }
+
+    pub fn driver(&self) -> &dyn MachineDriver {
+        &*self.driver
+    }
+
+    /// What the reset line does, the rest of the board keeps its state
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.watchdog_frames = 0;
+    }
+
+    /// Resets the machine when the game stops writing the driver's watchdog
+    /// port for `WATCHDOG_FRAMES` frames
+    pub fn set_watchdog(&mut self, enabled: bool) {
+        self.watchdog = enabled;
+        self.watchdog_frames = 0;
+    }
+
+    /// Notes the first IN and the first OUT from each place in the program
+    /// on each port nothing is wired to
+    pub fn set_io_logging(&mut self, enabled: bool) {
+        self.io_logging = enabled;
+    }
+
+    /// Watchdog resets and unhandled port accesses since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<String> {
+        self.diagnostics.split_off(0)
+    }
+
+    /// CPU cycles run since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Plays the sound ports through `generator`, starting silent until the
+    /// game turns the amplifier on
+    pub fn attach_sound(&mut self, generator: Box<dyn SoundGenerator>) {
+        let mut board = SoundBoard::new(generator, CPU_HZ as u64);
+        board.advance(self.cycles);
+        board.take_samples();
+        self.sound = Some(board);
+    }
+
+    /// Stops generating audio, for when nothing is left to play it
+    pub fn detach_sound(&mut self) {
+        self.sound = None;
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sound.as_ref().map(|s| s.sample_rate())
+    }
+
+    /// Audio generated so far and not yet taken, empty without a sound generator
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        match self.sound {
+            Some(ref mut sound) => sound.take_samples(),
+            None => Vec::new(),
         }
     }
 
+    pub fn schedule(&mut self, cycle: u64, event: Event) {
+        self.scheduler.schedule(cycle, event);
+    }
+
+    pub fn schedule_in(&mut self, delay: u64, event: Event) {
+        let cycle = self.cycles + delay;
+        self.scheduler.schedule(cycle, event);
+    }
+
     pub fn run(&mut self, t: f64) {
-        let mut cycles = (CPU_HZ as f64) * t;
+        let target = self.cycles + ((CPU_HZ as f64) * t) as u64;
+        self.run_until(target);
+    }
 
-        while cycles > 0.0 {
-            let cycles_spent = self.cpu.tick();
-            cycles -= cycles_spent as f64;
+    /// Runs the rest of the current frame, with the mid-screen and vblank
+    /// interrupts on the way, and returns the picture the beam drew
+    pub fn run_frame(&mut self) -> &Framebuffer {
+        let frame = self.cycles / CYCLES_PER_FRAME;
+        self.run_until((frame + 1) * CYCLES_PER_FRAME);
+
+        self.framebuffer.set_flip(self.driver.is_flipped());
+        self.framebuffer.update(&self.screen);
+        &self.framebuffer
+    }
 
-            self.update_ports();
+    /// The last frame, with the rows that changed from the one before marked dirty
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.framebuffer.set_format(format);
+        self.framebuffer.update(&self.screen);
+        self.framebuffer.mark_dirty();
+    }
+
+    /// Runs the CPU to `target`, stopping at each scheduled event on the way.
+    /// Instructions are never split, so an event fires at the first
+    /// instruction boundary at or after its cycle.
+    pub fn run_until(&mut self, target: u64) {
+        // Events due at the target itself are left for the next run
+        while self.cycles < target {
+            self.fire_due_events();
+
+            let stop = match self.scheduler.next_cycle() {
+                Some(cycle) if cycle < target => cycle,
+                _ => target,
+            };
+
+            while self.cycles < stop {
+                self.cycles += self.cpu.tick() as u64;
+
+                // Drivers only react to what the program writes or reads
+                if let Some(access) = self.cpu.take_port_access() {
+                    if self.io_logging {
+                        self.log_port_access(access);
+                    }
+                    self.update_ports();
+                }
+            }
+        }
+
+        if let Some(ref mut sound) = self.sound {
+            sound.advance(self.cycles);
         }
     }
 
-    fn update_ports(&mut self) {
-        let mut should_update_shift = false;
+    fn fire_due_events(&mut self) {
+        while let Some((cycle, event)) = self.scheduler.pop_due(self.cycles) {
+            match event {
+                Event::Rst(n) => self.cpu.signal(Interrupt::Rst(n), true),
+                Event::Scanline(line) => self.scanline(cycle / CYCLES_PER_FRAME, line),
+                Event::Callback(f) => f(self),
+            }
+        }
+    }
+
+    fn scanline(&mut self, frame: u64, line: u64) {
+        if line < VBLANK_LINE {
+            let start = line as usize * BYTES_PER_LINE;
+            let vram = self.cpu.get_memory_to_end(self.driver.vram_addr());
+            self.screen[start..start + BYTES_PER_LINE]
+                .copy_from_slice(&vram[start..start + BYTES_PER_LINE]);
+        }
 
-        match self.cpu.get_out_port(VALUE_TO_SHIFT_PORT) {
-            (v, true) => {
-                self.update_shift_register(v);
-                should_update_shift = true;
+        for &(at, n) in self.driver.interrupts() {
+            if at == line {
+                self.cpu.signal(Interrupt::Rst(n), true);
             }
-            (_, false) => (),
+        }
+
+        if line == VBLANK_LINE {
+            self.clock_watchdog();
+        }
+
+        // Nothing happens during vblank after the interrupt
+        let (frame, line) = if line < VBLANK_LINE {
+            (frame, line + 1)
+        } else {
+            (frame + 1, 0)
         };
+        self.schedule(line_cycle(frame, line), Event::Scanline(line));
+    }
 
-        match self.cpu.get_out_port(SHIFT_BY_BITS_PORT) {
-            (_, true) => should_update_shift = true,
-            (_, false) => (),
+    fn clock_watchdog(&mut self) {
+        let port = match self.driver.watchdog_port() {
+            Some(port) if self.watchdog => port,
+            _ => return,
         };
 
-        if should_update_shift {
-            self.update_shift_data();
+        self.watchdog_frames += 1;
+        if self.watchdog_frames >= WATCHDOG_FRAMES {
+            self.diagnostics.push(format!(
+                "Watchdog: OUT {} not written for {} frames, resetting at cycle {} with PC at {:#06x}",
+                port,
+                WATCHDOG_FRAMES,
+                self.cycles,
+                self.cpu.pc()
+            ));
+            self.reset();
         }
     }
 
-    fn update_shift_register(&mut self, new_val: u8) {
-        self.shift_register = self.shift_register >> 8;
-        let new_val = (new_val as u16) << 8;
-        self.shift_register = new_val | self.shift_register;
-    }
+    fn log_port_access(&mut self, access: PortAccess) {
+        let pc = self.cpu.pc();
+        let (key, message) = match access {
+            PortAccess::In(port) if !self.driver.in_ports().contains(&port) => {
+                ((false, port, pc), format!("IN {}", port))
+            }
+            PortAccess::Out(port, val) if !self.driver.out_ports().contains(&port) => {
+                ((true, port, pc), format!("OUT {}, {:#04x}", port, val))
+            }
+            _ => return,
+        };
+
+        if !self.logged_ports.insert(key) {
+            return;
+        }
 
-    fn update_shift_data(&mut self) {
-        let (shift, _) = self.cpu.get_out_port(SHIFT_BY_BITS_PORT);
-        let shift = shift & 0x07;
-        let val = (self.shift_register << shift) >> 8;
-        self.cpu.set_in_port(SHIFTED_VALUE_PORT, val as u8);
+        self.diagnostics.push(format!(
+            "Unhandled {} at cycle {} with PC at {:#06x}",
+            message, self.cycles, pc
+        ));
     }
 
-    pub fn get_render_buffer(&self) -> &[u8] {
-        self.cpu.get_memory_to_end(0x2400)
+    fn update_ports(&mut self) {
+        if let Some(port) = self.driver.watchdog_port() {
+            if let (_, true) = self.cpu.get_out_port(port) {
+                self.watchdog_frames = 0;
+            }
+        }
+
+        self.driver.update(&mut self.cpu, self.sound.as_mut(), self.cycles);
     }
 
-    pub fn signal_half_render(&mut self) {
-        self.cpu.interrupt(1);
+    pub fn set_input(&mut self, input: Input, down: bool) {
+        self.driver.set_input(&mut self.cpu, input, down);
     }
 
-    pub fn signal_finish_render(&mut self) {
-        self.cpu.interrupt(2);
+    pub fn cabinet(&self) -> Cabinet {
+        self.driver.cabinet()
     }
 
-    pub fn coin_key_toggle(&mut self, down: bool) {
-        self.cpu.set_in_port_bit(1, 0, down);
+    pub fn set_cabinet(&mut self, cabinet: Cabinet) -> Result<(), String> {
+        self.driver.set_cabinet(&mut self.cpu, cabinet)?;
+        self.framebuffer.set_flip(self.driver.is_flipped());
+        Ok(())
     }
 
-    pub fn start_p1_key_toggle(&mut self, down: bool) {
-        self.cpu.set_in_port_bit(1, 2, down);
+    /// The value of one of the driver's DIP switches
+    pub fn dip(&self, name: &str) -> Option<String> {
+        self.driver.dip(name)
     }
 
-    pub fn fire_p1_key_toggle(&mut self, down: bool) {
-        self.cpu.set_in_port_bit(1, 4, down);
+    /// Applies one `name=value` DIP switch setting. Can be done at any
+    /// time, though games may only read some switches when a game starts.
+    pub fn set_dip(&mut self, setting: &str) -> Result<(), String> {
+        self.driver.set_dip(&mut self.cpu, setting)
     }
 
-    pub fn left_p1_key_toggle(&mut self, down: bool) {
-        self.cpu.set_in_port_bit(1, 5, down);
+    /// Applies settings one per line, as in a DIP file
+    pub fn set_dips(&mut self, text: &str) -> Result<(), String> {
+        drivers::parse_settings(text, |line| self.set_dip(line))
     }
+}
 
-    pub fn right_p1_key_toggle(&mut self, down: bool) {
-        self.cpu.set_in_port_bit(1, 6, down);
+/// Whether the players share one control panel, or sit opposite each other
+/// with the picture flipped for player 2
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cabinet {
+    Upright,
+    Cocktail,
+}
+
+impl Cabinet {
+    pub fn from_name(name: &str) -> Result<Cabinet, String> {
+        match name {
+            "upright" => Ok(Cabinet::Upright),
+            "cocktail" => Ok(Cabinet::Cocktail),
+            _ => Err(format!("Unknown cabinet: {}", name)),
+        }
     }
 }
 
+/// The cycle the beam reaches `line` of `frame` at
+fn line_cycle(frame: u64, line: u64) -> u64 {
+    frame * CYCLES_PER_FRAME + line * CYCLES_PER_FRAME / LINES_PER_FRAME
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::fs::File;
-    use std::io::prelude::*;
-
-    fn init_decoder() -> OpcodeDecoder {
-        let mut opcode_data = String::new();
-        {
-            let mut opcode_file = File::open("./data/opcodes.txt").unwrap();
-            opcode_file.read_to_string(&mut opcode_data).unwrap();
-        }
-        OpcodeDecoder::new(&opcode_data)
+    use emulator::drivers::VRAM_ADDR;
+    use emulator::sound::SOUND_PORT_1;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn counting_machine() -> ArcadeMachine {
+        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+
+        machine.cpu.set_memory(0x08, &[0x04, 0xfb, 0xc9]); // INR B, EI, RET
+        machine.cpu.set_memory(0x10, &[0x0c, 0xfb, 0xc9]); // INR C, EI, RET
+        #[rustfmt::skip]
+        machine.cpu.set_memory(0x100, &[
+            0x31, 0x00, 0x02,   // LXI SP, 0x200
+            0xfb,               // EI
+            0xc3, 0x04, 0x01,   // JMP $
+        ]);
+        machine.cpu.set_pc(0x100);
+
+        machine
     }
 
     #[test]
-    fn test_shift_register() {
-        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+    fn test_scheduled_interrupts() {
+        let mut machine = counting_machine();
+        machine.schedule(2000, Event::Rst(1));
+        machine.schedule(1000, Event::Rst(1));
 
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
-        machine.update_ports();
+        machine.run_until(1500);
+        assert_eq!(machine.cpu.bc() >> 8, 1);
 
-        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110011);
+        machine.run_until(3000);
+        assert_eq!(machine.cpu.bc() >> 8, 2);
+        assert!(machine.cycles() >= 3000 && machine.cycles() < 3010);
     }
 
     #[test]
-    fn test_shift_register_offset() {
-        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+    fn test_scheduled_callback() {
+        let mut machine = counting_machine();
+        let fired_at = Rc::new(Cell::new(0));
+
+        let fired = fired_at.clone();
+        machine.schedule(
+            500,
+            Event::Callback(Box::new(move |m: &mut ArcadeMachine| {
+                fired.set(m.cycles());
+                m.schedule_in(100, Event::Rst(1));
+            })),
+        );
+
+        machine.run_until(550);
+        assert!(fired_at.get() >= 500 && fired_at.get() < 510);
+        assert_eq!(machine.cpu.bc() >> 8, 0);
+
+        machine.run_until(700);
+        assert_eq!(machine.cpu.bc() >> 8, 1);
+    }
 
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
-        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
-        machine.update_ports();
+    #[test]
+    fn test_frame_interrupts() {
+        let mut machine = counting_machine();
+        let mid_screen = line_cycle(0, MID_SCREEN_LINE);
+        let vblank = line_cycle(0, VBLANK_LINE);
+
+        machine.run_until(mid_screen - 20);
+        assert_eq!(machine.cpu.bc(), 0x0000);
+
+        machine.run_until(mid_screen + 20);
+        assert_eq!(machine.cpu.bc(), 0x0100);
+
+        machine.run_until(vblank + 20);
+        assert_eq!(machine.cpu.bc(), 0x0101);
 
-        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b10011000);
+        machine.run_frame();
+        assert!(machine.cycles() >= CYCLES_PER_FRAME);
+        assert!(machine.cycles() < CYCLES_PER_FRAME + 20);
+
+        for _ in 0..9 {
+            machine.run_frame();
+        }
+        assert_eq!(machine.cpu.bc(), 0x0a0a);
+        assert!(machine.cycles() >= 10 * CYCLES_PER_FRAME);
     }
 
     #[test]
-    fn test_shift_register_multiple() {
-        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
+    fn test_scanline_latching() {
+        let mut machine = counting_machine();
+        let line_addr = |line: u16| VRAM_ADDR + line * BYTES_PER_LINE as u16;
 
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01110011);
-        machine.update_ports();
+        machine.cpu.set_memory(line_addr(10), &[0xff; BYTES_PER_LINE]);
+        machine.run_until(line_cycle(0, 11));
 
-        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110011);
+        // Too late for line 10, in time for line 200
+        machine.cpu.set_memory(line_addr(10), &[0x00; BYTES_PER_LINE]);
+        machine.cpu.set_memory(line_addr(200), &[0xaa; BYTES_PER_LINE]);
 
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0b01010101);
+        machine.run_frame();
+        assert_eq!(machine.screen[10 * BYTES_PER_LINE], 0xff);
+        assert_eq!(machine.screen[200 * BYTES_PER_LINE + 5], 0xaa);
 
-        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
-        machine.cpu.get_out_port(SHIFT_BY_BITS_PORT);
+        machine.run_frame();
+        assert_eq!(machine.screen[10 * BYTES_PER_LINE], 0x00);
+    }
 
-        machine.update_ports();
+    #[test]
+    fn test_framebuffer() {
+        let mut machine = counting_machine();
+        machine.set_pixel_format(PixelFormat::Mono1);
+        machine.cpu.set_memory(VRAM_ADDR, &[0x01]);
+
+        let framebuffer = machine.run_frame();
+        assert!(framebuffer.pixel(0, framebuffer.height() - 1));
+        assert_eq!(framebuffer.dirty_rows(), vec![framebuffer.height() - 1]);
 
-        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b10101011);
+        assert!(machine.run_frame().dirty_rows().is_empty());
     }
 
     #[test]
-    fn test_shift_register_multiple2() {
-        let mut machine = ArcadeMachine::new(init_decoder(), &[]);
-
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x38);
-        machine.update_ports();
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0xF1);
-        machine.update_ports();
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0xFF);
-        machine.update_ports();
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x80);
-        machine.update_ports();
-        machine.cpu.set_out_port(VALUE_TO_SHIFT_PORT, 0x0E);
-        machine.cpu.set_out_port(SHIFT_BY_BITS_PORT, 3);
+    fn test_sound_ports() {
+        let mut machine = counting_machine();
+        let mut player = sound::SamplePlayer::new(CPU_HZ as u32 / 100);
+        let mut shot = wav::Wav::new(CPU_HZ as u32 / 100);
+        shot.samples = vec![1.0; 10];
+        player.set_sample(sound::Sound::Shot, &shot);
+        machine.attach_sound(Box::new(player));
+
+        machine.run_until(1000);
+        machine.cpu.set_out_port(SOUND_PORT_1, 0x22); // amplifier on, shot
         machine.update_ports();
+        machine.run_until(3000);
+
+        let audio = machine.take_audio();
+        assert_eq!(audio.len(), 30);
+        assert!(audio[..10].iter().all(|&s| s == 0.0));
+        assert!(audio[10..20].iter().all(|&s| s > 0.0));
+        assert!(audio[20..].iter().all(|&s| s == 0.0));
+
+        machine.detach_sound();
+        machine.run_until(4000);
+        assert_eq!(machine.sample_rate(), None);
+        assert!(machine.take_audio().is_empty());
+    }
 
-        assert_eq!(machine.cpu.get_in_port(SHIFTED_VALUE_PORT), 0b01110100);
+    #[test]
+    fn test_watchdog() {
+        #[rustfmt::skip]
+        let rom = [
+            0x31, 0x00, 0x24,   // LXI SP, 0x2400
+            0x3a, 0x00, 0x20,   // LDA 0x2000
+            0x3c,               // INR A
+            0x32, 0x00, 0x20,   // STA 0x2000
+            0xd3, 0x06,         // OUT 6
+            0xc3, 0x0c, 0x00,   // JMP $
+        ];
+        let mut machine = ArcadeMachine::new(init_decoder(), &rom);
+        machine.set_watchdog(true);
+
+        for _ in 0..WATCHDOG_FRAMES - 1 {
+            machine.run_frame();
+        }
+        assert_eq!(machine.cpu.get_memory(0x2000), 1);
+        assert!(machine.take_diagnostics().is_empty());
+
+        machine.run_frame();
+        machine.run_frame();
+        assert_eq!(machine.cpu.get_memory(0x2000), 2);
+
+        let diagnostics = machine.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].starts_with("Watchdog"));
+    }
+
+    #[test]
+    fn test_io_logging() {
+        #[rustfmt::skip]
+        let rom = [
+            0xdb, 0x01,         // IN 1
+            0xd3, 0x03,         // OUT 3
+            0x06, 0x02,         // MVI B, 2
+            0xdb, 0x07,         // IN 7
+            0xd3, 0x09,         // OUT 9
+            0x05,               // DCR B
+            0xc2, 0x06, 0x00,   // JNZ 6
+            0xdb, 0x07,         // IN 7
+            0xdb, 0x09,         // IN 9
+            0x76,               // HLT
+        ];
+        let mut machine = ArcadeMachine::new(init_decoder(), &rom);
+        machine.set_io_logging(true);
+        machine.run_until(200);
+
+        let diagnostics = machine.take_diagnostics();
+        assert_eq!(diagnostics.len(), 4);
+        assert!(diagnostics[0].starts_with("Unhandled IN 7"));
+        assert!(diagnostics[0].ends_with("PC at 0x0008"));
+        assert!(diagnostics[1].starts_with("Unhandled OUT 9"));
+        assert!(diagnostics[2].starts_with("Unhandled IN 7"));
+        assert!(diagnostics[2].ends_with("PC at 0x0010"));
+        assert!(diagnostics[3].starts_with("Unhandled IN 9"));
     }
 }
@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+struct Entry<E> {
+    cycle: u64,
+    seq: u64,
+    event: E,
+}
+
+impl<E> PartialEq for Entry<E> {
+    fn eq(&self, other: &Entry<E>) -> bool {
+        self.cycle == other.cycle && self.seq == other.seq
+    }
+}
+
+impl<E> Eq for Entry<E> {}
+
+impl<E> PartialOrd for Entry<E> {
+    fn partial_cmp(&self, other: &Entry<E>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Entry<E> {
+    // Reversed, so the heap pops the earliest cycle first and events on the
+    // same cycle in the order they were scheduled
+    fn cmp(&self, other: &Entry<E>) -> Ordering {
+        (other.cycle, other.seq).cmp(&(self.cycle, self.seq))
+    }
+}
+
+/// A queue of events keyed on absolute CPU cycle.
+///
+/// The owner runs the CPU up to `next_cycle` and then takes everything that
+/// became due with `pop_due`. So far only `ArcadeMachine` has one, for the
+/// beam and its interrupts.
+pub struct Scheduler<E> {
+    queue: BinaryHeap<Entry<E>>,
+    seq: u64,
+}
+
+impl<E> Scheduler<E> {
+    pub fn new() -> Scheduler<E> {
+        Scheduler {
+            queue: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub fn schedule(&mut self, cycle: u64, event: E) {
+        self.queue.push(Entry {
+            cycle,
+            seq: self.seq,
+            event,
+        });
+        self.seq += 1;
+    }
+
+    /// The cycle of the earliest pending event
+    pub fn next_cycle(&self) -> Option<u64> {
+        self.queue.peek().map(|entry| entry.cycle)
+    }
+
+    /// Removes the earliest event if it is due at or before `now`
+    pub fn pop_due(&mut self, now: u64) -> Option<(u64, E)> {
+        if self.next_cycle()? > now {
+            return None;
+        }
+
+        self.queue.pop().map(|entry| (entry.cycle, entry.event))
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Scheduler<E> {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(300, "c");
+        scheduler.schedule(100, "a");
+        scheduler.schedule(200, "b1");
+        scheduler.schedule(200, "b2");
+
+        assert_eq!(scheduler.next_cycle(), Some(100));
+        assert_eq!(scheduler.pop_due(99), None);
+        assert_eq!(scheduler.pop_due(100), Some((100, "a")));
+        assert_eq!(scheduler.pop_due(250), Some((200, "b1")));
+        assert_eq!(scheduler.pop_due(250), Some((200, "b2")));
+        assert_eq!(scheduler.pop_due(250), None);
+        assert_eq!(scheduler.len(), 1);
+    }
+}
@@ -0,0 +1,232 @@
+pub mod samples;
+pub mod sink;
+pub mod synth;
+
+pub use self::samples::SamplePlayer;
+pub use self::sink::{AudioSink, PipeSink, WavSink};
+pub use self::synth::Synth;
+
+pub const SOUND_PORT_1: usize = 3;
+pub const SOUND_PORT_2: usize = 5;
+
+const AMP_ENABLE: u8 = 0x20;
+
+/// The sounds the game triggers through OUT 3 and OUT 5
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraLife,
+    /// The four notes of the marching fleet, 1 to 4
+    Fleet(u8),
+    UfoHit,
+}
+
+impl Sound {
+    pub const ALL: [Sound; 10] = [
+        Sound::Ufo,
+        Sound::Shot,
+        Sound::PlayerDeath,
+        Sound::InvaderDeath,
+        Sound::Fleet(1),
+        Sound::Fleet(2),
+        Sound::Fleet(3),
+        Sound::Fleet(4),
+        Sound::UfoHit,
+        Sound::ExtraLife,
+    ];
+
+    /// The number of the sound in the usual sample set, 0.wav to 9.wav
+    pub fn index(self) -> usize {
+        match self {
+            Sound::Ufo => 0,
+            Sound::Shot => 1,
+            Sound::PlayerDeath => 2,
+            Sound::InvaderDeath => 3,
+            Sound::Fleet(n) => 3 + n as usize,
+            Sound::UfoHit => 8,
+            Sound::ExtraLife => 9,
+        }
+    }
+
+    fn from_port_bit(port: usize, bit: u8) -> Option<Sound> {
+        match (port, bit) {
+            (SOUND_PORT_1, 0) => Some(Sound::Ufo),
+            (SOUND_PORT_1, 1) => Some(Sound::Shot),
+            (SOUND_PORT_1, 2) => Some(Sound::PlayerDeath),
+            (SOUND_PORT_1, 3) => Some(Sound::InvaderDeath),
+            (SOUND_PORT_1, 4) => Some(Sound::ExtraLife),
+            (SOUND_PORT_2, 0..=3) => Some(Sound::Fleet(bit + 1)),
+            (SOUND_PORT_2, 4) => Some(Sound::UfoHit),
+            _ => None,
+        }
+    }
+}
+
+/// Something that turns sound triggers into audio
+pub trait SoundGenerator {
+    fn sample_rate(&self) -> u32;
+
+    /// The trigger bit for `sound` went high
+    fn start(&mut self, sound: Sound);
+
+    /// The trigger bit for `sound` went low
+    fn stop(&mut self, sound: Sound);
+
+    /// Fills `out` with the next samples
+    fn render(&mut self, out: &mut [f32]);
+}
+
+/// Watches the sound ports for edges and keeps the generator's output in
+/// step with the CPU, so a trigger lands on the sample it happened at.
+pub struct SoundBoard {
+    generator: Box<dyn SoundGenerator>,
+    cpu_hz: u64,
+
+    port1: u8,
+    port2: u8,
+
+    samples_out: u64,
+    buffer: Vec<f32>,
+}
+
+impl SoundBoard {
+    pub fn new(generator: Box<dyn SoundGenerator>, cpu_hz: u64) -> SoundBoard {
+        SoundBoard {
+            generator,
+            cpu_hz,
+            port1: 0,
+            port2: 0,
+            samples_out: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.generator.sample_rate()
+    }
+
+    /// Renders audio up to `cycle`
+    pub fn advance(&mut self, cycle: u64) {
+        let due = cycle * self.sample_rate() as u64 / self.cpu_hz;
+        if due <= self.samples_out {
+            return;
+        }
+
+        let start = self.buffer.len();
+        self.buffer
+            .resize(start + (due - self.samples_out) as usize, 0.0);
+        self.generator.render(&mut self.buffer[start..]);
+
+        // The amplifier is off until the game enables it
+        if self.port1 & AMP_ENABLE == 0 {
+            for sample in &mut self.buffer[start..] {
+                *sample = 0.0;
+            }
+        }
+
+        self.samples_out = due;
+    }
+
+    /// The CPU wrote `val` to a sound port at `cycle`
+    pub fn write(&mut self, port: usize, val: u8, cycle: u64) {
+        self.advance(cycle);
+
+        let old = match port {
+            SOUND_PORT_1 => &mut self.port1,
+            SOUND_PORT_2 => &mut self.port2,
+            _ => return,
+        };
+        let changed = *old ^ val;
+        *old = val;
+
+        for bit in 0..8 {
+            if changed & (1 << bit) == 0 {
+                continue;
+            }
+
+            if let Some(sound) = Sound::from_port_bit(port, bit) {
+                if val & (1 << bit) != 0 {
+                    self.generator.start(sound);
+                } else {
+                    self.generator.stop(sound);
+                }
+            }
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.buffer.split_off(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder {
+        events: Rc<RefCell<Vec<(Sound, bool)>>>,
+    }
+
+    impl SoundGenerator for Recorder {
+        fn sample_rate(&self) -> u32 {
+            1000
+        }
+
+        fn start(&mut self, sound: Sound) {
+            self.events.borrow_mut().push((sound, true));
+        }
+
+        fn stop(&mut self, sound: Sound) {
+            self.events.borrow_mut().push((sound, false));
+        }
+
+        fn render(&mut self, out: &mut [f32]) {
+            for sample in out.iter_mut() {
+                *sample = 1.0;
+            }
+        }
+    }
+
+    #[test]
+    fn test_edges_and_timing() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Recorder {
+            events: events.clone(),
+        };
+        let mut board = SoundBoard::new(Box::new(recorder), 2000);
+
+        board.advance(200);
+        assert_eq!(board.take_samples(), vec![0.0; 100]);
+
+        board.write(SOUND_PORT_1, AMP_ENABLE | 0x03, 200);
+        board.write(SOUND_PORT_1, AMP_ENABLE | 0x01, 210);
+        board.write(SOUND_PORT_2, 0x10, 220);
+        board.advance(400);
+
+        let samples = board.take_samples();
+        assert_eq!(samples.len(), 100);
+        assert!(samples.iter().all(|&s| s == 1.0));
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                (Sound::Ufo, true),
+                (Sound::Shot, true),
+                (Sound::Shot, false),
+                (Sound::UfoHit, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sound_numbers() {
+        for (i, sound) in Sound::ALL.iter().enumerate() {
+            assert_eq!(sound.index(), i);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+
+use emulator::sound::{Sound, SoundGenerator};
+use emulator::wav::Wav;
+
+const MIX_LEVEL: f32 = 0.5;
+
+struct Voice {
+    index: usize,
+    pos: usize,
+}
+
+/// Plays recorded samples, 0.wav to 9.wav in the usual numbering.
+///
+/// The UFO loops for as long as its bit stays high, every other sound plays
+/// once to the end from its rising edge.
+pub struct SamplePlayer {
+    sample_rate: u32,
+    samples: Vec<Vec<f32>>,
+    voices: Vec<Voice>,
+}
+
+impl SamplePlayer {
+    pub fn new(sample_rate: u32) -> SamplePlayer {
+        SamplePlayer {
+            sample_rate,
+            samples: vec![Vec::new(); Sound::ALL.len()],
+            voices: Vec::new(),
+        }
+    }
+
+    /// Loads whatever of the set is in `dir`, missing sounds stay silent
+    pub fn open(dir: &str, sample_rate: u32) -> Result<SamplePlayer, String> {
+        let mut player = SamplePlayer::new(sample_rate);
+        let mut found = 0;
+
+        for &sound in Sound::ALL.iter() {
+            let path = Path::new(dir).join(format!("{}.wav", sound.index()));
+            if path.exists() {
+                player.set_sample(sound, &Wav::open(&path.to_string_lossy())?);
+                found += 1;
+            }
+        }
+
+        if found == 0 {
+            return Err(format!("No samples found in {}", dir));
+        }
+
+        Ok(player)
+    }
+
+    pub fn set_sample(&mut self, sound: Sound, wav: &Wav) {
+        self.samples[sound.index()] = resample(wav, self.sample_rate);
+    }
+
+    pub fn is_playing(&self, sound: Sound) -> bool {
+        self.voices.iter().any(|v| v.index == sound.index())
+    }
+}
+
+impl SoundGenerator for SamplePlayer {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, sound: Sound) {
+        let index = sound.index();
+        self.voices.retain(|v| v.index != index);
+        self.voices.push(Voice { index, pos: 0 });
+    }
+
+    fn stop(&mut self, sound: Sound) {
+        if sound == Sound::Ufo {
+            self.voices.retain(|v| v.index != sound.index());
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let looping = Sound::Ufo.index();
+
+        for sample in out.iter_mut() {
+            let mut mix = 0.0;
+
+            for voice in &mut self.voices {
+                let data = &self.samples[voice.index];
+                if voice.index == looping && voice.pos >= data.len() {
+                    voice.pos = 0;
+                }
+
+                if let Some(v) = data.get(voice.pos) {
+                    mix += v;
+                }
+                voice.pos += 1;
+            }
+
+            let samples = &self.samples;
+            self.voices.retain(|v| {
+                (v.index == looping && !samples[looping].is_empty())
+                    || v.pos < samples[v.index].len()
+            });
+
+            *sample = (mix * MIX_LEVEL).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Converts to the output rate by linear interpolation
+fn resample(wav: &Wav, sample_rate: u32) -> Vec<f32> {
+    if wav.sample_rate == sample_rate || wav.samples.is_empty() {
+        return wav.samples.clone();
+    }
+
+    let step = wav.sample_rate as f64 / sample_rate as f64;
+    let len = (wav.samples.len() as f64 / step) as usize;
+
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let j = pos as usize;
+            let frac = (pos - j as f64) as f32;
+            let a = wav.samples[j];
+            let b = *wav.samples.get(j + 1).unwrap_or(&a);
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wav(samples: &[f32]) -> Wav {
+        let mut wav = Wav::new(1000);
+        wav.samples = samples.to_vec();
+        wav
+    }
+
+    fn render(player: &mut SamplePlayer, len: usize) -> Vec<f32> {
+        let mut out = vec![0.0; len];
+        player.render(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let mut player = SamplePlayer::new(1000);
+        player.set_sample(Sound::Shot, &wav(&[1.0, 1.0]));
+
+        player.start(Sound::Shot);
+        player.stop(Sound::Shot);
+        assert_eq!(render(&mut player, 4), vec![0.5, 0.5, 0.0, 0.0]);
+        assert!(!player.is_playing(Sound::Shot));
+
+        // A new edge restarts the sample
+        player.start(Sound::Shot);
+        render(&mut player, 1);
+        player.start(Sound::Shot);
+        assert_eq!(render(&mut player, 3), vec![0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_ufo_loop() {
+        let mut player = SamplePlayer::new(1000);
+        player.set_sample(Sound::Ufo, &wav(&[1.0, 0.0, -1.0]));
+        player.set_sample(Sound::InvaderDeath, &wav(&[1.0]));
+
+        player.start(Sound::Ufo);
+        player.start(Sound::InvaderDeath);
+        assert_eq!(
+            render(&mut player, 7),
+            vec![1.0, 0.0, -0.5, 0.5, 0.0, -0.5, 0.5]
+        );
+
+        player.stop(Sound::Ufo);
+        assert_eq!(render(&mut player, 2), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_resample() {
+        let samples = resample(&wav(&[0.0, 1.0, 0.0, -1.0]), 2000);
+        assert_eq!(samples, vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+    }
+}
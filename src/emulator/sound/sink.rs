@@ -0,0 +1,122 @@
+use std::io::prelude::*;
+use std::process::{Child, Command, Stdio};
+
+use emulator::wav::WavWriter;
+
+/// Where generated audio goes
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    /// Called once no more audio is coming
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Writes a WAV file as the audio comes, so it is complete whenever the
+/// program stops
+pub struct WavSink {
+    writer: WavWriter,
+}
+
+impl WavSink {
+    pub fn create(path: &str, sample_rate: u32) -> Result<WavSink, String> {
+        Ok(WavSink {
+            writer: WavWriter::create(path, sample_rate)?,
+        })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.writer.append(samples)
+    }
+}
+
+/// Plays on the host by piping 16 bit little endian mono PCM into a player
+/// such as `aplay -q -t raw -f S16_LE -c 1 -r 44100`. The pipe blocks once the
+/// player is a buffer ahead, which paces the emulator to the sound card.
+pub struct PipeSink {
+    child: Child,
+}
+
+impl PipeSink {
+    /// `command` is split on whitespace, `{rate}` is replaced by the sample rate
+    pub fn spawn(command: &str, sample_rate: u32) -> Result<PipeSink, String> {
+        let command = command.replace("{rate}", &sample_rate.to_string());
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or("Empty audio command")?;
+
+        let child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+
+        Ok(PipeSink { child })
+    }
+
+    pub fn default_command() -> &'static str {
+        "aplay -q -t raw -f S16_LE -c 1 -r {rate}"
+    }
+}
+
+impl AudioSink for PipeSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let val = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
+            data.extend_from_slice(&[val as u8, (val >> 8) as u8]);
+        }
+
+        match self.child.stdin {
+            Some(ref mut stdin) => stdin
+                .write_all(&data)
+                .map_err(|e| format!("Audio player went away: {}", e)),
+            None => Err("Audio player has no input".to_string()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        drop(self.child.stdin.take());
+        self.child
+            .wait()
+            .map(|_| ())
+            .map_err(|e| format!("Audio player failed: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emulator::wav::Wav;
+
+    #[test]
+    fn test_wav_sink() {
+        let path = ::std::env::temp_dir().join(format!("e8080-sink-{}.wav", ::std::process::id()));
+        let path = path.to_string_lossy();
+
+        let mut sink = WavSink::create(&path, 8000).unwrap();
+        sink.write(&[0.5, -0.5]).unwrap();
+        sink.write(&[0.25]).unwrap();
+
+        let wav = Wav::open(&path).unwrap();
+        ::std::fs::remove_file(&*path).unwrap();
+
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.samples.len(), 3);
+        assert!((wav.samples[1] + 0.5).abs() < 0.001);
+        assert!((wav.samples[2] - 0.25).abs() < 0.001);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipe_sink() {
+        let mut sink = PipeSink::spawn("cat", 8000).unwrap();
+        sink.write(&[0.0, 1.0]).unwrap();
+        sink.finish().unwrap();
+
+        assert!(PipeSink::spawn("", 8000).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use emulator::sound::{Sound, SoundGenerator};
+
+const MASTER_LEVEL: f32 = 0.5;
+
+// Note pitches of the four fleet steps, in Hz
+const FLEET_FREQS: [f32; 4] = [98.0, 87.0, 78.0, 69.0];
+
+/// An exponential decay that falls to about a third every `tau` seconds
+#[derive(Copy, Clone)]
+struct Envelope {
+    level: f32,
+    factor: f32,
+}
+
+impl Envelope {
+    fn new(tau: f32, sample_rate: u32) -> Envelope {
+        Envelope {
+            level: 0.0,
+            factor: (-1.0 / (tau * sample_rate as f32)).exp(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.level = 1.0;
+    }
+
+    fn next(&mut self) -> f32 {
+        let level = self.level;
+        self.level *= self.factor;
+        if self.level < 1e-4 {
+            self.level = 0.0;
+        }
+        level
+    }
+}
+
+/// A one pole low pass, the RC filters on the board
+#[derive(Copy, Clone)]
+struct LowPass {
+    out: f32,
+    alpha: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: u32) -> LowPass {
+        let rc = 1.0 / (2.0 * ::std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        LowPass {
+            out: 0.0,
+            alpha: dt / (rc + dt),
+        }
+    }
+
+    fn next(&mut self, input: f32) -> f32 {
+        self.out += self.alpha * (input - self.out);
+        self.out
+    }
+}
+
+/// Phase accumulator for square and triangle waves
+#[derive(Copy, Clone, Default)]
+struct Oscillator {
+    phase: f32,
+}
+
+impl Oscillator {
+    fn step(&mut self, freq: f32, sample_rate: u32) {
+        self.phase += freq / sample_rate as f32;
+        self.phase -= self.phase.floor();
+    }
+
+    fn square(&self) -> f32 {
+        if self.phase < 0.5 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    /// Between 0 and 1
+    fn triangle(&self) -> f32 {
+        if self.phase < 0.5 {
+            self.phase * 2.0
+        } else {
+            2.0 - self.phase * 2.0
+        }
+    }
+}
+
+/// Models the analog sound board instead of playing samples.
+///
+/// A 17 bit shift register stands in for the noise transistor, so the output
+/// only depends on the sample rate and the triggers. The UFO is the SN76477:
+/// a VCO swept by its slow triangle oscillator, running while the bit is high.
+/// Shots and explosions are filtered noise under a decaying envelope, the
+/// fleet notes are decaying square waves.
+pub struct Synth {
+    sample_rate: u32,
+    noise: u32,
+
+    ufo_on: bool,
+    ufo_slf: Oscillator,
+    ufo_vco: Oscillator,
+
+    shot: Envelope,
+    shot_filter: LowPass,
+    shot_tone: Oscillator,
+
+    player_death: Envelope,
+    player_death_filter: LowPass,
+
+    invader_death: Envelope,
+    invader_death_filter: LowPass,
+
+    fleet: Envelope,
+    fleet_note: usize,
+    fleet_osc: Oscillator,
+    fleet_filter: LowPass,
+
+    ufo_hit: Envelope,
+    ufo_hit_lfo: Oscillator,
+    ufo_hit_osc: Oscillator,
+
+    extra_life_left: u32,
+    extra_life_gate: Oscillator,
+    extra_life_osc: Oscillator,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> Synth {
+        Synth {
+            sample_rate,
+            noise: 1,
+            ufo_on: false,
+            ufo_slf: Oscillator::default(),
+            ufo_vco: Oscillator::default(),
+            shot: Envelope::new(0.15, sample_rate),
+            shot_filter: LowPass::new(3000.0, sample_rate),
+            shot_tone: Oscillator::default(),
+            player_death: Envelope::new(0.6, sample_rate),
+            player_death_filter: LowPass::new(600.0, sample_rate),
+            invader_death: Envelope::new(0.08, sample_rate),
+            invader_death_filter: LowPass::new(2000.0, sample_rate),
+            fleet: Envelope::new(0.05, sample_rate),
+            fleet_note: 0,
+            fleet_osc: Oscillator::default(),
+            fleet_filter: LowPass::new(400.0, sample_rate),
+            ufo_hit: Envelope::new(0.4, sample_rate),
+            ufo_hit_lfo: Oscillator::default(),
+            ufo_hit_osc: Oscillator::default(),
+            extra_life_left: 0,
+            extra_life_gate: Oscillator::default(),
+            extra_life_osc: Oscillator::default(),
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        let bit = (self.noise ^ (self.noise >> 3)) & 1;
+        self.noise = (self.noise >> 1) | (bit << 16);
+
+        if self.noise & 1 != 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let rate = self.sample_rate;
+        let noise = self.next_noise();
+        let mut mix = 0.0;
+
+        if self.ufo_on {
+            self.ufo_slf.step(5.0, rate);
+            self.ufo_vco
+                .step(400.0 + 700.0 * self.ufo_slf.triangle(), rate);
+            mix += 0.25 * self.ufo_vco.square();
+        }
+
+        let level = self.shot.next();
+        let filtered = self.shot_filter.next(noise);
+        self.shot_tone.step(300.0 + 1200.0 * level, rate);
+        mix += level * (0.5 * filtered + 0.2 * self.shot_tone.square());
+
+        let level = self.player_death.next();
+        mix += level * 0.8 * self.player_death_filter.next(noise);
+
+        let level = self.invader_death.next();
+        mix += level * 0.6 * self.invader_death_filter.next(noise);
+
+        let level = self.fleet.next();
+        self.fleet_osc.step(FLEET_FREQS[self.fleet_note], rate);
+        mix += level * 0.6 * self.fleet_filter.next(self.fleet_osc.square());
+
+        let level = self.ufo_hit.next();
+        if level > 0.0 {
+            self.ufo_hit_lfo.step(12.0, rate);
+            let freq = 200.0 + 800.0 * level * (0.5 + 0.5 * self.ufo_hit_lfo.triangle());
+            self.ufo_hit_osc.step(freq, rate);
+            mix += level * 0.3 * self.ufo_hit_osc.square();
+        }
+
+        if self.extra_life_left > 0 {
+            self.extra_life_left -= 1;
+            self.extra_life_gate.step(8.0, rate);
+            self.extra_life_osc.step(1000.0, rate);
+            if self.extra_life_gate.phase < 0.5 {
+                mix += 0.2 * self.extra_life_osc.square();
+            }
+        }
+
+        (mix * MASTER_LEVEL).clamp(-1.0, 1.0)
+    }
+}
+
+impl SoundGenerator for Synth {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn start(&mut self, sound: Sound) {
+        match sound {
+            Sound::Ufo => self.ufo_on = true,
+            Sound::Shot => self.shot.trigger(),
+            Sound::PlayerDeath => self.player_death.trigger(),
+            Sound::InvaderDeath => self.invader_death.trigger(),
+            Sound::ExtraLife => {
+                self.extra_life_left = self.sample_rate;
+                self.extra_life_gate = Oscillator::default();
+            }
+            Sound::Fleet(n) => {
+                self.fleet_note = (n as usize).clamp(1, 4) - 1;
+                self.fleet_osc = Oscillator::default();
+                self.fleet.trigger();
+            }
+            Sound::UfoHit => self.ufo_hit.trigger(),
+        }
+    }
+
+    fn stop(&mut self, sound: Sound) {
+        if sound == Sound::Ufo {
+            self.ufo_on = false;
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    fn render(synth: &mut Synth, len: usize) -> Vec<f32> {
+        let mut out = vec![0.0; len];
+        synth.render(&mut out);
+        out
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32
+    }
+
+    // A bit of everything, with edges in the middle of buffers
+    fn play(synth: &mut Synth) -> Vec<f32> {
+        let mut out = Vec::new();
+        synth.start(Sound::Ufo);
+        out.extend(render(synth, 1000));
+        synth.start(Sound::Shot);
+        synth.start(Sound::Fleet(2));
+        out.extend(render(synth, 777));
+        synth.stop(Sound::Ufo);
+        synth.start(Sound::InvaderDeath);
+        synth.start(Sound::UfoHit);
+        out.extend(render(synth, 2000));
+        out
+    }
+
+    // FNV-1a of the samples as 16 bit PCM
+    fn checksum(samples: &[f32]) -> u64 {
+        samples.iter().fold(0xcbf2_9ce4_8422_2325, |hash, s| {
+            let val = (s.clamp(-1.0, 1.0) * 32767.0) as i16 as u16;
+            let hash = (hash ^ (val & 0xff) as u64).wrapping_mul(0x0100_0000_01b3);
+            (hash ^ (val >> 8) as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let first = play(&mut Synth::new(RATE));
+        let second = play(&mut Synth::new(RATE));
+
+        assert_eq!(first.len(), 3777);
+        assert_eq!(first, second);
+        assert!(first.iter().all(|s| s.abs() <= 1.0));
+        assert_eq!(checksum(&first), 0xeac8_ed86_60fd_b97b);
+    }
+
+    #[test]
+    fn test_silent_until_triggered() {
+        let mut synth = Synth::new(RATE);
+        assert!(render(&mut synth, 1000).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_every_sound() {
+        for &sound in Sound::ALL.iter() {
+            let mut synth = Synth::new(RATE);
+            synth.start(sound);
+            let start = render(&mut synth, RATE as usize / 10);
+            assert!(energy(&start) > 1e-3, "{:?} is silent", sound);
+
+            if sound != Sound::Ufo {
+                // Everything but the UFO dies away on its own
+                synth.stop(sound);
+                render(&mut synth, RATE as usize * 4);
+                let end = render(&mut synth, RATE as usize / 10);
+                assert!(energy(&end) < 1e-6, "{:?} keeps going", sound);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ufo_runs_while_high() {
+        let mut synth = Synth::new(RATE);
+        synth.start(Sound::Ufo);
+        render(&mut synth, RATE as usize * 2);
+        assert!(energy(&render(&mut synth, 800)) > 1e-3);
+
+        synth.stop(Sound::Ufo);
+        assert_eq!(energy(&render(&mut synth, 800)), 0.0);
+    }
+}
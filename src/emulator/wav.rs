@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+/// Mono PCM audio with samples between -1.0 and 1.0
+#[derive(Clone)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn new(sample_rate: u32) -> Wav {
+        Wav {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn open(path: &str) -> Result<Wav, String> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+
+        Wav::from_bytes(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Reads 8 or 16 bit PCM, channels get mixed down to mono
+    pub fn from_bytes(data: &[u8]) -> Result<Wav, String> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err("Not a WAV file".to_string());
+        }
+
+        let mut pos = 12;
+        let mut format = None;
+
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let len = read_u32(data, pos + 4) as usize;
+            let body_start = pos + 8;
+            let body_end = data.len().min(body_start + len);
+            let body = &data[body_start..body_end];
+
+            match id {
+                b"fmt " => {
+                    if body.len() < 16 {
+                        return Err("WAV format chunk is too short".to_string());
+                    }
+
+                    let audio_format = read_u16(body, 0);
+                    let channels = read_u16(body, 2) as usize;
+                    let sample_rate = read_u32(body, 4);
+                    let bits = read_u16(body, 14);
+
+                    if audio_format != 1 || (bits != 8 && bits != 16) || channels == 0 {
+                        return Err("Only 8 and 16 bit PCM WAV files are supported".to_string());
+                    }
+
+                    format = Some((channels, sample_rate, bits));
+                }
+                b"data" => {
+                    let (channels, sample_rate, bits) = match format {
+                        Some(f) => f,
+                        None => return Err("WAV data before format".to_string()),
+                    };
+
+                    let frame_size = channels * bits as usize / 8;
+                    let mut samples = Vec::with_capacity(body.len() / frame_size);
+
+                    for frame in body.chunks(frame_size) {
+                        if frame.len() < frame_size {
+                            break;
+                        }
+
+                        let mut sum = 0.0;
+                        for channel in 0..channels {
+                            sum += if bits == 8 {
+                                (frame[channel] as f32 - 128.0) / 128.0
+                            } else {
+                                read_u16(frame, channel * 2) as i16 as f32 / 32768.0
+                            };
+                        }
+
+                        samples.push(sum / channels as f32);
+                    }
+
+                    return Ok(Wav {
+                        sample_rate,
+                        samples,
+                    });
+                }
+                _ => (),
+            }
+
+            pos = body_start + len + (len & 1);
+        }
+
+        Err("WAV file has no data".to_string())
+    }
+
+    /// 16 bit mono PCM
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut data = header(self.sample_rate, data_len);
+        push_samples(&mut data, &self.samples);
+        data
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        File::create(path)
+            .and_then(|mut f| f.write_all(&self.to_bytes()))
+            .map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
+
+/// Writes a WAV file as the samples come in, so it is complete whenever the
+/// program stops
+pub struct WavWriter {
+    file: File,
+    path: String,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<WavWriter, String> {
+        let mut file =
+            File::create(path).map_err(|e| format!("Could not write {}: {}", path, e))?;
+        file.write_all(&header(sample_rate, 0))
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+
+        Ok(WavWriter {
+            file,
+            path: path.to_string(),
+            data_len: 0,
+        })
+    }
+
+    /// Adds the samples to the end and updates the lengths in the header
+    pub fn append(&mut self, samples: &[f32]) -> Result<(), String> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        push_samples(&mut data, samples);
+        self.data_len += data.len() as u32;
+
+        let mut riff_len = Vec::new();
+        push_u32(&mut riff_len, 36 + self.data_len);
+        let mut data_len = Vec::new();
+        push_u32(&mut data_len, self.data_len);
+
+        let file = &mut self.file;
+        file.seek(SeekFrom::End(0))
+            .and_then(|_| file.write_all(&data))
+            .and_then(|_| file.seek(SeekFrom::Start(4)))
+            .and_then(|_| file.write_all(&riff_len))
+            .and_then(|_| file.seek(SeekFrom::Start(40)))
+            .and_then(|_| file.write_all(&data_len))
+            .map_err(|e| format!("Could not write {}: {}", self.path, e))
+    }
+}
+
+/// The 44 byte header of a 16 bit mono PCM file
+fn header(sample_rate: u32, data_len: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(44 + data_len as usize);
+
+    data.extend_from_slice(b"RIFF");
+    push_u32(&mut data, 36 + data_len);
+    data.extend_from_slice(b"WAVE");
+
+    data.extend_from_slice(b"fmt ");
+    push_u32(&mut data, 16);
+    push_u16(&mut data, 1); // PCM
+    push_u16(&mut data, 1); // mono
+    push_u32(&mut data, sample_rate);
+    push_u32(&mut data, sample_rate * 2);
+    push_u16(&mut data, 2);
+    push_u16(&mut data, 16);
+
+    data.extend_from_slice(b"data");
+    push_u32(&mut data, data_len);
+    data
+}
+
+fn push_samples(data: &mut Vec<u8>, samples: &[f32]) {
+    for s in samples {
+        let val = (s.clamp(-1.0, 1.0) * 32767.0) as i16;
+        push_u16(data, val as u16);
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    (data[pos] as u16) | ((data[pos + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (read_u16(data, pos) as u32) | ((read_u16(data, pos + 2) as u32) << 16)
+}
+
+fn push_u16(data: &mut Vec<u8>, val: u16) {
+    data.push(val as u8);
+    data.push((val >> 8) as u8);
+}
+
+fn push_u32(data: &mut Vec<u8>, val: u32) {
+    push_u16(data, val as u16);
+    push_u16(data, (val >> 16) as u16);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut wav = Wav::new(22050);
+        wav.samples = vec![0.0, 0.5, -0.5, 1.0, -1.0];
+
+        let read = Wav::from_bytes(&wav.to_bytes()).unwrap();
+
+        assert_eq!(read.sample_rate, 22050);
+        assert_eq!(read.samples.len(), 5);
+        assert!((read.samples[1] - 0.5).abs() < 0.001);
+        assert!((read.samples[4] + 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_writer() {
+        let path = std::env::temp_dir().join(format!("wav_writer_{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut writer = WavWriter::create(path, 8000).unwrap();
+        writer.append(&[0.0, 0.5]).unwrap();
+        writer.append(&[-0.5]).unwrap();
+
+        let mut written = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut written).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let mut wav = Wav::new(8000);
+        wav.samples = vec![0.0, 0.5, -0.5];
+        assert_eq!(written, wav.to_bytes());
+    }
+
+    #[test]
+    fn test_8bit_stereo() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF");
+        push_u32(&mut data, 40);
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(b"fmt ");
+        push_u32(&mut data, 16);
+        push_u16(&mut data, 1);
+        push_u16(&mut data, 2);
+        push_u32(&mut data, 8000);
+        push_u32(&mut data, 16000);
+        push_u16(&mut data, 2);
+        push_u16(&mut data, 8);
+        data.extend_from_slice(b"data");
+        push_u32(&mut data, 4);
+        data.extend_from_slice(&[128, 128, 255, 255]);
+
+        let wav = Wav::from_bytes(&data).unwrap();
+
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.samples.len(), 2);
+        assert_eq!(wav.samples[0], 0.0);
+        assert!(wav.samples[1] > 0.99);
+    }
+
+    #[test]
+    fn test_not_wav() {
+        assert!(Wav::from_bytes(b"nope").is_err());
+    }
+}
@@ -1,3 +1,7 @@
+extern crate image;
+#[cfg(unix)]
+extern crate libc;
+
 pub mod disassembler;
 pub mod emulator;
 pub mod opcode_decoder;
@@ -2,148 +2,556 @@ extern crate e8080;
 
 mod renderer;
 
+use e8080::emulator::sound::AudioSink;
 use e8080::*;
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+const CPU_DIAG_MAX_CYCLES: u64 = 10_000_000;
+const CPM_DEFAULT_MEMORY: u16 = 64;
+const CPM_CYCLES_PER_SLICE: u64 = 20_000;
+const CPM_DRIVE_ARGS: [&str; 4] = ["--disk-a", "--disk-b", "--disk-c", "--disk-d"];
+const ALTAIR_DEFAULT_RAM: u16 = 64;
+const ALTAIR_CYCLES_PER_SLICE: u64 = 20_000;
+const HEADLESS_DEFAULT_FRAMES: u64 = 600;
+const SOUND_DEFAULT_SAMPLE_RATE: u32 = 44_100;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if let Ok(i) = args.binary_search(&String::from("--disassemble")) {
+    if let Some(i) = args.iter().position(|a| a == "--disassemble") {
         if args.len() < (i + 2) {
-            println!("{}", "Required argument: file to disassemble");
+            println!("Required argument: file to disassemble");
             ::std::process::exit(1);
         }
 
         disassemble(&args[i + 1]);
-    } else if let Ok(_) = args.binary_search(&String::from("--cpu-diag")) {
+    } else if args.iter().any(|a| a == "--cpu-diag") {
         run_cpu_diag();
+    } else if args.iter().any(|a| a == "--cpm") {
+        run_cpm(&args);
+    } else if args.iter().any(|a| a == "--altair") {
+        run_altair(&args);
+    } else if args.iter().any(|a| a == "--headless") {
+        run_headless(&args);
     } else {
-        run_game();
+        run_game(&args);
     }
 }
 
-fn run_game() {
+fn run_game(args: &[String]) {
     let opcode_data = load_opcodes();
     let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
 
-    let rom_data = load_invaders();
-    let mut am = e8080::emulator::ArcadeMachine::new(decoder, &rom_data);
+    let mut am = load_arcade_machine(args, decoder);
 
-    renderer::run(&mut am);
+    configure_machine(args, &mut am);
+
+    let mut sink: Option<Box<dyn AudioSink>> = None;
+    if let Some(rate) = attach_sound(args, &mut am) {
+        let command = arg_value(args, "--audio-command")
+            .map(|c| c.as_str())
+            .unwrap_or_else(|| emulator::sound::PipeSink::default_command());
+
+        match emulator::sound::PipeSink::spawn(command, rate) {
+            Ok(pipe) => sink = Some(Box::new(pipe)),
+            Err(e) => {
+                println!("{}, playing without sound", e);
+                am.detach_sound();
+            }
+        }
+    }
+
+    let input_map = match arg_value(args, "--input-config") {
+        Some(path) => {
+            emulator::input_map::InputMap::open(path).unwrap_or_else(|e| exit_with_error(&e))
+        }
+        None => emulator::input_map::InputMap::for_driver(am.driver()),
+    };
+
+    let macro_out = arg_value(args, "--macro-out").map(|p| p.as_str());
+    renderer::run(&mut am, input_map, sink, macro_out);
 }
 
-fn run_cpu_diag() {
-    use emulator::cpu::CPU;
+/// The game picked with --driver, Space Invaders by default, with each
+/// --driver-option and its ROM from ./data/<driver>.rom
+fn load_arcade_machine(
+    args: &[String],
+    decoder: opcode_decoder::OpcodeDecoder,
+) -> emulator::ArcadeMachine {
+    let name = arg_value(args, "--driver").map_or("invaders", |n| n.as_str());
+    let mut driver = emulator::drivers::by_name(name).unwrap_or_else(|e| exit_with_error(&e));
+
+    for option in arg_values(args, "--driver-option") {
+        driver.set_option(option).unwrap_or_else(|e| exit_with_error(&e));
+    }
 
+    let rom_data = load_binary_file(&format!("./data/{}.rom", driver.name()));
+    emulator::ArcadeMachine::with_driver(decoder, &rom_data, driver)
+}
+
+/// Applies --dip-file, then each --dip name=value on top, --cabinet,
+/// --watchdog and --log-io
+fn configure_machine(args: &[String], am: &mut emulator::ArcadeMachine) {
+    if let Some(path) = arg_value(args, "--dip-file") {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)));
+
+        am.set_dips(&text)
+            .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)));
+    }
+
+    for setting in arg_values(args, "--dip") {
+        am.set_dip(setting).unwrap_or_else(|e| exit_with_error(&e));
+    }
+
+    if let Some(name) = arg_value(args, "--cabinet") {
+        let cabinet = emulator::Cabinet::from_name(name).unwrap_or_else(|e| exit_with_error(&e));
+        am.set_cabinet(cabinet).unwrap_or_else(|e| exit_with_error(&e));
+    }
+
+    am.set_watchdog(args.iter().any(|a| a == "--watchdog"));
+    am.set_io_logging(args.iter().any(|a| a == "--log-io"));
+}
+
+/// Sets up sound from --samples or --synth, returning the sample rate
+fn attach_sound(args: &[String], am: &mut emulator::ArcadeMachine) -> Option<u32> {
+    let samples = arg_value(args, "--samples");
+    let synth = args.iter().any(|a| a == "--synth");
+    if samples.is_none() && !synth {
+        return None;
+    }
+
+    let rate = match arg_value(args, "--sample-rate") {
+        Some(v) => v.parse().unwrap_or_else(|_| exit_with_error("Invalid --sample-rate")),
+        None => SOUND_DEFAULT_SAMPLE_RATE,
+    };
+
+    match samples {
+        Some(dir) => {
+            let player = emulator::sound::SamplePlayer::open(dir, rate)
+                .unwrap_or_else(|e| exit_with_error(&e));
+            am.attach_sound(Box::new(player));
+        }
+        None => am.attach_sound(Box::new(emulator::sound::Synth::new(rate))),
+    }
+
+    Some(rate)
+}
+
+fn run_headless(args: &[String]) {
     let opcode_data = load_opcodes();
     let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
-    let cpudiag = load_cpudiag();
 
-    let mut cpu = emulator::cpu::CPU::new(decoder);
-    cpu.debug = true;
-    cpu.set_memory(0x0100, &cpudiag); // the rom expects to be loaded at 0x0100
-    cpu.set_memory(368, &[0x7]); // fix a bug, supposedly
+    let mut am = load_arcade_machine(args, decoder);
 
-    cpu.set_memory(0x00, &[0xc3, 0x00, 0x01]); // JMP 0x0100
+    configure_machine(args, &mut am);
 
-    // A assembly subroutine to set D in OUT 0 and E in OUT 1 to print a message
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    cpu.set_memory(0x0005, &[
-        0xf5,               // PUSH PSW
-        0x3e, 0x09,         // MVI A, 9
-        0xb9,               // CMP C
-        0xca, 0x10, 0x00,   // JZ
-        0x3e, 0x02,         // MVI A, 2
-        0xb9,               // CMP C
-        0xc0,               // RNZ
-        0x7a,               // MOV A, D
-        0xd3, 0x00,         // OUT 0
-        0x7b,               // MOV A, E
-        0xd3, 0x01,         // OUT 1
-        0xf1,               // POP PSW
-        0xc9                // RET
-    ]);
+    let sound_wav = arg_value(args, "--sound-wav");
+    let rate = attach_sound(args, &mut am);
+    if sound_wav.is_some() && rate.is_none() {
+        exit_with_error("--sound-wav needs --samples or --synth");
+    }
+
+    let frames = match arg_value(args, "--frames") {
+        Some(v) => v.parse().unwrap_or_else(|_| exit_with_error("Invalid --frames")),
+        None => HEADLESS_DEFAULT_FRAMES,
+    };
 
-    fn msg_printer(cpu: &mut CPU) -> &mut CPU {
-        if cpu.pc() == 0 {
-            println!("{}", "Success!");
-            ::std::process::exit(0);
+    let script = match arg_value(args, "--input-script") {
+        Some(path) => emulator::headless::InputScript::open(path).unwrap_or_else(|e| exit_with_error(&e)),
+        None => emulator::headless::InputScript::new(),
+    };
+
+    let dump_dir = arg_value(args, "--dump-frames");
+    if let Some(dir) = dump_dir {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|e| exit_with_error(&format!("Failed to create {}: {}", dir, e)));
+    }
+
+    // Every frame unless picked with --dump-at 100,200,...
+    let selected: Option<Vec<u64>> = arg_value(args, "--dump-at").map(|list| {
+        list.split(',')
+            .map(|v| v.trim().parse().unwrap_or_else(|_| exit_with_error("Invalid --dump-at")))
+            .collect()
+    });
+
+    let mut sink = match (sound_wav, rate) {
+        (Some(path), Some(rate)) => Some(
+            emulator::sound::WavSink::create(path, rate).unwrap_or_else(|e| exit_with_error(&e)),
+        ),
+        _ => None,
+    };
+    let sink = sink.as_mut().map(|s| s as &mut dyn emulator::sound::AudioSink);
+
+    let result = emulator::headless::run_frames(&mut am, &script, frames, sink, |frame, framebuffer| {
+        let dir = match dump_dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+
+        if selected.as_ref().is_none_or(|s| s.contains(&frame)) {
+            let path = std::path::Path::new(dir).join(format!("frame_{:05}.png", frame));
+            framebuffer.save_png(&path.to_string_lossy())?;
+        }
+
+        Ok(())
+    });
+
+    for message in am.take_diagnostics() {
+        println!("{}", message);
+    }
+
+    result.unwrap_or_else(|e| exit_with_error(&e));
+}
+
+fn run_cpu_diag() {
+    let opcode_data = load_opcodes();
+    let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
+    let cpudiag = load_cpudiag();
+
+    let mut runner = emulator::cpm::ComRunner::new(decoder, &cpudiag);
+    runner.cpu().set_memory(368, &[0x7]); // fix a bug, supposedly
+
+    let result = runner.run(CPU_DIAG_MAX_CYCLES);
+    println!("{}", runner.output());
+
+    match result {
+        Ok(()) if !runner.output().contains("FAILED") => println!("Success!"),
+        Ok(()) => ::std::process::exit(1),
+        Err(err) => {
+            println!("{}", err);
+            ::std::process::exit(1);
         }
+    }
+}
 
-        let (val1, dirty1) = cpu.get_out_port(1);
+fn run_cpm(args: &[String]) {
+    let opcode_data = load_opcodes();
+    let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
 
-        if !dirty1 {
-            return cpu;
+    let memory_size = match arg_value(args, "--cpm-memory") {
+        Some(v) => v.parse().unwrap_or_else(|_| exit_with_error("Invalid --cpm-memory")),
+        None => CPM_DEFAULT_MEMORY,
+    };
+
+    let mut machine = emulator::cpm::CpmMachine::new(decoder, memory_size)
+        .unwrap_or_else(|e| exit_with_error(&e));
+
+    for (drive, arg) in CPM_DRIVE_ARGS.iter().enumerate() {
+        if let Some(path) = arg_value(args, arg) {
+            let disk = emulator::cpm::DiskImage::open(path).unwrap_or_else(|e| exit_with_error(&e));
+            machine.insert_disk(drive, disk);
         }
+    }
+
+    machine.boot().unwrap_or_else(|e| exit_with_error(&e));
 
-        let (val0, _) = cpu.get_out_port(0);
+    let rx = spawn_stdin_reader();
 
-        let mut offset = ((val0 as u16) << 8) | (val1 as u16);
-        offset += 4;
+    loop {
+        machine
+            .run(CPM_CYCLES_PER_SLICE)
+            .unwrap_or_else(|e| exit_with_error(&e));
 
-        let mut s = std::char::from_u32(cpu.get_memory(offset) as u32)
-            .unwrap()
-            .to_string();
+        write_stdout(&machine.take_output());
 
-        loop {
-            let c = std::char::from_u32(cpu.get_memory(offset) as u32).unwrap();
-            if c == '$' {
-                break;
+        if machine.is_waiting_for_input() {
+            match rx.recv() {
+                Ok(b) => machine.push_input(&[b]),
+                Err(_) => return,
             }
+        }
+
+        while let Ok(b) = rx.try_recv() {
+            machine.push_input(&[b]);
+        }
+    }
+}
+
+fn run_altair(args: &[String]) {
+    let opcode_data = load_opcodes();
+    let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
+
+    let ram_size = match arg_value(args, "--ram") {
+        Some(v) => v.parse().unwrap_or_else(|_| exit_with_error("Invalid --ram")),
+        None => ALTAIR_DEFAULT_RAM,
+    };
+
+    let mut machine = emulator::altair::Altair8800::new(decoder, ram_size)
+        .unwrap_or_else(|e| exit_with_error(&e));
+
+    for spec in arg_values(args, "--rom") {
+        let (path, addr) = parse_load_spec(spec);
+        machine.load_rom(addr, &load_binary_file(path));
+    }
+
+    for spec in arg_values(args, "--load") {
+        let (path, addr) = parse_load_spec(spec);
+        machine.load(addr, &load_binary_file(path));
+    }
+
+    let disks = arg_values(args, "--dcdd-disk");
+    let drive_count = match arg_value(args, "--dcdd-drives") {
+        Some(v) => v.parse().unwrap_or_else(|_| exit_with_error("Invalid --dcdd-drives")),
+        None => disks.len(),
+    };
 
-            s += &c.to_string();
-            offset += 1;
+    if disks.len() > drive_count {
+        exit_with_error("More --dcdd-disk images than --dcdd-drives");
+    }
+
+    if drive_count > 0 {
+        if drive_count > emulator::altair::dcdd::MAX_DRIVES {
+            exit_with_error("Too many 88-DCDD drives");
         }
 
-        println!("{}", s);
+        let mut dcdd = emulator::altair::Dcdd::new(drive_count);
+        for (drive, path) in disks.iter().enumerate() {
+            let disk =
+                emulator::altair::AltairDisk::open(path).unwrap_or_else(|e| exit_with_error(&e));
+            dcdd.insert_disk(drive, disk);
+        }
 
-        if s.contains("FAILED") {
-            ::std::process::exit(1);
+        machine.attach_dcdd(dcdd);
+    }
+
+    let record_path = arg_value(args, "--cassette-record");
+
+    if arg_value(args, "--cassette").is_some() || record_path.is_some() {
+        let format = match arg_value(args, "--cassette-format") {
+            Some(name) => emulator::devices::CassetteFormat::from_name(name)
+                .unwrap_or_else(|e| exit_with_error(&e)),
+            None => emulator::devices::KANSAS_CITY,
+        };
+
+        let (status_port, data_port) = match arg_value(args, "--cassette-ports") {
+            Some(v) => parse_port_pair(v),
+            None => (
+                emulator::altair::ACR_STATUS_PORT,
+                emulator::altair::ACR_DATA_PORT,
+            ),
+        };
+
+        let mut cassette = emulator::devices::Cassette::new(
+            status_port,
+            data_port,
+            format,
+            emulator::altair::CPU_HZ,
+        );
+        cassette.set_turbo(args.iter().any(|a| a == "--cassette-turbo"));
+
+        if let Some(path) = arg_value(args, "--cassette") {
+            let wav = emulator::wav::Wav::open(path).unwrap_or_else(|e| exit_with_error(&e));
+            cassette.insert_tape(&wav);
         }
 
-        cpu
+        machine.attach_cassette(cassette);
+    }
+
+    let mut console_on_stdin = true;
+
+    for spec in arg_values(args, "--usart") {
+        let (port, backend) = match spec.find('@') {
+            Some(i) => (&spec[..i], &spec[i + 1..]),
+            None => exit_with_error(&format!("Expected data_port@backend, got {}", spec)),
+        };
+        // The status port is the one above, so 0xff is out
+        let port = u8::from_str_radix(port, 16)
+            .ok()
+            .filter(|p| *p <= 0xfe)
+            .unwrap_or_else(|| exit_with_error(&format!("Invalid port in {}", spec)));
+
+        let backend = open_usart_backend(backend, port);
+        if backend.1 {
+            console_on_stdin = false;
+        }
+
+        let mut usart = emulator::devices::Usart8251::new(port as usize, port as usize + 1);
+        usart.set_backend(backend.0);
+        machine.attach_device(Box::new(usart));
+    }
+
+    for spec in arg_values(args, "--timer") {
+        let (port, divider) = match spec.find('@') {
+            Some(i) => (&spec[..i], spec[i + 1..].parse().ok()),
+            None => (&spec[..], Some(1)),
+        };
+        let port = u8::from_str_radix(port, 16).ok().filter(|p| *p <= 0xfc);
+
+        match (port, divider) {
+            (Some(port), Some(divider)) => machine.attach_device(Box::new(
+                emulator::devices::Timer8253::new(port as usize, divider),
+            )),
+            _ => exit_with_error(&format!("Expected base_port[@cycles_per_clock], got {}", spec)),
+        }
+    }
+
+    if let Some(v) = arg_value(args, "--switches") {
+        let switches =
+            u8::from_str_radix(v, 16).unwrap_or_else(|_| exit_with_error("Invalid --switches"));
+        machine.set_sense_switches(switches);
+    }
+
+    match arg_value(args, "--panel-script") {
+        Some(path) => {
+            let mut script = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut script))
+                .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", path, e)));
+
+            emulator::altair::run_script(&mut machine, &script)
+                .unwrap_or_else(|e| exit_with_error(&e));
+        }
+        None => {
+            let start = match arg_value(args, "--start") {
+                Some(v) => u16::from_str_radix(v, 16)
+                    .unwrap_or_else(|_| exit_with_error("Invalid --start")),
+                None => 0,
+            };
+
+            machine.examine(start);
+            machine.start();
+        }
+    }
+
+    let rx = if console_on_stdin {
+        spawn_stdin_reader()
+    } else {
+        mpsc::channel().1
     };
 
-    step_cpu(&mut cpu, msg_printer);
-}
+    let mut recording: Option<emulator::wav::WavWriter> = None;
 
-fn step_cpu(
-    cpu: &mut emulator::cpu::CPU,
-    step_callback: fn(&mut emulator::cpu::CPU) -> &mut emulator::cpu::CPU,
-) {
-    let mut cpu = cpu;
-    let mut instr: u64 = 0;
+    while machine.is_running() {
+        machine.run(ALTAIR_CYCLES_PER_SLICE);
 
-    loop {
-        let mut ticks = 1;
+        let output: Vec<u8> = machine.take_output().iter().map(|b| b & 0x7f).collect();
+        write_stdout(&output);
+
+        while let Ok(b) = rx.try_recv() {
+            machine.push_input(&[b]);
+        }
+
+        if let Some(e) = machine.dcdd().and_then(|d| d.take_error()) {
+            println!("{}", e);
+        }
+
+        if let (Some(path), Some(cassette)) = (record_path, machine.cassette()) {
+            if cassette.take_recorded() {
+                let wav = cassette.take_recording();
+                let writer = recording.get_or_insert_with(|| {
+                    emulator::wav::WavWriter::create(path, wav.sample_rate)
+                        .unwrap_or_else(|e| exit_with_error(&e))
+                });
 
-        let mut cmd = String::new();
-        io::stdin().read_line(&mut cmd).unwrap();
+                writer.append(&wav.samples).unwrap_or_else(|e| exit_with_error(&e));
+            }
+        }
+
+        if machine.cpu().is_halted() {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+}
+
+/// `path@addr` with a hex load address, 0 if omitted
+fn parse_load_spec(spec: &str) -> (&str, u16) {
+    match spec.rfind('@') {
+        Some(i) => {
+            let addr = u16::from_str_radix(&spec[i + 1..], 16)
+                .unwrap_or_else(|_| exit_with_error(&format!("Invalid load address in {}", spec)));
+            (&spec[..i], addr)
+        }
+        None => (spec, 0),
+    }
+}
 
-        if cmd.trim().len() > 0 {
-            ticks = cmd.trim().parse().unwrap();
+/// The backend for an 8251 and whether it takes over stdin
+fn open_usart_backend(
+    spec: &str,
+    port: u8,
+) -> (Box<dyn emulator::devices::SerialBackend>, bool) {
+    match spec {
+        "pty" => {
+            let pty = emulator::devices::serial::PtyBackend::open()
+                .unwrap_or_else(|e| exit_with_error(&e));
+            println!("8251 on port {:02x} is at {}", port, pty.path());
+            (Box::new(pty), false)
         }
+        _ => {
+            let backend =
+                emulator::devices::open_backend(spec).unwrap_or_else(|e| exit_with_error(&e));
+            if let Some(tcp_port) = spec.strip_prefix("tcp:") {
+                println!("8251 on port {:02x} is at telnet localhost {}", port, tcp_port);
+            }
+            (backend, spec == "stdio")
+        }
+    }
+}
 
-        loop {
-            print!("{}: ", instr);
+/// `status,data` with hex port numbers
+fn parse_port_pair(spec: &str) -> (usize, usize) {
+    let ports: Vec<Option<u8>> = spec
+        .split(',')
+        .map(|p| u8::from_str_radix(p, 16).ok())
+        .collect();
 
-            cpu.tick();
-            step_callback(&mut cpu);
-            ticks -= 1;
-            instr += 1;
+    match ports[..] {
+        [Some(status), Some(data)] => (status as usize, data as usize),
+        _ => exit_with_error(&format!("Invalid port pair {}", spec)),
+    }
+}
 
-            if ticks == 0 {
-                break;
+/// Host keyboard input for the console devices, with newlines turned into carriage returns
+fn spawn_stdin_reader() -> mpsc::Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(b'\n') => tx.send(b'\r').unwrap(),
+                Ok(b) => tx.send(b).unwrap(),
+                Err(_) => break,
             }
         }
+    });
+    rx
+}
 
-        cpu.print_state();
+fn write_stdout(data: &[u8]) {
+    if !data.is_empty() {
+        let mut stdout = io::stdout();
+        stdout.write_all(data).unwrap();
+        stdout.flush().unwrap();
     }
 }
 
+fn arg_values<'a>(args: &'a [String], name: &str) -> Vec<&'a String> {
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|(a, _)| *a == name)
+        .map(|(_, v)| v)
+        .collect()
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+}
+
+fn exit_with_error(msg: &str) -> ! {
+    println!("{}", msg);
+    ::std::process::exit(1);
+}
+
 fn disassemble(file: &str) {
     let opcode_data = load_opcodes();
     let decoder = opcode_decoder::OpcodeDecoder::new(&opcode_data);
@@ -158,10 +566,10 @@ fn disassemble(file: &str) {
 
     let da = disassembler::Disassembler::new(decoder);
 
-    let ops = da.disassemble(&partial_data);
+    let ops = da.disassemble(partial_data);
 
     for op in ops.into_iter() {
-        println!("{}", op.to_string());
+        println!("{}", op);
     }
 }
 
@@ -179,10 +587,6 @@ fn load_binary_file(path: &str) -> Vec<u8> {
     data
 }
 
-fn load_invaders() -> Vec<u8> {
-    load_binary_file("./data/invaders.rom")
-}
-
 fn load_cpudiag() -> Vec<u8> {
     load_binary_file("./data/cpudiag.bin")
 }
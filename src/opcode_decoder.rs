@@ -1,7 +1,14 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 use std::rc::Weak;
-use std::u8;
+
+/// The decoder for ./data/opcodes.txt, for the unit tests
+#[cfg(test)]
+pub fn init_decoder() -> OpcodeDecoder {
+    let opcode_data = ::std::fs::read_to_string("./data/opcodes.txt").unwrap();
+    OpcodeDecoder::new(&opcode_data)
+}
 
 pub struct OpcodeDecoder {
     opcodes: HashMap<u8, Rc<OpType>>,
@@ -14,7 +21,7 @@ impl OpcodeDecoder {
         for line in opcode_data.lines() {
             let parts: Vec<&str> = line.split("\t").collect();
 
-            let mut instruction = parts[1].to_string();
+            let instruction = parts[1].to_string();
 
             if instruction == "-" {
                 continue;
@@ -51,7 +58,7 @@ impl OpcodeDecoder {
     pub fn get_next_op(&self, program: &[u8]) -> Result<Op, String> {
         if let Some(optype) = self.opcodes.get(&program[0]) {
             let mut op = Op {
-                optype: Rc::downgrade(&optype),
+                optype: Rc::downgrade(optype),
                 arg1: None,
                 arg2: None,
             };
@@ -109,16 +116,18 @@ impl Op {
             None => panic!("Expected arg2 in Op"),
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Op {
                 arg1: Some(a1),
                 arg2: Some(a2),
                 ..
-            } => format!("{} {:#04x?} {:#04x?}", self.instruction(), a1, a2),
-            Op { arg1: Some(a1), .. } => format!("{} {:#04x?}", self.instruction(), a1),
-            _ => format!("{}", self.instruction()),
+            } => write!(f, "{} {:#04x?} {:#04x?}", self.instruction(), a1, a2),
+            Op { arg1: Some(a1), .. } => write!(f, "{} {:#04x?}", self.instruction(), a1),
+            _ => write!(f, "{}", self.instruction()),
         }
     }
 }
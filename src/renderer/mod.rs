@@ -10,38 +10,87 @@ use renderer::piston_window::{
 };
 
 use emulator;
+use emulator::input_map::InputMap;
+use emulator::sound::AudioSink;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+const DIP_KEYS: [Key; 8] = [
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+/// F2 cycles the driver's first DIP switch through its values, F3 the
+/// second and so on
+fn change_dip_switches(emulator: &mut emulator::ArcadeMachine, key: Key) {
+    let switches = emulator.driver().dip_switches();
+    let (name, values) = match DIP_KEYS.iter().position(|&k| k == key) {
+        Some(i) if i < switches.len() => switches[i],
+        _ => return,
+    };
+
+    let current = emulator.dip(name).unwrap_or_default();
+    let next = match values.iter().position(|&v| v == current) {
+        Some(i) => values[(i + 1) % values.len()],
+        None => values[0],
+    };
+
+    if emulator.set_dip(&format!("{}={}", name, next)).is_ok() {
+        let settings: Vec<String> = switches
+            .iter()
+            .map(|&(name, _)| format!("{}: {}", name, emulator.dip(name).unwrap_or_default()))
+            .collect();
+        println!("{}", settings.join(", "));
+    }
+}
 
-const SIZE_X: u32 = 224;
-const SIZE_Y: u32 = 256;
-
-fn calc_addr_in_buffer(x: u32, y: u32) -> (usize, u32) {
-    let x_addr = x * (SIZE_Y / 8);
-
-    let y_addr = (SIZE_Y - 1 - y) / 8;
-    let y_bit = (SIZE_Y - 1 - y) % 8;
-
-    let addr = x_addr as usize + y_addr as usize;
+/// Prints a macro that just finished recording, and appends it to
+/// `macro_out` if there is one
+fn save_macro(text: &str, macro_out: Option<&str>) {
+    print!("{}", text);
 
-    (addr, y_bit)
-}
+    if let Some(path) = macro_out {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(text.as_bytes()));
 
-fn get_bit(val: u8, bit: u32) -> u8 {
-    (val >> bit) & 0x01
+        if let Err(e) = result {
+            println!("Could not write {}: {}", path, e);
+        }
+    }
 }
 
-pub fn run(emulator: &mut emulator::ArcadeMachine) {
+/// Keys go through `input_map` by their piston names, such as `Space`, `Left`
+/// or `D2`. F2 to F9 change DIP switches unless the map binds them.
+pub fn run(
+    emulator: &mut emulator::ArcadeMachine,
+    mut input_map: InputMap,
+    mut sink: Option<Box<dyn AudioSink>>,
+    macro_out: Option<&str>,
+) {
     let opengl = OpenGL::V3_2;
 
-    let mut window: PistonWindow = WindowSettings::new("e8080", [SIZE_X, SIZE_Y])
+    let size_x = emulator.framebuffer().width() as u32;
+    let size_y = emulator.framebuffer().height() as u32;
+
+    let mut window: PistonWindow = WindowSettings::new("e8080", [size_x, size_y])
         .opengl(opengl)
         .exit_on_esc(true)
         .build()
         .unwrap();
 
-    let mut canvas = im::ImageBuffer::new(SIZE_X, SIZE_Y);
+    let mut canvas = im::ImageBuffer::new(size_x, size_y);
 
-    for x in 0..SIZE_X {
-        for y in 0..SIZE_Y {
+    for x in 0..size_x {
+        for y in 0..size_y {
             canvas.put_pixel(x, y, im::Rgba([0, 0, 0, 255]));
         }
     }
@@ -51,64 +100,55 @@ pub fn run(emulator: &mut emulator::ArcadeMachine) {
 
     let mut events = Events::new(EventSettings::new());
     while let Some(e) = events.next(&mut window) {
-        if let Some(button) = e.press_args() {
-            match button {
-                Button::Keyboard(Key::C) => emulator.coin_key_toggle(true),
-                Button::Keyboard(Key::Left) => emulator.left_p1_key_toggle(true),
-                Button::Keyboard(Key::Right) => emulator.right_p1_key_toggle(true),
-                Button::Keyboard(Key::Space) => emulator.fire_p1_key_toggle(true),
-                Button::Keyboard(Key::S) => emulator.start_p1_key_toggle(true),
-                _ => (),
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            let name = format!("{:?}", key);
+            let recording = input_map.is_recording();
+            input_map.key_down(&name, emulator);
+            if recording != input_map.is_recording() {
+                println!("Macro recording {}", if recording { "stopped" } else { "started" });
             }
-        };
 
-        if let Some(button) = e.release_args() {
-            match button {
-                Button::Keyboard(Key::C) => emulator.coin_key_toggle(false),
-                Button::Keyboard(Key::Left) => emulator.left_p1_key_toggle(false),
-                Button::Keyboard(Key::Right) => emulator.right_p1_key_toggle(false),
-                Button::Keyboard(Key::Space) => emulator.fire_p1_key_toggle(false),
-                Button::Keyboard(Key::S) => emulator.start_p1_key_toggle(false),
-                _ => (),
+            if let Some(text) = input_map.take_recorded() {
+                save_macro(&text, macro_out);
             }
-        };
-
-        if let Some(_) = e.render_args() {
-            let half_time = 1.0 / 60.0 / 2.0;
 
-            emulator.run(half_time);
-
-            {
-                let buff = emulator.get_render_buffer();
+            if !input_map.is_bound(&name) {
+                change_dip_switches(emulator, key);
+            }
+        };
 
-                for y in 0..(SIZE_Y / 2) {
-                    for x in 0..SIZE_X {
-                        let (addr, bit) = calc_addr_in_buffer(x, y);
-                        let val = get_bit(buff[addr], bit) * 255;
+        if let Some(Button::Keyboard(key)) = e.release_args() {
+            input_map.key_up(&format!("{:?}", key), emulator);
+        };
 
-                        canvas.put_pixel(x, y, im::Rgba([val; 4]));
-                    }
+        if e.render_args().is_some() {
+            input_map.update(emulator);
+            let framebuffer = emulator.run_frame();
+
+            for y in framebuffer.dirty_rows() {
+                for (x, pixel) in framebuffer.row(y).chunks(4).enumerate() {
+                    canvas.put_pixel(
+                        x as u32,
+                        y as u32,
+                        im::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+                    );
                 }
             }
 
-            emulator.signal_half_render();
-            emulator.run(half_time);
-
-            {
-                let buff = emulator.get_render_buffer();
-
-                for y in (SIZE_Y / 2)..SIZE_Y {
-                    for x in 0..SIZE_X {
-                        let (addr, bit) = calc_addr_in_buffer(x, y);
-                        let val = get_bit(buff[addr], bit) * 255;
+            for message in emulator.take_diagnostics() {
+                println!("{}", message);
+            }
 
-                        canvas.put_pixel(x, y, im::Rgba([val; 4]));
+            if let Some(mut audio) = sink.take() {
+                match audio.write(&emulator.take_audio()) {
+                    Ok(()) => sink = Some(audio),
+                    Err(e) => {
+                        println!("{}, sound off", e);
+                        emulator.detach_sound();
                     }
                 }
             }
 
-            emulator.signal_finish_render();
-
             texture.update(&mut window.encoder, &canvas).unwrap();
             window.draw_2d(&e, |c, gl| {
                 clear([0.0; 4], gl);
@@ -116,4 +156,10 @@ pub fn run(emulator: &mut emulator::ArcadeMachine) {
             });
         }
     }
+
+    if let Some(mut audio) = sink {
+        if let Err(e) = audio.finish() {
+            println!("{}", e);
+        }
+    }
 }
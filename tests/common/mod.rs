@@ -0,0 +1,9 @@
+//! Helpers shared by the integration tests
+
+use e8080::opcode_decoder::OpcodeDecoder;
+use std::fs;
+
+pub fn init_decoder() -> OpcodeDecoder {
+    let opcode_data = fs::read_to_string("./data/opcodes.txt").unwrap();
+    OpcodeDecoder::new(&opcode_data)
+}
@@ -0,0 +1,122 @@
+//! Runs the classic 8080 CPU exercisers through `ComRunner`.
+//!
+//! The binaries are not part of the repository, so the tests are ignored and
+//! fail when their program is missing. cpudiag.bin is the one `--cpu-diag`
+//! uses, TST8080.COM, 8080PRE.COM, CPUTEST.COM and 8080EXM.COM come with most
+//! CP/M archives and 8080 emulator test suites. Put them in ./data and run
+//! `cargo test --release --test exercisers -- --ignored`.
+//!
+//! A plain `cargo test`, and so CI, runs none of them. There the CPU is only
+//! covered by the unit tests in src/emulator/cpu.
+
+extern crate e8080;
+
+mod common;
+
+use common::init_decoder;
+use e8080::emulator::cpm::ComRunner;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+const DATA_DIR: &str = "./data";
+
+fn load_program(name: &str) -> Vec<u8> {
+    let path = Path::new(DATA_DIR).join(name);
+    let mut file = File::open(&path)
+        .unwrap_or_else(|err| panic!("{}: {}, see the top of this file", path.display(), err));
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    data
+}
+
+fn run_program(runner: &mut ComRunner, name: &str, max_cycles: u64) -> String {
+    if let Err(err) = runner.run(max_cycles) {
+        panic!("{}: {}\nOutput so far:\n{}", name, err, runner.output());
+    }
+
+    runner.output().to_string()
+}
+
+fn assert_passed(name: &str, output: &str, success: &str, failures: &[&str]) {
+    let failed_lines: Vec<&str> = output
+        .lines()
+        .filter(|line| failures.iter().any(|f| line.contains(f)))
+        .collect();
+
+    assert!(
+        failed_lines.is_empty(),
+        "{} reported failures:\n{}",
+        name,
+        failed_lines.join("\n")
+    );
+    assert!(
+        output.contains(success),
+        "{} did not report success, output:\n{}",
+        name,
+        output
+    );
+}
+
+fn run_exerciser(name: &str, max_cycles: u64, success: &str, failures: &[&str]) {
+    let program = load_program(name);
+
+    let mut runner = ComRunner::new(init_decoder(), &program);
+    let output = run_program(&mut runner, name, max_cycles);
+
+    assert_passed(name, &output, success, failures);
+}
+
+#[test]
+#[ignore]
+fn test_cpudiag() {
+    let program = load_program("cpudiag.bin");
+
+    let mut runner = ComRunner::new(init_decoder(), &program);
+    runner.cpu().set_memory(368, &[0x7]); // fix the stack pointer the binary was assembled with
+
+    let output = run_program(&mut runner, "cpudiag.bin", 10_000_000);
+
+    assert_passed(
+        "cpudiag.bin",
+        &output,
+        "CPU IS OPERATIONAL",
+        &["CPU HAS FAILED"],
+    );
+}
+
+#[test]
+#[ignore]
+fn test_tst8080() {
+    run_exerciser(
+        "TST8080.COM",
+        10_000_000,
+        "CPU IS OPERATIONAL",
+        &["CPU HAS FAILED"],
+    );
+}
+
+#[test]
+#[ignore]
+fn test_8080pre() {
+    run_exerciser(
+        "8080PRE.COM",
+        10_000_000,
+        "Preliminary tests complete",
+        &["ERROR", "failed"],
+    );
+}
+
+#[test]
+#[ignore]
+fn test_cputest() {
+    run_exerciser("CPUTEST.COM", 1_000_000_000, "CPU TESTS OK", &["ERROR"]);
+}
+
+// Takes billions of cycles, run with `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn test_8080exm() {
+    run_exerciser("8080EXM.COM", 50_000_000_000, "Tests complete", &["ERROR"]);
+}
@@ -0,0 +1,89 @@
+//! Plays scenarios headless and compares frames against golden hashes.
+//!
+//! Each scenario has its hashes.txt and input script in a directory under
+//! tests/golden. Run with E8080_UPDATE_GOLDEN=1 to record missing hashes,
+//! and delete the stale ones first after an intended change. Frames that
+//! fail go to the cargo target temp directory.
+//!
+//! Only the fill scenario has hashes committed and runs by default. The
+//! Space Invaders scenario under tests/golden/invaders has an input script
+//! but no hashes yet, so it covers nothing until a maintainer with the ROM
+//! records them:
+//!
+//! 1. Put invaders.rom in ./data like for the game itself.
+//! 2. Run `E8080_UPDATE_GOLDEN=1 cargo test --test golden -- --ignored`.
+//! 3. Look over the same frames with `--headless --frames 1200
+//!    --input-script tests/golden/invaders/input.txt --dump-frames <dir>
+//!    --dump-at 120,300,600,900,1199`, hashes alone prove nothing.
+//! 4. Commit the new tests/golden/invaders/hashes.txt.
+//!
+//! After that `cargo test --test golden -- --ignored` checks them. The test
+//! stays ignored because it fails without the ROM.
+
+extern crate e8080;
+
+mod common;
+
+use common::init_decoder;
+use e8080::emulator::golden::Golden;
+use e8080::emulator::headless::InputScript;
+use e8080::emulator::ArcadeMachine;
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+const ROM_PATH: &str = "./data/invaders.rom";
+const GOLDEN_DIR: &str = "./tests/golden";
+
+fn invaders_rom() -> Vec<u8> {
+    let mut rom = Vec::new();
+    File::open(ROM_PATH)
+        .and_then(|mut f| f.read_to_end(&mut rom))
+        .unwrap_or_else(|e| panic!("{}: {}", ROM_PATH, e));
+    rom
+}
+
+fn check_golden(scenario: &str, rom: &[u8], frames: u64, checkpoints: &[u64]) {
+    let dir = Path::new(GOLDEN_DIR).join(scenario);
+    let script = InputScript::open(&dir.join("input.txt").to_string_lossy()).unwrap();
+
+    let mut golden = Golden::new(&dir, env!("CARGO_TARGET_TMPDIR"));
+    golden.set_update(env::var("E8080_UPDATE_GOLDEN").is_ok());
+
+    let mut machine = ArcadeMachine::new(init_decoder(), rom);
+    if let Err(e) = golden.run(&mut machine, &script, frames, checkpoints) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn test_fill_golden_frames() {
+    // Fills video memory with what IN 1 reads, so the picture shows when the
+    // coin went in and out
+    #[rustfmt::skip]
+    let rom = [
+        0x21, 0x00, 0x24,   // LXI H, 0x2400
+        0xdb, 0x01,         // IN 1
+        0x77,               // MOV M, A
+        0x23,               // INX H
+        0x7c,               // MOV A, H
+        0xfe, 0x40,         // CPI 0x40
+        0xc2, 0x03, 0x00,   // JNZ 3
+        0xc3, 0x00, 0x00,   // JMP 0
+    ];
+    check_golden("fill", &rom, 60, &[10, 25, 40, 59]);
+}
+
+#[test]
+#[ignore]
+fn test_invaders_golden_frames() {
+    // Attract mode, the coin going in, and the first wave after starting.
+    // Fails until the hashes are recorded, see the module doc
+    check_golden(
+        "invaders",
+        &invaders_rom(),
+        1200,
+        &[120, 300, 600, 900, 1199],
+    );
+}